@@ -3,20 +3,61 @@ use crate::event::MouseEvent;
 
 use crate::virtual_model::SharedVirtualModel;
 use anyhow::Result;
-use std::sync::Mutex as StdMutex;
-use std::sync::Once;
 use tokio::sync::mpsc;
 
-// グローバルな状態を管理するための構造体
-struct GlobalState {
-    virtual_model: Option<SharedVirtualModel>,
-    sender: Option<mpsc::UnboundedSender<MouseEvent>>,
-    is_running: std::sync::Arc<std::sync::atomic::AtomicBool>,
-    config: Option<Config>,
+/// 終了時に相手側へ送るイベント。押したままのキーとボタンを離してから制御を返す
+fn release_events(pressed: impl IntoIterator<Item = u16>) -> Vec<MouseEvent> {
+    pressed
+        .into_iter()
+        .map(|code| MouseEvent::Key {
+            code,
+            pressed: false,
+        })
+        .chain([MouseEvent::Buttons { held: 0 }, MouseEvent::ControlLeave])
+        .collect()
 }
 
-static GLOBAL_STATE: StdMutex<Option<GlobalState>> = StdMutex::new(None);
-static INIT: Once = Once::new();
+/// 確認の入力が1行届いたか。入力が閉じていたら（EOF）確認されなかったものとする
+fn confirmed(mut input: impl std::io::BufRead) -> bool {
+    let mut line = String::new();
+    matches!(input.read_line(&mut line), Ok(n) if n > 0)
+}
+
+/// 初回移譲の確認をstdinで待つ
+///
+/// stdinが閉じている（サービスとして起動したなど）ときは確認できないので、移譲は止めたままにする
+fn wait_for_confirmation(vm: SharedVirtualModel) {
+    std::thread::spawn(move || {
+        if !confirmed(std::io::stdin().lock()) {
+            log::warn!("Standard input is closed: transfer to remote stays blocked");
+            return;
+        }
+        if let Ok(mut vm) = vm.lock() {
+            vm.transfer_blocked = false;
+        }
+        log::info!("Transfer to remote confirmed");
+    });
+}
+
+/// リモート操作中なら、受信側の現在位置で行うスクロールを返す
+fn scroll_event(
+    config: &Config,
+    vm: &SharedVirtualModel,
+    delta_x: i64,
+    delta_y: i64,
+) -> Option<MouseEvent> {
+    let vm = vm.lock().ok()?;
+    if vm.in_host(config) {
+        return None;
+    }
+    let (x, y) = vm.receiver_position(config);
+    Some(MouseEvent::Scroll {
+        x,
+        y,
+        delta_x,
+        delta_y,
+    })
+}
 
 pub trait MouseCapturer {
     async fn start_capture_with_model(
@@ -25,66 +66,458 @@ pub trait MouseCapturer {
         sender: mpsc::UnboundedSender<MouseEvent>,
         virtual_model: SharedVirtualModel,
     ) -> Result<()>;
+
+    /// キャプチャを止める。止まると`start_capture_with_model`が戻る
+    fn stop_capture(&self);
 }
 
 #[cfg(target_os = "macos")]
 pub mod macos {
     use super::*;
-    use core_graphics::display::CGDisplayShowCursor;
-    use core_graphics::event::{CGEvent, CGEventType, CGMouseButton};
+    use crate::config::HostPosition;
+    use core_graphics::display::CGDisplay;
+    use core_graphics::event::{CGEvent, CGEventType, CGMouseButton, EventField};
     use core_graphics::event_source::{CGEventSource, CGEventSourceStateID};
     use core_graphics::geometry::CGPoint;
     use std::sync::atomic::{AtomicBool, Ordering};
     use std::sync::Arc;
+    use std::sync::Mutex as StdMutex;
+    use std::time::{Duration, Instant};
+
+    // グローバルな状態を管理するための構造体
+    struct GlobalState {
+        virtual_model: Option<SharedVirtualModel>,
+        sender: Option<mpsc::UnboundedSender<MouseEvent>>,
+        is_running: Arc<AtomicBool>,
+        config: Option<Config>,
+        hotkeys: StdMutex<crate::keymap::HotkeyTracker>,
+        tuning: Tuning,
+        motion: StdMutex<MotionFilter>,
+    }
+
+    static GLOBAL_STATE: StdMutex<Option<GlobalState>> = StdMutex::new(None);
+
+    /// 移動の間引きとエッジ判定の調整値（Configの同名の項目）
+    #[derive(Clone, Copy)]
+    struct Tuning {
+        poll_interval: Duration,
+        move_threshold: f64,
+        secondary_move_threshold: f64,
+        edge_margin: f64,
+    }
+
+    /// ホストの移動を間引き、リモートへ送るMoveをまとめる状態
+    #[derive(Default)]
+    struct MotionFilter {
+        /// 最後にVirtualModelへ反映したホストのカーソル位置
+        last_host: Option<(f64, f64)>,
+        /// 最後に送ったMoveの位置と時刻
+        last_sent: Option<((f64, f64), Instant)>,
+        /// 次の送信間隔まで保留しているMoveの位置
+        pending: Option<(f64, f64)>,
+    }
+
+    impl MotionFilter {
+        /// ホスト操作中の移動を反映すべきか。move_threshold未満の揺れは捨てる
+        fn host_moved(&mut self, tuning: &Tuning, x: f64, y: f64) -> bool {
+            if let Some((last_x, last_y)) = self.last_host {
+                if (x - last_x).hypot(y - last_y) < tuning.move_threshold {
+                    return false;
+                }
+            }
+            self.last_host = Some((x, y));
+            true
+        }
+
+        /// ホスト操作中のカーソル位置を、VirtualModelへ反映する位置にする。反映しなくてよければNone
+        ///
+        /// 移譲エッジに押し当てている間はOSが同じ座標を返し続けるが、
+        /// edge_dwell_msの経過を判定するため、揺れとして捨てずに毎回反映する
+        fn host_sample(
+            &mut self,
+            config: &Config,
+            tuning: &Tuning,
+            x: f64,
+            y: f64,
+        ) -> Option<(f64, f64)> {
+            let pushed = push_past_edge(config, tuning, x, y);
+            let moved = self.host_moved(tuning, x, y);
+            (moved || pushed != (x, y)).then_some(pushed)
+        }
+
+        /// 今送るべきMoveの位置を返す。間隔が詰まっているか移動が小さければ保留する
+        fn offer(&mut self, tuning: &Tuning, position: (f64, f64)) -> Option<(f64, f64)> {
+            self.pending = Some(position);
+            let ready = match self.last_sent {
+                Some((last, at)) => {
+                    (position.0 - last.0).hypot(position.1 - last.1)
+                        >= tuning.secondary_move_threshold
+                        && at.elapsed() >= tuning.poll_interval
+                }
+                None => true,
+            };
+            if ready {
+                self.take()
+            } else {
+                None
+            }
+        }
+
+        /// 送信間隔が空いていれば保留中のMoveを返す
+        fn due(&mut self, tuning: &Tuning) -> Option<(f64, f64)> {
+            match self.last_sent {
+                Some((_, at)) if at.elapsed() < tuning.poll_interval => None,
+                _ => self.take(),
+            }
+        }
+
+        /// 保留中のMoveを送ったことにして返す
+        fn take(&mut self) -> Option<(f64, f64)> {
+            let position = self.pending.take()?;
+            self.last_sent = Some((position, Instant::now()));
+            Some(position)
+        }
+
+        /// 制御側が変わったので、保留や前回位置を捨てる
+        fn reset(&mut self) {
+            *self = Self::default();
+        }
+    }
+
+    /// 保留中のMoveがあれば送る。ボタンなどを送る前に、位置を受信側へ揃えておく
+    fn flush_move(state: &GlobalState, sender: &mpsc::UnboundedSender<MouseEvent>, force: bool) {
+        let position = match state.motion.lock() {
+            Ok(mut motion) if force => motion.take(),
+            Ok(mut motion) => motion.due(&state.tuning),
+            Err(_) => None,
+        };
+        if let Some((x, y)) = position {
+            send_event(state, sender, MouseEvent::Move { x, y });
+        }
+    }
+
+    /// 移譲エッジからedge_margin以内のホスト位置を、エッジの外へ押し出す
+    ///
+    /// OSはカーソルを画面内に留めるので、端の画素まで来たら越えたものとして扱う
+    fn push_past_edge(config: &Config, tuning: &Tuning, x: f64, y: f64) -> (f64, f64) {
+        let (x_p, y_p) = config.screen.to_physical(x, y);
+        let width = config.screen.width as f64;
+        let x_p = match config.host_position {
+            HostPosition::Left if x_p >= width - tuning.edge_margin => width,
+            HostPosition::Right if x_p < tuning.edge_margin => -1.0,
+            _ => return (x, y),
+        };
+        config.screen.to_os(x_p, y_p)
+    }
 
     pub struct MacOSCapturer {
         is_running: Arc<AtomicBool>,
+        tuning: Tuning,
     }
 
     impl MacOSCapturer {
-        pub fn new() -> Self {
+        pub fn new(
+            poll_interval_ms: u64,
+            move_threshold: f64,
+            secondary_move_threshold: f64,
+            edge_margin: f64,
+        ) -> Self {
             Self {
                 is_running: Arc::new(AtomicBool::new(false)),
+                tuning: Tuning {
+                    poll_interval: Duration::from_millis(poll_interval_ms),
+                    move_threshold,
+                    secondary_move_threshold,
+                    edge_margin,
+                },
             }
         }
+    }
 
-        /// マウスを画面中央に固定する関数
-        pub fn warp_to_center(&self, config: &Config) -> Result<()> {
-            let (center_x, center_y) = config.host_center();
-            let center_point = CGPoint::new(center_x, center_y);
-
-            match CGEventSource::new(CGEventSourceStateID::CombinedSessionState) {
-                Ok(event_source) => {
-                    match CGEvent::new_mouse_event(
-                        event_source,
-                        CGEventType::MouseMoved,
-                        center_point,
-                        CGMouseButton::Left,
-                    ) {
-                        Ok(event) => {
-                            event.post(core_graphics::event::CGEventTapLocation::HID);
-                            log::debug!(
-                                "Mouse warped to center: ({:.1}, {:.1})",
-                                center_x,
-                                center_y
-                            );
-                            Ok(())
-                        }
-                        Err(e) => {
-                            log::error!("Failed to create mouse event: {:?}", e);
-                            Err(anyhow::anyhow!("Failed to create mouse event"))
-                        }
+    /// イベントを送る。チャネルが閉じていたら共有は続けられないので、キャプチャを止める
+    fn send_event(
+        state: &GlobalState,
+        sender: &mpsc::UnboundedSender<MouseEvent>,
+        event: MouseEvent,
+    ) {
+        if sender.send(event).is_err() && state.is_running.swap(false, Ordering::SeqCst) {
+            log::error!(
+                "!!! Event channel closed: stopping capture, mouse sharing is no longer active !!!"
+            );
+        }
+    }
+
+    /// 押下中のキーを相手側で離す
+    fn release_keys(state: &GlobalState, sender: &mpsc::UnboundedSender<MouseEvent>) {
+        let pressed: Vec<u16> = match state.hotkeys.lock() {
+            Ok(hotkeys) => hotkeys.pressed().collect(),
+            Err(_) => return,
+        };
+        for code in pressed {
+            send_event(
+                state,
+                sender,
+                MouseEvent::Key {
+                    code,
+                    pressed: false,
+                },
+            );
+        }
+    }
+
+    /// CapsLockのevdevキーコード
+    const KEY_CAPSLOCK: u16 = 58;
+
+    /// キーボードのタップが止められたら立てる。タップのスレッドが有効にし直す
+    static KEY_TAP_DISABLED: AtomicBool = AtomicBool::new(false);
+
+    /// CGEventTapで受けたキーを処理する。ホストに届けない（横取りする）ならtrue
+    fn handle_key(event_type: CGEventType, event: &CGEvent) -> bool {
+        let Ok(global_state) = GLOBAL_STATE.lock() else {
+            return false;
+        };
+        let Some(state) = global_state.as_ref() else {
+            return false;
+        };
+        if !state.is_running.load(Ordering::SeqCst) {
+            return false;
+        }
+        let (Some(vm), Some(sender), Some(config)) = (
+            state.virtual_model.as_ref(),
+            state.sender.as_ref(),
+            state.config.as_ref(),
+        ) else {
+            return false;
+        };
+        let mac = event.get_integer_value_field(EventField::KEYBOARD_EVENT_KEYCODE) as u16;
+        let Some(code) = crate::keymap::from_mac(mac) else {
+            log::debug!("No evdev code for macOS key {:#x}", mac);
+            // 送れないキーでも、リモート操作中はホストに入力させない
+            return vm.lock().is_ok_and(|vm| !vm.in_host(config));
+        };
+        let presses: &[bool] = match event_type {
+            CGEventType::KeyDown => &[true],
+            CGEventType::KeyUp => &[false],
+            // CapsLockは押すたびに切り替わるので、押して離したものとして送る
+            _ if code == KEY_CAPSLOCK => &[true, false],
+            // 修飾キーは押下も解放もFlagsChangedで届くので、押下中かどうかで判別する
+            _ => match state.hotkeys.lock() {
+                Ok(hotkeys) if hotkeys.pressed().any(|held| held == code) => &[false],
+                Ok(_) => &[true],
+                Err(_) => return false,
+            },
+        };
+        let mut swallowed = false;
+        for &pressed in presses {
+            // 終了時に離せるよう、ホットキーがなくても押下中のキーを追跡する
+            let fired = match state.hotkeys.lock() {
+                Ok(mut hotkeys) => hotkeys.key(config.toggle_hotkey.as_ref(), code, pressed),
+                Err(_) => false,
+            };
+            if fired {
+                // 最後のキーはリモートにもホストにも届けない。先に押した修飾キーは、
+                // ホスト操作中ならホストに入力されている
+                toggle_by_hotkey(state, sender, vm, config);
+                swallowed = true;
+                continue;
+            }
+            let in_host = match vm.lock() {
+                Ok(vm) => vm.in_host(config),
+                Err(_) => return swallowed,
+            };
+            if !in_host {
+                send_event(state, sender, MouseEvent::Key { code, pressed });
+                swallowed = true;
+            }
+        }
+        swallowed
+    }
+
+    /// キーボードをCGEventTapで受け続ける
+    ///
+    /// rdevは入力を横取りできず、リモート操作中のキーがホストにも入力されてしまうので、
+    /// キーだけはタップで受けてリモート操作中はホストに届けない
+    fn run_key_tap() {
+        use core_foundation::runloop::{kCFRunLoopCommonModes, kCFRunLoopDefaultMode, CFRunLoop};
+        use core_graphics::event::{
+            CGEventTap, CGEventTapLocation, CGEventTapOptions, CGEventTapPlacement,
+        };
+
+        let tap = CGEventTap::new(
+            CGEventTapLocation::HID,
+            CGEventTapPlacement::HeadInsertEventTap,
+            CGEventTapOptions::Default,
+            vec![
+                CGEventType::KeyDown,
+                CGEventType::KeyUp,
+                CGEventType::FlagsChanged,
+            ],
+            |_, event_type, event| {
+                match event_type {
+                    CGEventType::TapDisabledByTimeout | CGEventType::TapDisabledByUserInput => {
+                        KEY_TAP_DISABLED.store(true, Ordering::SeqCst);
+                    }
+                    _ if handle_key(event_type, event) => {
+                        // Nullにしたイベントはどのアプリにも届かない
+                        event.set_type(CGEventType::Null);
                     }
+                    _ => {}
+                }
+                None
+            },
+        );
+        let Ok(tap) = tap else {
+            log::error!(
+                "Failed to create the keyboard event tap: keys are not forwarded (see `sharemouse doctor`)"
+            );
+            return;
+        };
+        let Ok(source) = tap.mach_port.create_runloop_source(0) else {
+            log::error!("Failed to create a run loop source for the keyboard event tap");
+            return;
+        };
+        // SAFETY: CoreFoundationが用意する定数を読むだけ
+        unsafe {
+            CFRunLoop::get_current().add_source(&source, kCFRunLoopCommonModes);
+        }
+        tap.enable();
+        log::info!("Forwarding keys through a keyboard event tap");
+        loop {
+            // SAFETY: 同上
+            CFRunLoop::run_in_mode(
+                unsafe { kCFRunLoopDefaultMode },
+                Duration::from_secs(1),
+                false,
+            );
+            // 応答が遅れるとOSがタップを止めるので、有効にし直す
+            if KEY_TAP_DISABLED.swap(false, Ordering::SeqCst) {
+                log::warn!("Keyboard event tap was disabled by the system, enabling it again");
+                tap.enable();
+            }
+        }
+    }
+
+    /// ホットキーで制御側を切り替え、相手側とカーソルの状態を合わせる
+    fn toggle_by_hotkey(
+        state: &GlobalState,
+        sender: &mpsc::UnboundedSender<MouseEvent>,
+        vm: &SharedVirtualModel,
+        config: &Config,
+    ) {
+        let Ok(mut vm) = vm.lock() else {
+            return;
+        };
+        if !vm.toggle_control(config) {
+            return;
+        }
+        if let Ok(mut motion) = state.motion.lock() {
+            motion.reset();
+        }
+        let display = CGDisplay::main();
+        if vm.in_host(config) {
+            // リモートで押したままの修飾キーなどを離しておく
+            release_keys(state, sender);
+            send_event(state, sender, MouseEvent::ControlLeave);
+            if config.warp_strategy.hides_cursor() {
+                if let Err(e) = display.show_cursor() {
+                    log::warn!("Failed to toggle cursor: {:?}", e);
+                }
+            }
+            let (x, y) = vm.local_position(config);
+            if let Err(e) = warp_to(x, y) {
+                log::error!("Failed to warp mouse home: {}", e);
+            }
+        } else {
+            send_event(state, sender, MouseEvent::ControlEnter);
+            if config.wake_remote_on_enter {
+                for wake in vm.wake_moves(config) {
+                    send_event(state, sender, wake);
+                }
+            }
+            if config.warp_strategy.hides_cursor() {
+                if let Err(e) = display.hide_cursor() {
+                    log::warn!("Failed to toggle cursor: {:?}", e);
                 }
-                Err(e) => {
-                    log::error!("Failed to create event source: {:?}", e);
-                    Err(anyhow::anyhow!("Failed to create event source"))
+            }
+            if let Err(e) = warp_to(vm.anchor_x, vm.anchor_y) {
+                log::error!("Failed to warp mouse: {}", e);
+            }
+            let (x, y) = vm.receiver_position(config);
+            send_event(state, sender, MouseEvent::Move { x, y });
+        }
+    }
+
+    /// マウスを指定位置にワープする関数
+    fn warp_to(x: f64, y: f64) -> Result<()> {
+        let point = CGPoint::new(x, y);
+
+        match CGEventSource::new(CGEventSourceStateID::CombinedSessionState) {
+            Ok(event_source) => {
+                match CGEvent::new_mouse_event(
+                    event_source,
+                    CGEventType::MouseMoved,
+                    point,
+                    CGMouseButton::Left,
+                ) {
+                    Ok(event) => {
+                        event.post(core_graphics::event::CGEventTapLocation::HID);
+                        log::debug!("Mouse warped to: ({:.1}, {:.1})", x, y);
+                        Ok(())
+                    }
+                    Err(e) => {
+                        log::error!("Failed to create mouse event: {:?}", e);
+                        Err(anyhow::anyhow!("Failed to create mouse event"))
+                    }
                 }
             }
+            Err(e) => {
+                log::error!("Failed to create event source: {:?}", e);
+                Err(anyhow::anyhow!("Failed to create event source"))
+            }
         }
     }
 
     impl MouseCapturer for MacOSCapturer {
+        fn stop_capture(&self) {
+            // rdevのlistenは止められないので、届いたイベントを無視するだけ
+            self.is_running.store(false, Ordering::SeqCst);
+            // コールバックが持つ送信チャネルを手放し、送信ループが残りを送り切って終われるようにする
+            let Some(state) = GLOBAL_STATE.lock().ok().and_then(|mut state| state.take()) else {
+                return;
+            };
+            let (Some(vm), Some(sender), Some(config)) = (
+                state.virtual_model.as_ref(),
+                state.sender.as_ref(),
+                state.config.as_ref(),
+            ) else {
+                return;
+            };
+            let Ok(mut vm) = vm.lock() else {
+                return;
+            };
+            if !vm.return_to_host(config) {
+                return;
+            }
+            log::info!("Returning control to the host before exiting");
+            let pressed: Vec<u16> = match state.hotkeys.lock() {
+                Ok(hotkeys) => hotkeys.pressed().collect(),
+                Err(_) => Vec::new(),
+            };
+            for event in release_events(pressed) {
+                let _ = sender.send(event);
+            }
+            if config.warp_strategy.hides_cursor() {
+                if let Err(e) = CGDisplay::main().show_cursor() {
+                    log::warn!("Failed to show cursor: {:?}", e);
+                }
+            }
+            // 中央などに留めたままにせず、ホストに戻ったときの位置に置く
+            let (x, y) = vm.local_position(config);
+            if let Err(e) = warp_to(x, y) {
+                log::error!("Failed to restore the cursor: {}", e);
+            }
+        }
+
         async fn start_capture_with_model(
             &self,
             config: &Config,
@@ -96,49 +529,37 @@ pub mod macos {
 
             // アクセシビリティ権限をチェック
             log::info!("Checking accessibility permissions...");
-            match CGEventSource::new(CGEventSourceStateID::CombinedSessionState) {
-                Ok(event_source) => {
-                    match CGEvent::new_mouse_event(
-                        event_source,
-                        CGEventType::MouseMoved,
-                        CGPoint::new(0.0, 0.0),
-                        CGMouseButton::Left,
-                    ) {
-                        Ok(_event) => {
-                            log::info!("Accessibility permissions OK");
-                        }
-                        Err(_) => {
-                            log::error!(
-                                "Failed to create CGEvent - please grant accessibility permissions"
-                            );
-                            return Err(anyhow::anyhow!("Accessibility permissions required"));
-                        }
-                    }
-                }
-                Err(_) => {
-                    log::error!(
-                        "Failed to create CGEventSource - please grant accessibility permissions"
-                    );
-                    return Err(anyhow::anyhow!("Accessibility permissions required"));
-                }
+            if !crate::permissions::probe_event_source() {
+                log::error!(
+                    "Failed to create CGEvent - please grant accessibility permissions (see `sharemouse doctor`)"
+                );
+                return Err(anyhow::anyhow!("Accessibility permissions required"));
             }
+            log::info!("Accessibility permissions OK");
 
-            // 初期マウス位置を設定
-            let current_position = unsafe {
-                use cocoa::appkit::NSEvent;
-                use cocoa::base::nil;
-                let mouse_location = NSEvent::mouseLocation(nil);
-                CGPoint::new(mouse_location.x, mouse_location.y)
+            // 初期マウス位置を設定（rdevと同じ左上原点の座標で取得する）
+            let (x, y) = match config.initial_cursor() {
+                Some((x, y)) => {
+                    warp_to(x, y)?;
+                    (x, y)
+                }
+                None => {
+                    let current = CGEventSource::new(CGEventSourceStateID::CombinedSessionState)
+                        .and_then(CGEvent::new)
+                        .map(|event| event.location())
+                        .map_err(|_| anyhow::anyhow!("Failed to read current mouse position"))?;
+                    (current.x, current.y)
+                }
             };
 
             {
                 let mut locked = virtual_model.lock().unwrap();
-                locked.init(config, current_position.x, current_position.y);
-                log::info!(
-                    "VirtualModel initialized at ({}, {})",
-                    current_position.x,
-                    current_position.y
-                );
+                locked.init(config, x, y);
+                log::info!("VirtualModel initialized at ({}, {})", x, y);
+            }
+
+            if config.confirm_first_transfer {
+                wait_for_confirmation(virtual_model.clone());
             }
 
             // グローバル状態を設定
@@ -149,9 +570,14 @@ pub mod macos {
                     sender: Some(sender.clone()),
                     is_running: self.is_running.clone(),
                     config: Some(config.clone()),
+                    hotkeys: StdMutex::new(Default::default()),
+                    tuning: self.tuning,
+                    motion: StdMutex::new(Default::default()),
                 });
             }
 
+            std::thread::spawn(run_key_tap);
+
             // rdevでマウスイベントをリッスン（別スレッドで実行）
             std::thread::spawn(move || {
                 use rdev::{listen, Event, EventType};
@@ -174,17 +600,104 @@ pub mod macos {
 
                                     // VirtualModelを更新
                                     if let Ok(mut vm) = vm.lock() {
+                                        let was_in_host = vm.in_host(config);
+                                        if vm.is_warp_echo(config, x, y) {
+                                            // 自分でワープさせたイベントなので無視
+                                            return;
+                                        }
+                                        let (x, y) = if was_in_host {
+                                            let sample = match state.motion.lock() {
+                                                Ok(mut motion) => {
+                                                    motion.host_sample(config, &state.tuning, x, y)
+                                                }
+                                                Err(_) => Some(push_past_edge(
+                                                    config,
+                                                    &state.tuning,
+                                                    x,
+                                                    y,
+                                                )),
+                                            };
+                                            let Some(position) = sample else {
+                                                return;
+                                            };
+                                            position
+                                        } else {
+                                            (x, y)
+                                        };
+                                        let prev = (vm.virtual_x, vm.virtual_y);
                                         vm.update(config, x, y);
                                         log::debug!(
                                             "VirtualModel updated: ({}, {})",
                                             vm.virtual_x,
                                             vm.virtual_y
                                         );
-                                        if !vm.in_host(config) {
-                                            let (x, y) = vm.receiver_position(config);
-                                            let mouse_event = MouseEvent::Move { x, y };
-                                            if let Err(e) = sender.send(mouse_event) {
-                                                log::error!("Failed to send mouse event: {}", e);
+                                        let in_host = vm.in_host(config);
+                                        if let Some(preview) = vm.edge_preview(config) {
+                                            send_event(state, sender, preview);
+                                        }
+                                        if was_in_host != in_host {
+                                            if let Ok(mut motion) = state.motion.lock() {
+                                                motion.reset();
+                                            }
+                                            if in_host {
+                                                // 押したままのキーがリモートで押されっぱなしにならないよう離しておく
+                                                release_keys(state, sender);
+                                            }
+                                            let control_event = if in_host {
+                                                MouseEvent::ControlLeave
+                                            } else {
+                                                MouseEvent::ControlEnter
+                                            };
+                                            send_event(state, sender, control_event);
+                                            if !in_host && config.wake_remote_on_enter {
+                                                for wake in vm.wake_moves(config) {
+                                                    send_event(state, sender, wake);
+                                                }
+                                            }
+                                        }
+                                        if config.warp_strategy.hides_cursor()
+                                            && was_in_host != in_host
+                                        {
+                                            // 留めている間は物理カーソルを隠す
+                                            let display = CGDisplay::main();
+                                            let result = if in_host {
+                                                display.show_cursor()
+                                            } else {
+                                                display.hide_cursor()
+                                            };
+                                            if let Err(e) = result {
+                                                log::warn!("Failed to toggle cursor: {:?}", e);
+                                            }
+                                        }
+                                        if in_host && !was_in_host {
+                                            // 物理カーソルはアンカーに残っているので、仮想座標の戻り先へ移す。
+                                            // 移さないと次の移動で仮想座標がアンカーの位置へ飛ぶ
+                                            let (x, y) = vm.local_position(config);
+                                            if let Err(e) = warp_to(x, y) {
+                                                log::error!("Failed to warp mouse home: {}", e);
+                                            }
+                                        }
+                                        if !in_host {
+                                            if let Err(e) = warp_to(vm.anchor_x, vm.anchor_y) {
+                                                log::error!("Failed to warp mouse: {}", e);
+                                            }
+                                            if prev == (vm.virtual_x, vm.virtual_y) {
+                                                // 画面端に押し当てているなどで位置が変わっていない
+                                                return;
+                                            }
+                                            let position = match state.motion.lock() {
+                                                Ok(mut motion) => motion.offer(
+                                                    &state.tuning,
+                                                    vm.receiver_position(config),
+                                                ),
+                                                Err(_) => Some(vm.receiver_position(config)),
+                                            };
+                                            if let Some((x, y)) = position {
+                                                send_event(
+                                                    state,
+                                                    sender,
+                                                    MouseEvent::Move { x, y },
+                                                );
                                             }
                                         }
                                     }
@@ -197,9 +710,20 @@ pub mod macos {
                                         _ => return,
                                     };
 
-                                    if let Err(e) = sender.send(mouse_event) {
-                                        log::error!("Failed to send mouse click event: {}", e);
+                                    // 制御中の側にだけボタンイベントを送る
+                                    let in_host = match vm.lock() {
+                                        Ok(mut vm) => {
+                                            vm.track_button(&mouse_event);
+                                            vm.in_host(config)
+                                        }
+                                        Err(_) => return,
+                                    };
+                                    if in_host {
+                                        return;
                                     }
+                                    // 受信側は現在のカーソル位置で押すので、保留中の移動を先に送る
+                                    flush_move(state, sender, true);
+                                    send_event(state, sender, mouse_event);
                                 }
                                 EventType::ButtonRelease(button) => {
                                     let mouse_event = match button {
@@ -209,16 +733,35 @@ pub mod macos {
                                         _ => return,
                                     };
 
-                                    if let Err(e) = sender.send(mouse_event) {
-                                        log::error!("Failed to send mouse release event: {}", e);
+                                    // 制御中の側にだけボタンイベントを送る
+                                    let in_host = match vm.lock() {
+                                        Ok(mut vm) => {
+                                            vm.track_button(&mouse_event);
+                                            vm.in_host(config)
+                                        }
+                                        Err(_) => return,
+                                    };
+                                    if in_host {
+                                        return;
                                     }
+                                    // 受信側は現在のカーソル位置で押すので、保留中の移動を先に送る
+                                    flush_move(state, sender, true);
+                                    send_event(state, sender, mouse_event);
                                 }
                                 EventType::Wheel { delta_x, delta_y } => {
-                                    let mouse_event = MouseEvent::Scroll { delta_x, delta_y };
-
-                                    if let Err(e) = sender.send(mouse_event) {
-                                        log::error!("Failed to send mouse scroll event: {}", e);
-                                    }
+                                    // rdevはピクセル単位の連続的な量を返す。横（AXIS_2）は正が左なので、右を正に揃える
+                                    // ホスト操作中のスクロールはリモートに送らない
+                                    let Some(mouse_event) = scroll_event(
+                                        config,
+                                        vm,
+                                        -delta_x * crate::event::SCROLL_UNITS_PER_MAC_PIXEL,
+                                        delta_y * crate::event::SCROLL_UNITS_PER_MAC_PIXEL,
+                                    ) else {
+                                        return;
+                                    };
+                                    // スクロールは受信側の現在位置で行うので、保留中の移動を先に送る
+                                    flush_move(state, sender, true);
+                                    send_event(state, sender, mouse_event);
                                 }
                                 _ => {}
                             }
@@ -231,13 +774,938 @@ pub mod macos {
                 }
             });
 
+            // メインループを維持し、間引いて保留したMoveを送信間隔ごとに送る
+            let tick = self.tuning.poll_interval.max(Duration::from_millis(1));
+            while self.is_running.load(Ordering::SeqCst) {
+                tokio::time::sleep(tick).await;
+                let Ok(state) = GLOBAL_STATE.lock() else {
+                    continue;
+                };
+                if let Some(state) = state.as_ref() {
+                    if let Some(sender) = state.sender.as_ref() {
+                        flush_move(state, sender, false);
+                    }
+                }
+            }
+
+            if sender.is_closed() {
+                anyhow::bail!("Mouse capture stopped because the event channel closed");
+            }
+            log::info!("Mouse capture stopped");
+            Ok(())
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::clock::FakeClock;
+        use crate::config::Screen;
+        use crate::virtual_model::VirtualModel;
+
+        fn screen(width: u32, height: u32) -> Screen {
+            Screen {
+                width,
+                height,
+                flip_x: false,
+                flip_y: false,
+                rotation: 0,
+            }
+        }
+
+        #[test]
+        fn resting_on_the_edge_crosses_once_the_dwell_expires() {
+            let mut config = Config::template();
+            config.screen = screen(1000, 800);
+            config.remote_screen = screen(800, 600);
+            config.host_position = HostPosition::Left;
+            config.edge_dwell_ms = 100;
+            let tuning = Tuning {
+                poll_interval: Duration::from_millis(5),
+                move_threshold: 0.5,
+                secondary_move_threshold: 2.0,
+                edge_margin: 1.0,
+            };
+            let clock = FakeClock::default();
+            let mut vm = VirtualModel::with_clock(Box::new(clock.clone()));
+            vm.init(&config, 990.0, 400.0);
+            let mut motion = MotionFilter::default();
+            // エッジから離れた場所での揺れは捨てる
+            assert!(motion.host_sample(&config, &tuning, 990.0, 400.0).is_some());
+            assert!(motion.host_sample(&config, &tuning, 990.2, 400.0).is_none());
+            // エッジに着いたら、OSが同じ座標を返し続けても毎回反映する
+            for _ in 0..10 {
+                let (x, y) = motion
+                    .host_sample(&config, &tuning, 999.0, 400.0)
+                    .expect("edge-pinned samples must reach the virtual model");
+                vm.update(&config, x, y);
+                assert!(vm.in_host(&config));
+                clock.advance(Duration::from_millis(10));
+            }
+            clock.advance(Duration::from_millis(10));
+            let (x, y) = motion.host_sample(&config, &tuning, 999.0, 400.0).unwrap();
+            vm.update(&config, x, y);
+            assert!(!vm.in_host(&config));
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+pub mod linux {
+    use super::*;
+    use crate::event::SCROLL_UNITS_PER_NOTCH;
+    use crate::virtual_model::VirtualModel;
+    use evdev::{Device, InputEventKind, Key, RelativeAxisType};
+    use std::os::unix::io::{AsRawFd, RawFd};
+    use std::path::PathBuf;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+    use std::sync::Mutex as StdMutex;
+
+    /// evdevからマウスを読む送信側
+    ///
+    /// Waylandではカーソルの絶対位置を取れないので、ホスト側の位置は相対移動を積算して推定する。
+    /// リモート操作中はデバイスを掴み（grab）、ローカルのカーソルを止める
+    pub struct LinuxCapturer {
+        device: Option<String>,
+        keyboard_device: Option<String>,
+        is_running: Arc<AtomicBool>,
+    }
+
+    /// パスが指定されていれば開き、なければ条件に合う最初のデバイスを探す
+    fn open_device(
+        path: &Option<String>,
+        matches: impl Fn(&Device) -> bool,
+    ) -> Result<Option<(PathBuf, Device)>> {
+        if let Some(path) = path {
+            let device = Device::open(path)
+                .map_err(|e| anyhow::anyhow!("Failed to open {}: {}", path, e))?;
+            return Ok(Some((PathBuf::from(path), device)));
+        }
+        // 双方向モードで自分が作ったuinputデバイスを読まないようにする
+        Ok(evdev::enumerate().find(|(_, device)| {
+            !device
+                .name()
+                .is_some_and(|name| name.starts_with("sharemouse"))
+                && matches(device)
+        }))
+    }
+
+    impl LinuxCapturer {
+        pub fn new(device: Option<String>, keyboard_device: Option<String>) -> Self {
+            Self {
+                device,
+                keyboard_device,
+                is_running: Arc::new(AtomicBool::new(false)),
+            }
+        }
+
+        fn open_mouse(&self) -> Result<(PathBuf, Device)> {
+            // 相対移動と左ボタンを持つ最初のデバイスをマウスとみなす
+            open_device(&self.device, |device| {
+                device
+                    .supported_relative_axes()
+                    .is_some_and(|axes| axes.contains(RelativeAxisType::REL_X))
+                    && device
+                        .supported_keys()
+                        .is_some_and(|keys| keys.contains(Key::BTN_LEFT))
+            })?
+            .ok_or_else(|| {
+                anyhow::anyhow!(
+                    "No readable mouse found in /dev/input: set capture_device or run `sharemouse permissions`"
+                )
+            })
+        }
+
+        fn open_keyboard(&self) -> Result<Option<(PathBuf, Device)>> {
+            // 文字キーを持ち、相対移動を持たないデバイスをキーボードとみなす
+            open_device(&self.keyboard_device, |device| {
+                device
+                    .supported_keys()
+                    .is_some_and(|keys| keys.contains(Key::KEY_A))
+                    && device.supported_relative_axes().is_none()
+            })
+        }
+    }
+
+    /// 停止の確認を兼ねて、入力を待つ最長時間
+    const READ_TIMEOUT_MS: i32 = 100;
+
+    /// 入力が届くまで待つ。タイムアウトしたら`false`
+    ///
+    /// `fetch_events`は入力が来るまで戻らないので、停止できるよう読む前に必ずここで待つ
+    fn wait_readable(fd: RawFd) -> Result<bool> {
+        let mut fd = libc::pollfd {
+            fd,
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        // SAFETY: 有効なpollfdを1つだけ渡している
+        let ret = unsafe { libc::poll(&mut fd, 1, READ_TIMEOUT_MS) };
+        if ret < 0 {
+            let err = std::io::Error::last_os_error();
+            if err.kind() == std::io::ErrorKind::Interrupted {
+                return Ok(false);
+            }
+            return Err(err.into());
+        }
+        Ok(ret > 0)
+    }
+
+    /// イベントを送る。チャネルが閉じていたら共有は続けられないので、キャプチャを止める
+    fn send_event(
+        sender: &mpsc::UnboundedSender<MouseEvent>,
+        is_running: &AtomicBool,
+        event: MouseEvent,
+    ) {
+        if sender.send(event).is_err() && is_running.swap(false, Ordering::SeqCst) {
+            log::error!(
+                "!!! Event channel closed: stopping capture, mouse sharing is no longer active !!!"
+            );
+        }
+    }
+
+    /// リモート操作中だけ掴む（grab）デバイス
+    struct Grab {
+        device: Device,
+        grabbed: bool,
+        /// ログに出すデバイスの種類
+        kind: &'static str,
+    }
+
+    impl Grab {
+        fn new(device: Device, kind: &'static str) -> Self {
+            Self {
+                device,
+                grabbed: false,
+                kind,
+            }
+        }
+
+        /// 制御側に合わせて掴む・離す
+        fn sync(&mut self, in_host: bool) {
+            if self.grabbed != in_host {
+                return;
+            }
+            let result = if in_host {
+                self.device.ungrab()
+            } else {
+                self.device.grab()
+            };
+            match result {
+                Ok(()) => self.grabbed = !in_host,
+                Err(e) => log::error!("Failed to toggle the {} grab: {}", self.kind, e),
+            }
+        }
+
+        /// 掴んだまま終わるとローカルの入力が使えなくなるので離す
+        fn release(&mut self) {
+            if self.grabbed {
+                let _ = self.device.ungrab();
+                self.grabbed = false;
+            }
+        }
+    }
+
+    /// マウスとキーボードの読み取りスレッドで共有するキーボード
+    type SharedKeyboard = Arc<StdMutex<Grab>>;
+
+    /// 押下中のキー。制御をホストへ戻すとき、マウス側からも相手側で離せるよう共有する
+    type SharedKeys = Arc<StdMutex<crate::keymap::HotkeyTracker>>;
+
+    /// 押下中のキーを相手側で離すイベント
+    fn key_releases(keys: &SharedKeys) -> Vec<MouseEvent> {
+        match keys.lock() {
+            Ok(keys) => keys
+                .pressed()
+                .map(|code| MouseEvent::Key {
+                    code,
+                    pressed: false,
+                })
+                .collect(),
+            Err(_) => Vec::new(),
+        }
+    }
+
+    /// ホットキーで制御側を切り替え、相手側の状態を合わせる
+    ///
+    /// マウスの掴み直しは次の移動でCaptureが行う
+    fn toggle_by_hotkey(
+        config: &Config,
+        vm: &SharedVirtualModel,
+        sender: &mpsc::UnboundedSender<MouseEvent>,
+        is_running: &AtomicBool,
+        keys: &SharedKeys,
+    ) {
+        let Ok(mut vm) = vm.lock() else {
+            return;
+        };
+        if !vm.toggle_control(config) {
+            return;
+        }
+        let mut events = Vec::new();
+        if vm.in_host(config) {
+            // リモートで押したままの修飾キーなどを離しておく
+            events.extend(key_releases(keys));
+            events.push(MouseEvent::ControlLeave);
+        } else {
+            events.push(MouseEvent::ControlEnter);
+            if config.wake_remote_on_enter {
+                events.extend(vm.wake_moves(config));
+            }
+            let (x, y) = vm.receiver_position(config);
+            events.push(MouseEvent::Move { x, y });
+        }
+        drop(vm);
+        for event in events {
+            send_event(sender, is_running, event);
+        }
+    }
+
+    /// リモート操作中のキー入力を送る
+    ///
+    /// キーボードはエッジを越えたときにマウス側のCaptureが、ホットキーではここで掴む（grab）
+    fn run_keyboard(
+        keyboard: SharedKeyboard,
+        keys: SharedKeys,
+        config: Config,
+        vm: SharedVirtualModel,
+        sender: mpsc::UnboundedSender<MouseEvent>,
+        is_running: Arc<AtomicBool>,
+    ) -> Result<()> {
+        let fd = match keyboard.lock() {
+            Ok(keyboard) => keyboard.device.as_raw_fd(),
+            Err(_) => anyhow::bail!("Keyboard lock poisoned"),
+        };
+        while is_running.load(Ordering::SeqCst) {
+            if !wait_readable(fd)? {
+                continue;
+            }
+            let events: Vec<_> = match keyboard.lock() {
+                Ok(mut keyboard) => keyboard.device.fetch_events()?.collect(),
+                Err(_) => anyhow::bail!("Keyboard lock poisoned"),
+            };
+            for event in events {
+                let InputEventKind::Key(key) = event.kind() else {
+                    continue;
+                };
+                // 2はオートリピート。リピートは受信側のOSに任せる
+                let pressed = match event.value() {
+                    0 => false,
+                    1 => true,
+                    _ => continue,
+                };
+                let fired = match keys.lock() {
+                    Ok(mut keys) => keys.key(config.toggle_hotkey.as_ref(), key.code(), pressed),
+                    Err(_) => false,
+                };
+                if fired {
+                    // ホットキーのキーはリモートに送らない。ホスト操作中はキーボードを
+                    // 掴んでいないので、ホストには入力される
+                    toggle_by_hotkey(&config, &vm, &sender, &is_running, &keys);
+                    continue;
+                }
+                let in_host = match vm.lock() {
+                    Ok(vm) => vm.in_host(&config),
+                    Err(_) => continue,
+                };
+                if !in_host {
+                    send_event(
+                        &sender,
+                        &is_running,
+                        MouseEvent::Key {
+                            code: key.code(),
+                            pressed,
+                        },
+                    );
+                }
+            }
+            // ホットキーで制御側が変わったときに掴み直す
+            let in_host = match vm.lock() {
+                Ok(vm) => vm.in_host(&config),
+                Err(_) => continue,
+            };
+            if let Ok(mut keyboard) = keyboard.lock() {
+                keyboard.sync(in_host);
+            }
+        }
+        Ok(())
+    }
+
+    /// 終了時、制御がリモートにあればホストに戻し、押したままのキーとボタンを相手側で離す
+    fn return_to_host(
+        config: &Config,
+        vm: &SharedVirtualModel,
+        sender: &mpsc::UnboundedSender<MouseEvent>,
+        pressed: Vec<u16>,
+    ) {
+        let returned = match vm.lock() {
+            Ok(mut vm) => vm.return_to_host(config),
+            Err(_) => false,
+        };
+        if !returned {
+            return;
+        }
+        log::info!("Returning control to the host before exiting");
+        for event in release_events(pressed) {
+            let _ = sender.send(event);
+        }
+    }
+
+    /// キャプチャスレッドの状態
+    struct Capture {
+        config: Config,
+        vm: SharedVirtualModel,
+        sender: mpsc::UnboundedSender<MouseEvent>,
+        /// ホットキーでも制御側が変わるので、移動のたびに掴み直す
+        mouse: Grab,
+        /// エッジを越えた時点で掴み、最初のキー入力からリモートへ送る
+        keyboard: Option<SharedKeyboard>,
+        keys: SharedKeys,
+        is_running: Arc<AtomicBool>,
+        noise: NoiseFloor,
+    }
+
+    /// リモート操作中の小さな揺れを溜めておき、閾値を超えたらまとめて反映する
+    ///
+    /// evdevの相対移動は静止中も±1程度揺れるので、Linuxの送信側でだけ使う
+    #[derive(Default)]
+    struct NoiseFloor {
+        pending_x: f64,
+        pending_y: f64,
+    }
+
+    impl NoiseFloor {
+        /// 閾値を超えたら溜めた分を含む移動量を返す
+        fn filter(&mut self, floor_px: f64, dx: f64, dy: f64) -> Option<(f64, f64)> {
+            let (d_x, d_y) = (self.pending_x + dx, self.pending_y + dy);
+            if d_x.hypot(d_y) < floor_px {
+                (self.pending_x, self.pending_y) = (d_x, d_y);
+                return None;
+            }
+            self.reset();
+            Some((d_x, d_y))
+        }
+
+        fn reset(&mut self) {
+            (self.pending_x, self.pending_y) = (0.0, 0.0);
+        }
+    }
+
+    /// ホスト操作中の相対移動を足したカーソル位置（OS座標）
+    ///
+    /// カーソル位置は取得できないので、仮想マウスから求めた位置にあるものとみなす。
+    /// 制御がホストに戻ったとき（home_positionやホットキーを含む）も、これで戻り先から動き出す
+    fn host_target(config: &Config, vm: &VirtualModel, dx: f64, dy: f64) -> (f64, f64) {
+        let (x, y) = vm.local_position(config);
+        let (width, height) = config.screen.os_size();
+        // 画面外への押し出しもエッジ越えの判定に使うので、クランプは足す前の位置にだけ行う
+        (
+            x.max(0.0).min(width - 1.0) + dx,
+            y.max(0.0).min(height - 1.0) + dy,
+        )
+    }
+
+    /// SYN_REPORTまでに溜めた相対移動を仮想マウスに反映し、(ホスト側か, 相手側へ送るイベント)を返す
+    ///
+    /// デバイスには触れないので、掴み直しは呼び出し側が戻り値に合わせて行う
+    fn process_motion(
+        config: &Config,
+        vm: &SharedVirtualModel,
+        noise: &mut NoiseFloor,
+        keys: &SharedKeys,
+        dx: f64,
+        dy: f64,
+    ) -> Option<(bool, Vec<MouseEvent>)> {
+        let mut vm = vm.lock().ok()?;
+        let was_in_host = vm.in_host(config);
+        let prev = (vm.virtual_x, vm.virtual_y);
+        if was_in_host {
+            noise.reset();
+            let (x, y) = host_target(config, &vm, dx, dy);
+            vm.update(config, x, y);
+        } else if let Some((dx, dy)) = noise.filter(config.noise_floor_px, dx, dy) {
+            // 掴んでいる間はローカルのカーソルが動かないので、アンカーからの移動量として渡す
+            let (anchor_x, anchor_y) = (vm.anchor_x, vm.anchor_y);
+            vm.update(config, anchor_x + dx, anchor_y + dy);
+        }
+        let in_host = vm.in_host(config);
+        let mut events: Vec<MouseEvent> = vm.edge_preview(config).into_iter().collect();
+        if was_in_host != in_host {
+            if in_host {
+                // 押したままのキーがリモートで押されっぱなしにならないよう離しておく
+                events.extend(key_releases(keys));
+                events.push(MouseEvent::ControlLeave);
+            } else {
+                events.push(MouseEvent::ControlEnter);
+                if config.wake_remote_on_enter {
+                    events.extend(vm.wake_moves(config));
+                }
+            }
+        }
+        if !in_host && prev != (vm.virtual_x, vm.virtual_y) {
+            let (x, y) = vm.receiver_position(config);
+            events.push(MouseEvent::Move { x, y });
+        }
+        Some((in_host, events))
+    }
+
+    impl Capture {
+        fn send_event(&self, event: MouseEvent) {
+            send_event(&self.sender, &self.is_running, event);
+        }
+
+        /// 相対移動を反映してイベントを送り、制御側に合わせてデバイスを掴み直す
+        fn handle_motion(&mut self, dx: f64, dy: f64) {
+            let Some((in_host, events)) =
+                process_motion(&self.config, &self.vm, &mut self.noise, &self.keys, dx, dy)
+            else {
+                return;
+            };
+            for event in events {
+                self.send_event(event);
+            }
+            self.mouse.sync(in_host);
+            if let Some(keyboard) = &self.keyboard {
+                if let Ok(mut keyboard) = keyboard.lock() {
+                    keyboard.sync(in_host);
+                }
+            }
+        }
+
+        /// 制御中の側にだけボタンイベントを送る
+        fn handle_button(&mut self, event: MouseEvent) {
+            let in_host = match self.vm.lock() {
+                Ok(mut vm) => {
+                    vm.track_button(&event);
+                    vm.in_host(&self.config)
+                }
+                Err(_) => return,
+            };
+            if !in_host {
+                self.send_event(event);
+            }
+        }
+
+        fn handle_scroll(&mut self, delta_x: i64, delta_y: i64) {
+            if let Some(event) = scroll_event(&self.config, &self.vm, delta_x, delta_y) {
+                self.send_event(event);
+            }
+        }
+
+        fn run(&mut self) -> Result<()> {
+            let (mut dx, mut dy) = (0.0, 0.0);
+            let (mut wheel_x, mut wheel_y) = (0i64, 0i64);
+            // 高分解能ホイールのあるデバイスは両方を報告するので、細かい方だけを使う
+            let axes = self.mouse.device.supported_relative_axes();
+            let hi_res_x =
+                axes.is_some_and(|axes| axes.contains(RelativeAxisType::REL_HWHEEL_HI_RES));
+            let hi_res_y =
+                axes.is_some_and(|axes| axes.contains(RelativeAxisType::REL_WHEEL_HI_RES));
+            while self.is_running.load(Ordering::SeqCst) {
+                if !wait_readable(self.mouse.device.as_raw_fd())? {
+                    continue;
+                }
+                let events: Vec<_> = self.mouse.device.fetch_events()?.collect();
+                for event in events {
+                    match event.kind() {
+                        InputEventKind::RelAxis(RelativeAxisType::REL_X) => {
+                            dx += event.value() as f64
+                        }
+                        InputEventKind::RelAxis(RelativeAxisType::REL_Y) => {
+                            dy += event.value() as f64
+                        }
+                        InputEventKind::RelAxis(RelativeAxisType::REL_WHEEL) if !hi_res_y => {
+                            wheel_y += event.value() as i64 * SCROLL_UNITS_PER_NOTCH
+                        }
+                        InputEventKind::RelAxis(RelativeAxisType::REL_HWHEEL) if !hi_res_x => {
+                            wheel_x += event.value() as i64 * SCROLL_UNITS_PER_NOTCH
+                        }
+                        InputEventKind::RelAxis(RelativeAxisType::REL_WHEEL_HI_RES) => {
+                            wheel_y += event.value() as i64
+                        }
+                        InputEventKind::RelAxis(RelativeAxisType::REL_HWHEEL_HI_RES) => {
+                            wheel_x += event.value() as i64
+                        }
+                        InputEventKind::Key(key) => {
+                            // 2はオートリピート
+                            let pressed = match event.value() {
+                                0 => false,
+                                1 => true,
+                                _ => continue,
+                            };
+                            let bit = match key {
+                                Key::BTN_LEFT => crate::event::BUTTON_LEFT,
+                                Key::BTN_RIGHT => crate::event::BUTTON_RIGHT,
+                                Key::BTN_MIDDLE => crate::event::BUTTON_MIDDLE,
+                                _ => continue,
+                            };
+                            if let Some(event) = MouseEvent::from_button(bit, pressed) {
+                                self.handle_button(event);
+                            }
+                        }
+                        InputEventKind::Synchronization(_) => {
+                            if dx != 0.0 || dy != 0.0 {
+                                self.handle_motion(dx, dy);
+                                (dx, dy) = (0.0, 0.0);
+                            }
+                            if wheel_x != 0 || wheel_y != 0 {
+                                self.handle_scroll(wheel_x, wheel_y);
+                                (wheel_x, wheel_y) = (0, 0);
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            Ok(())
+        }
+    }
+
+    impl MouseCapturer for LinuxCapturer {
+        fn stop_capture(&self) {
+            self.is_running.store(false, Ordering::SeqCst);
+        }
+
+        async fn start_capture_with_model(
+            &self,
+            config: &Config,
+            sender: mpsc::UnboundedSender<MouseEvent>,
+            virtual_model: SharedVirtualModel,
+        ) -> Result<()> {
+            let (path, device) = self.open_mouse()?;
+            log::info!(
+                "Starting Linux mouse capture from {:?} ({})",
+                path,
+                device.name().unwrap_or("unnamed")
+            );
+            self.is_running.store(true, Ordering::SeqCst);
+
+            // 起動時のカーソル位置は取得もワープもできないので、設定の位置（省略時は画面中央）にあるものとして始める
+            let (local_x, local_y) = config
+                .initial_cursor()
+                .unwrap_or_else(|| config.host_center());
+            virtual_model
+                .lock()
+                .map_err(|_| anyhow::anyhow!("Virtual model lock poisoned"))?
+                .init(config, local_x, local_y);
+            log::info!(
+                "VirtualModel initialized at ({}, {}), assuming the cursor starts there",
+                local_x,
+                local_y
+            );
+            if config.confirm_first_transfer {
+                wait_for_confirmation(virtual_model.clone());
+            }
+
+            let keys = SharedKeys::default();
+            let (keyboard, keyboard_task) = match self.open_keyboard()? {
+                Some((path, device)) => {
+                    log::info!(
+                        "Forwarding keys from {:?} ({})",
+                        path,
+                        device.name().unwrap_or("unnamed")
+                    );
+                    let keyboard = Arc::new(StdMutex::new(Grab::new(device, "keyboard")));
+                    let (keyboard_ref, keys, config, vm, sender, is_running) = (
+                        keyboard.clone(),
+                        keys.clone(),
+                        config.clone(),
+                        virtual_model.clone(),
+                        sender.clone(),
+                        self.is_running.clone(),
+                    );
+                    let task = tokio::task::spawn_blocking(move || {
+                        if let Err(e) =
+                            run_keyboard(keyboard_ref.clone(), keys, config, vm, sender, is_running)
+                        {
+                            log::error!("Failed to read key events: {}", e);
+                        }
+                        if let Ok(mut keyboard) = keyboard_ref.lock() {
+                            keyboard.release();
+                        }
+                    });
+                    (Some(keyboard), Some(task))
+                }
+                None => {
+                    log::warn!("No readable keyboard found, only the mouse is shared");
+                    (None, None)
+                }
+            };
+
+            let mut capture = Capture {
+                config: config.clone(),
+                vm: virtual_model.clone(),
+                sender: sender.clone(),
+                mouse: Grab::new(device, "mouse"),
+                keyboard,
+                keys: keys.clone(),
+                is_running: self.is_running.clone(),
+                noise: NoiseFloor::default(),
+            };
+            let is_running = self.is_running.clone();
+            let mouse = tokio::task::spawn_blocking(move || {
+                if let Err(e) = capture.run() {
+                    log::error!("Failed to read mouse events: {}", e);
+                }
+                capture.mouse.release();
+                is_running.store(false, Ordering::SeqCst);
+            });
+
             // メインループを維持
             while self.is_running.load(Ordering::SeqCst) {
                 tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
             }
+            // 読み取りスレッドが掴んだデバイスを離すまで待つ
+            let _ = mouse.await;
+            if let Some(task) = keyboard_task {
+                let _ = task.await;
+            }
+            let pressed = match keys.lock() {
+                Ok(keys) => keys.pressed().collect(),
+                Err(_) => Vec::new(),
+            };
+            return_to_host(config, &virtual_model, &sender, pressed);
 
+            if sender.is_closed() {
+                anyhow::bail!("Mouse capture stopped because the event channel closed");
+            }
             log::info!("Mouse capture stopped");
             Ok(())
         }
     }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::config::{HomePosition, Screen};
+
+        fn screen(width: u32, height: u32) -> Screen {
+            Screen {
+                width,
+                height,
+                flip_x: false,
+                flip_y: false,
+                rotation: 0,
+            }
+        }
+
+        /// ホスト1000x800が左、リモート800x600が右
+        fn config() -> Config {
+            let mut config = Config::template();
+            config.screen = screen(1000, 800);
+            config.remote_screen = screen(800, 600);
+            config
+        }
+
+        /// リモートへ渡ってからアンカー基準で左へ押し戻す
+        fn cross_and_return(config: &Config, vm: &mut VirtualModel) {
+            let (x, y) = host_target(config, vm, 15.0, 0.0);
+            vm.update(config, x, y);
+            assert!(!vm.in_host(config));
+            vm.update(config, vm.anchor_x - 100.0, vm.anchor_y);
+            assert!(vm.in_host(config));
+        }
+
+        #[test]
+        fn host_moves_start_from_home_position_after_return() {
+            let mut config = config();
+            config.home_position = Some(HomePosition { x: 100.0, y: 200.0 });
+            let mut vm = VirtualModel::new();
+            vm.init(&config, 990.0, 400.0);
+            cross_and_return(&config, &mut vm);
+            assert_eq!(host_target(&config, &vm, 1.0, -1.0), (101.0, 199.0));
+        }
+
+        #[test]
+        fn host_moves_follow_the_hotkey_toggle() {
+            let config = config();
+            let mut vm = VirtualModel::new();
+            vm.init(&config, 500.0, 400.0);
+            vm.toggle_control(&config);
+            assert!(!vm.in_host(&config));
+            vm.toggle_control(&config);
+            assert!(vm.in_host(&config));
+            let (x, y) = vm.local_position(&config);
+            assert_eq!(host_target(&config, &vm, 2.0, 3.0), (x + 2.0, y + 3.0));
+        }
+
+        /// 受信側の注入を記録するだけのインジェクタ
+        struct Recorder(Arc<StdMutex<Vec<MouseEvent>>>);
+
+        impl crate::injector::MouseInjector for Recorder {
+            fn inject_event(&mut self, event: MouseEvent) -> Result<()> {
+                self.0.lock().unwrap().push(event);
+                Ok(())
+            }
+
+            fn backend(&self) -> Option<&'static str> {
+                None
+            }
+        }
+
+        #[test]
+        fn handoff_reaches_the_receiver_and_returns() {
+            use crate::injector::{ButtonSyncInjector, ClampInjector, MouseInjector};
+
+            let config = config();
+            let vm: SharedVirtualModel = Arc::new(StdMutex::new(VirtualModel::new()));
+            vm.lock().unwrap().init(&config, 990.0, 300.0);
+            let keys = SharedKeys::default();
+            let mut noise = NoiseFloor::default();
+            // ネットワークの代わりに、シリアライズしたイベントをチャネルで渡す
+            let (wire_tx, mut wire_rx) = mpsc::unbounded_channel();
+            let mut moves = |dx: f64, dy: f64| {
+                let (in_host, events) =
+                    process_motion(&config, &vm, &mut noise, &keys, dx, dy).unwrap();
+                for event in events {
+                    wire_tx.send(bincode::serialize(&event).unwrap()).unwrap();
+                }
+                in_host
+            };
+            assert!(moves(-10.0, 0.0));
+            assert!(!moves(25.0, 0.0));
+            assert!(!moves(20.0, 0.0));
+            assert!(!moves(10.0, 10.0));
+            // リモート画面の右端より先へは行かない
+            assert!(!moves(2000.0, 0.0));
+            assert!(moves(-1000.0, 0.0));
+            // ホストに戻った後の移動は送らない
+            assert!(moves(-10.0, 0.0));
+
+            let injected = Arc::new(StdMutex::new(Vec::new()));
+            let mut injector = ClampInjector::new(
+                Box::new(ButtonSyncInjector::new(Box::new(Recorder(
+                    injected.clone(),
+                )))),
+                (800, 600),
+            );
+            while let Ok(bytes) = wire_rx.try_recv() {
+                let event: MouseEvent = bincode::deserialize(&bytes).unwrap();
+                injector.inject_event(event).unwrap();
+            }
+            assert_eq!(
+                *injected.lock().unwrap(),
+                vec![
+                    MouseEvent::ControlEnter,
+                    MouseEvent::Move { x: 5.0, y: 300.0 },
+                    MouseEvent::Move { x: 25.0, y: 300.0 },
+                    MouseEvent::Move { x: 35.0, y: 310.0 },
+                    MouseEvent::Move { x: 799.0, y: 310.0 },
+                    MouseEvent::ControlLeave,
+                ]
+            );
+            // 制御はホストに戻り、戻った位置から動き出す
+            let vm = vm.lock().unwrap();
+            assert!(vm.in_host(&config));
+            let (x, _) = vm.local_position(&config);
+            assert!(x < 1000.0);
+        }
+
+        #[test]
+        fn scroll_carries_the_remote_cursor_position() {
+            let config = config();
+            let vm: SharedVirtualModel = Arc::new(StdMutex::new(VirtualModel::new()));
+            vm.lock().unwrap().init(&config, 990.0, 300.0);
+            assert_eq!(scroll_event(&config, &vm, 0, -120), None);
+            let keys = SharedKeys::default();
+            let mut noise = NoiseFloor::default();
+            // 渡った直後は入口の位置から始まる
+            process_motion(&config, &vm, &mut noise, &keys, 30.0, 20.0).unwrap();
+            process_motion(&config, &vm, &mut noise, &keys, 15.0, 0.0).unwrap();
+            let position = vm.lock().unwrap().receiver_position(&config);
+            assert_eq!(position, (20.0, 320.0));
+            assert_eq!(
+                scroll_event(&config, &vm, 60, -120),
+                Some(MouseEvent::Scroll {
+                    x: 20.0,
+                    y: 320.0,
+                    delta_x: 60,
+                    delta_y: -120,
+                })
+            );
+        }
+
+        #[test]
+        fn jitter_below_the_noise_floor_is_held_back() {
+            let mut noise = NoiseFloor::default();
+            for (dx, dy) in [(1.0, 0.0), (-1.0, 1.0), (0.0, -1.0), (1.0, 1.0)] {
+                assert_eq!(noise.filter(3.0, dx, dy), None);
+            }
+            // 溜めた分も含めて反映する
+            assert_eq!(noise.filter(3.0, 2.0, 0.0), Some((3.0, 1.0)));
+            assert_eq!(noise.filter(0.0, 1.0, 0.0), Some((1.0, 0.0)));
+        }
+
+        #[test]
+        fn pushes_past_the_edge_start_from_the_clamped_position() {
+            let config = config();
+            let mut vm = VirtualModel::new();
+            vm.init(&config, 0.0, 0.0);
+            assert_eq!(host_target(&config, &vm, -5.0, -5.0), (-5.0, -5.0));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::virtual_model::VirtualModel;
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn scroll_lands_where_the_cursor_is_for_absolute_and_normalized_receivers() {
+        let mut config = Config::template();
+        config.screen.width = 1000;
+        config.screen.height = 800;
+        config.remote_screen.width = 1920;
+        config.remote_screen.height = 1080;
+        let vm: SharedVirtualModel = Arc::new(Mutex::new(VirtualModel::new()));
+        vm.lock().unwrap().init(&config, 500.0, 300.0);
+        // ホスト操作中のスクロールはリモートに送らない
+        assert_eq!(scroll_event(&config, &vm, 0, -120), None);
+        let cursor = {
+            let mut vm = vm.lock().unwrap();
+            assert!(vm.toggle_control(&config));
+            let (x, y) = (vm.anchor_x + 40.0, vm.anchor_y + 25.0);
+            vm.update(&config, x, y);
+            vm.receiver_position(&config)
+        };
+        let event = scroll_event(&config, &vm, 60, -120).unwrap();
+        assert_eq!(
+            event,
+            MouseEvent::Scroll {
+                x: cursor.0,
+                y: cursor.1,
+                delta_x: 60,
+                delta_y: -120,
+            }
+        );
+        // 割合で受け取る受信側も、直前のMoveと同じ画素でスクロールする
+        let (width, height) = config.remote_screen.os_size();
+        let moved = MouseEvent::Move {
+            x: cursor.0,
+            y: cursor.1,
+        }
+        .normalize(width, height)
+        .denormalize(width, height);
+        match (
+            event.normalize(width, height).denormalize(width, height),
+            moved,
+        ) {
+            (MouseEvent::Scroll { x, y, .. }, MouseEvent::Move { x: mx, y: my }) => {
+                assert_eq!((x.round(), y.round()), (mx.round(), my.round()));
+                assert_eq!((x.round(), y.round()), (cursor.0.round(), cursor.1.round()));
+            }
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn confirmation_needs_a_line() {
+        assert!(confirmed(&b"\n"[..]));
+        assert!(confirmed(&b"yes"[..]));
+        // EOFは拒否として扱う
+        assert!(!confirmed(&b""[..]));
+    }
 }
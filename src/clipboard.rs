@@ -0,0 +1,85 @@
+use anyhow::Result;
+use std::process::Stdio;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+
+/// 一度に共有するクリップボードの上限（UDPの1データグラムに収める）
+pub const MAX_BYTES: usize = 32 * 1024;
+
+/// クリップボードを読み出すコマンド
+#[cfg(target_os = "macos")]
+fn paste_command() -> &'static [&'static str] {
+    &["pbpaste"]
+}
+
+/// クリップボードに書き込むコマンド
+#[cfg(target_os = "macos")]
+fn copy_command() -> &'static [&'static str] {
+    &["pbcopy"]
+}
+
+#[cfg(target_os = "linux")]
+fn is_wayland() -> bool {
+    std::env::var_os("WAYLAND_DISPLAY").is_some()
+}
+
+/// クリップボードを読み出すコマンド（Waylandはwl-clipboard、X11はxclip）
+#[cfg(target_os = "linux")]
+fn paste_command() -> &'static [&'static str] {
+    if is_wayland() {
+        &["wl-paste", "--no-newline"]
+    } else {
+        &["xclip", "-selection", "clipboard", "-o"]
+    }
+}
+
+/// クリップボードに書き込むコマンド
+#[cfg(target_os = "linux")]
+fn copy_command() -> &'static [&'static str] {
+    if is_wayland() {
+        &["wl-copy"]
+    } else {
+        &["xclip", "-selection", "clipboard"]
+    }
+}
+
+/// クリップボードのテキストを読み出す
+pub async fn read() -> Result<String> {
+    let command = paste_command();
+    let output = Command::new(command[0])
+        .args(&command[1..])
+        .stdin(Stdio::null())
+        .output()
+        .await
+        .map_err(|e| anyhow::anyhow!("Failed to run {}: {}", command[0], e))?;
+    if !output.status.success() {
+        anyhow::bail!(
+            "{} failed: {}",
+            command[0],
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    String::from_utf8(output.stdout)
+        .map_err(|_| anyhow::anyhow!("The clipboard does not contain text"))
+}
+
+/// クリップボードにテキストを書き込む
+pub async fn write(text: &str) -> Result<()> {
+    let command = copy_command();
+    // wl-copyやxclipはバックグラウンドに残って内容を提供するので、出力は受け取らない
+    let mut child = Command::new(command[0])
+        .args(&command[1..])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .map_err(|e| anyhow::anyhow!("Failed to run {}: {}", command[0], e))?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(text.as_bytes()).await?;
+    }
+    let status = child.wait().await?;
+    if !status.success() {
+        anyhow::bail!("{} failed with {}", command[0], status);
+    }
+    Ok(())
+}
@@ -0,0 +1,51 @@
+use std::time::Instant;
+#[cfg(test)]
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+/// 時刻の取得元
+///
+/// 時間に依存する処理（速度計算やボタン間隔など）に注入し、テストでは任意の時刻を返せるようにする
+pub trait Clock: Send {
+    fn now(&self) -> Instant;
+}
+
+/// OSの単調時計
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+/// テスト用の時計。`advance`で進めた分だけ時刻が進む
+///
+/// 複製は同じ時刻を共有するので、注入した後もテスト側から進められる
+#[cfg(test)]
+#[derive(Debug, Clone)]
+pub struct FakeClock(Arc<Mutex<Instant>>);
+
+#[cfg(test)]
+impl Default for FakeClock {
+    fn default() -> Self {
+        Self(Arc::new(Mutex::new(Instant::now())))
+    }
+}
+
+#[cfg(test)]
+impl FakeClock {
+    pub fn advance(&self, duration: Duration) {
+        *self.0.lock().unwrap() += duration;
+    }
+}
+
+#[cfg(test)]
+impl Clock for FakeClock {
+    fn now(&self) -> Instant {
+        *self.0.lock().unwrap()
+    }
+}
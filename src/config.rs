@@ -1,3 +1,7 @@
+use crate::discovery;
+use crate::keymap::Hotkey;
+use crate::network::IpNet;
+use crate::schedule::QuietHours;
 use anyhow::Result;
 use serde::{Deserialize, Serialize};
 use std::fs;
@@ -5,20 +9,454 @@ use std::path::Path;
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct Config {
+    /// receiverのアドレスかホスト名（IPv6は"fe80::1"や"[fe80::1]"）
+    ///
+    /// "discover"なら、起動時にLANでreceiverのビーコンを探す（remote_portもビーコンの値になる）
     pub remote_ip: String,
     pub remote_port: u16,
+    /// 送信に使うトランスポート。受信側はUDPとTCPの両方で待ち受ける
+    #[serde(default)]
+    pub transport: Transport,
     pub screen: Screen,
     pub remote_screen: Screen,
+    /// 仮想画面でのホストの位置。移譲するエッジもこれで決まる（leftならホストの右端で移譲し、リモートの左端で戻る）
     pub host_position: HostPosition,
+    /// 初回の制御権移譲前にユーザーの確認を求めるか
+    #[serde(default)]
+    pub confirm_first_transfer: bool,
+    #[serde(default)]
+    pub edge: Edge,
+    /// 移譲に必要なエッジ到達時の最低速度（px/ms）。0で無効
+    #[serde(default)]
+    pub edge_min_velocity: f64,
+    /// 移譲エッジにこの時間（ミリ秒）押し当て続けてから移譲する。0で無効
+    #[serde(default)]
+    pub edge_dwell_ms: u64,
+    /// リモート操作中の物理カーソルの扱い
+    #[serde(default)]
+    pub warp_strategy: WarpStrategy,
+    /// 相手側画面に入るときのエッジからの距離（px）
+    #[serde(default = "default_entry_margin_px")]
+    pub entry_margin_px: f64,
+    /// receiverが応答しないときにSSHで起動する設定
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub remote_launch: Option<RemoteLaunch>,
+    /// 共有を止める時間帯（例: "sat,sun 00:00-23:59", "18:00-09:00"）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub quiet_hours: Option<QuietHours>,
+    /// 起動時に実行し、標準出力（例: "1920x1080"）でremote_screenのサイズを上書きするコマンド
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub remote_screen_cmd: Option<String>,
+    /// 操作対象にするリモートのモニター（省略時はリモート画面の原点から）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub remote_monitor: Option<RemoteMonitor>,
+    /// 制御権を移譲したとき、スリープ中のリモート画面を起こすために小さな移動を送るか
+    #[serde(default)]
+    pub wake_remote_on_enter: bool,
+    /// リモート操作中に現在位置を送り直す間隔（ミリ秒）。0で無効
+    #[serde(default)]
+    pub resync_interval_ms: u64,
+    /// リモート操作中、アンカーからこの距離（px）以内の移動は無視する
+    ///
+    /// ワープ直後の端数による見かけの移動量を消すため
+    #[serde(default = "default_center_deadband_px")]
+    pub center_deadband_px: f64,
+    /// リモート操作中、累積移動量がこの距離（px）を超えるまでMoveを送らない。0で無効
+    ///
+    /// evdevの揺れ対策なので、Linuxの送信側でだけ効く
+    #[serde(default)]
+    pub noise_floor_px: f64,
+    /// macOSの送信側で、リモートへのMoveをまとめて送る間隔（ミリ秒）。0で届いたたびに送る
+    #[serde(default = "default_poll_interval_ms")]
+    pub poll_interval_ms: u64,
+    /// macOSの送信側で、ホスト操作中にこの距離（px）未満しか動かない移動を無視する
+    #[serde(default = "default_move_threshold")]
+    pub move_threshold: f64,
+    /// macOSの送信側で、リモート操作中に前回送った位置からこの距離（px）未満の移動は、
+    /// 次の送信間隔まで保留する
+    #[serde(default = "default_secondary_move_threshold")]
+    pub secondary_move_threshold: f64,
+    /// macOSの送信側で、移譲エッジからこの距離（px）以内に来たらエッジを越えたとみなす
+    #[serde(default = "default_edge_margin")]
+    pub edge_margin: f64,
+    /// 制御側が切り替わってから次の切り替えを許すまでの時間（ミリ秒）。0で無効
+    #[serde(default)]
+    pub transfer_cooldown_ms: u64,
+    /// 移譲エッジからこの距離（px）以内に近づいたら、リモートに入る位置を予告する。0で無効
+    #[serde(default)]
+    pub edge_preview_px: f64,
+    /// 座標をremote_screenに対する0..1の割合で送り、受信側で実際の画面サイズに戻す
+    #[serde(default)]
+    pub normalized_coordinates: bool,
+    /// ホストに戻ったとき、計算上の位置に関わらずカーソルを置く位置
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub home_position: Option<HomePosition>,
+    /// イベントの送信形式。受信側は形式を自動で判別する
+    #[serde(default)]
+    pub wire_format: WireFormat,
+    /// Linuxで送信側になるときに読むマウスの入力デバイス（例: /dev/input/event5）。省略時は自動で探す
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub capture_device: Option<String>,
+    /// Linuxで送信側になるときに読むキーボードの入力デバイス。省略時は自動で探す
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub keyboard_device: Option<String>,
+    /// リモート操作中の移動量に掛ける倍率
+    #[serde(default = "default_sensitivity")]
+    pub sensitivity: f64,
+    /// 加速度。1イベントの移動量（px）あたり倍率をこの割合だけ上げる。0で無効
+    #[serde(default)]
+    pub acceleration: f64,
+    /// 画面を移るとき、y座標を画面の高さの比率で変換する（同じ相対的な高さで入る）
+    #[serde(default)]
+    pub scale_on_transfer: bool,
+    /// エッジを使わずに制御側を切り替えるホットキー（例: "cmd+shift+m"）
+    ///
+    /// ホスト操作中に押したホットキーは、Linuxではホストにも入力される
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub toggle_hotkey: Option<Hotkey>,
+    /// 制御が移るときにクリップボードを共有する（受信側も --share-clipboard が必要）
+    #[serde(default)]
+    pub share_clipboard: bool,
+    /// この時間（ミリ秒）内に続いたMoveをまとめ、最後の位置だけを送る。0で無効
+    #[serde(default)]
+    pub coalesce_ms: u64,
+    /// 通信を暗号化・認証する共有鍵。受信側には同じ値を --psk-file で渡す
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub psk: Option<String>,
+    /// 起動時のカーソル位置（"current", "center", または {x, y}）
+    #[serde(default)]
+    pub initial_position: InitialPosition,
+    /// 双方向モード: このポートで相手のsenderからのイベントも受信し、このマシンに注入する
+    ///
+    /// 相手側でもlisten_port付きでsendを起動し、互いのremote_ipを向け合う
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub listen_port: Option<u16>,
+    /// listen_portで待ち受けるアドレス（省略時は0.0.0.0。IPv6なら"::"）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bind_address: Option<std::net::IpAddr>,
+    /// listen_portで受け付ける送信元のIPアドレスかCIDR（例: 192.168.1.0/24）。空なら全て受け付ける
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub allowed_senders: Vec<IpNet>,
+    /// remote_ipが"discover"のとき、この名前で告知しているreceiverだけを選ぶ（省略時は最初に見つかったもの）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub discovery_name: Option<String>,
+    /// 送信に使うローカルのアドレスとポート（省略時はOSに任せる）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub local_bind: Option<LocalBind>,
+}
+
+/// ホストに戻ったときのカーソル位置（ホストのOS座標）
+///
+/// マルチモニターで戻り先のモニターが計算次第で変わるのを避けるため
+#[derive(Debug, Clone, Copy, Deserialize, Serialize)]
+pub struct HomePosition {
+    pub x: f64,
+    pub y: f64,
+}
+
+/// 起動時のカーソル位置
+#[derive(Debug, Clone, Copy, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum InitialPosition {
+    Mode(InitialMode),
+    /// 指定した位置（ホストのOS座標）にワープしてから始める
+    ///
+    /// Linuxではワープできないので、起動時にカーソルがそこにあるものとして扱う
+    Point(HomePosition),
+}
+
+impl Default for InitialPosition {
+    fn default() -> Self {
+        InitialPosition::Mode(InitialMode::Current)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum InitialMode {
+    /// 今の位置から始める（Linuxでは取得できないので画面中央とみなす）
+    Current,
+    /// 画面中央にワープしてから始める
+    Center,
+}
+
+/// リモートのマルチモニター環境で操作対象にするモニター
+///
+/// remote_screenにはこのモニターのサイズを書く
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct RemoteMonitor {
+    /// ログに出す名前（例: DP-2）
+    pub name: String,
+    /// リモートのデスクトップ座標でのモニター左上の位置
+    pub x: f64,
+    pub y: f64,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct RemoteLaunch {
+    /// sshの接続先（例: user@192.168.1.100）
+    pub ssh_target: String,
+    /// 相手側で実行するコマンド（例: nohup sharemouse receive >/dev/null 2>&1 &）
+    pub command: String,
+}
+
+/// 送信側のソケットを開くアドレス
+///
+/// 複数のインターフェースがあるときの経路や、ファイアウォールで許可した送信元ポートに合わせる
+#[derive(Debug, Clone, Copy, Deserialize, Serialize)]
+pub struct LocalBind {
+    pub address: std::net::IpAddr,
+    /// 固定する送信元ポート（省略時は空いているポート）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub port: Option<u16>,
+}
+
+impl LocalBind {
+    pub fn socket_addr(&self) -> std::net::SocketAddr {
+        std::net::SocketAddr::new(self.address, self.port.unwrap_or(0))
+    }
+}
+
+/// コマンドを実行して画面サイズを取得する
+///
+/// 出力は `1920x1080` または `1920 1080` の形式
+fn query_screen_size(cmd: &str) -> Result<(u32, u32)> {
+    let output = std::process::Command::new("sh")
+        .args(["-c", cmd])
+        .output()
+        .map_err(|e| anyhow::anyhow!("Failed to run remote_screen_cmd {:?}: {}", cmd, e))?;
+    if !output.status.success() {
+        anyhow::bail!(
+            "remote_screen_cmd {:?} failed with {}: {}",
+            cmd,
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let parsed = stdout
+        .trim()
+        .split_once(|c: char| c == 'x' || c.is_whitespace())
+        .and_then(|(w, h)| Some((w.trim().parse::<u32>().ok()?, h.trim().parse::<u32>().ok()?)));
+    match parsed {
+        Some((width, height)) if width > 0 && height > 0 => Ok((width, height)),
+        _ => anyhow::bail!(
+            "remote_screen_cmd {:?} printed {:?}, expected WIDTHxHEIGHT",
+            cmd,
+            stdout.trim()
+        ),
+    }
+}
+
+fn default_entry_margin_px() -> f64 {
+    5.0
+}
+
+fn default_sensitivity() -> f64 {
+    1.0
+}
+
+fn default_center_deadband_px() -> f64 {
+    0.5
+}
+
+fn default_poll_interval_ms() -> u64 {
+    5
+}
+
+fn default_move_threshold() -> f64 {
+    0.5
+}
+
+fn default_secondary_move_threshold() -> f64 {
+    2.0
+}
+
+fn default_edge_margin() -> f64 {
+    1.0
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct Screen {
     pub width: u32,
     pub height: u32,
+    /// 物理的に左右反転して設置されているか
+    #[serde(default)]
+    pub flip_x: bool,
+    /// 物理的に上下反転して設置されているか
+    #[serde(default)]
+    pub flip_y: bool,
+    /// OS座標が物理的な向きに対して時計回りに何度回転しているか（0, 90, 180, 270）
+    ///
+    /// width/heightは物理的な向きでのサイズを書く
+    #[serde(default)]
+    pub rotation: u16,
+}
+
+impl Screen {
+    /// OS座標 ⇔ 物理座標の変換（反転なので往復とも同じ変換）
+    pub fn flip(&self, x: f64, y: f64) -> (f64, f64) {
+        let x = if self.flip_x {
+            self.width as f64 - 1.0 - x
+        } else {
+            x
+        };
+        let y = if self.flip_y {
+            self.height as f64 - 1.0 - y
+        } else {
+            y
+        };
+        (x, y)
+    }
+
+    /// OS座標系での画面サイズ（90°/270°回転時は幅と高さが入れ替わる）
+    pub fn os_size(&self) -> (f64, f64) {
+        match self.rotation {
+            90 | 270 => (self.height as f64, self.width as f64),
+            _ => (self.width as f64, self.height as f64),
+        }
+    }
+
+    /// OS座標 → 物理座標
+    pub fn to_physical(&self, x: f64, y: f64) -> (f64, f64) {
+        let w = self.width as f64 - 1.0;
+        let h = self.height as f64 - 1.0;
+        let (x, y) = match self.rotation {
+            90 => (w - y, x),
+            180 => (w - x, h - y),
+            270 => (y, h - x),
+            _ => (x, y),
+        };
+        self.flip(x, y)
+    }
+
+    /// 物理座標 → OS座標
+    pub fn to_os(&self, x: f64, y: f64) -> (f64, f64) {
+        let w = self.width as f64 - 1.0;
+        let h = self.height as f64 - 1.0;
+        let (x, y) = self.flip(x, y);
+        match self.rotation {
+            90 => (y, w - x),
+            180 => (w - x, h - y),
+            270 => (h - y, x),
+            _ => (x, y),
+        }
+    }
+}
+
+/// 各エッジの設定
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct Edge {
+    /// ホスト → リモートへ移譲するエッジ
+    #[serde(default)]
+    pub to_remote: EdgeRange,
+    /// リモート → ホストへ戻すエッジ
+    #[serde(default)]
+    pub to_host: EdgeRange,
+}
+
+/// エッジのうち移譲が有効な範囲（エッジ長に対する0.0〜1.0の割合）
+///
+/// 範囲外のエッジは壁として振る舞う
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct EdgeRange {
+    pub start: f64,
+    pub end: f64,
 }
 
-#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+impl Default for EdgeRange {
+    fn default() -> Self {
+        Self {
+            start: 0.0,
+            end: 1.0,
+        }
+    }
+}
+
+impl EdgeRange {
+    pub fn contains(&self, fraction: f64) -> bool {
+        self.start <= fraction && fraction <= self.end
+    }
+    fn validate(&self, name: &str) -> Result<()> {
+        if !(0.0..=1.0).contains(&self.start) || !(0.0..=1.0).contains(&self.end) {
+            anyhow::bail!("edge.{}: start and end must be within 0.0..=1.0", name);
+        }
+        if self.start >= self.end {
+            anyhow::bail!(
+                "edge.{}: start ({}) must be less than end ({})",
+                name,
+                self.start,
+                self.end
+            );
+        }
+        Ok(())
+    }
+}
+
+/// リモート操作中に物理カーソルをどこに固定するか
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum WarpStrategy {
+    /// 画面中央にワープし続ける
+    #[default]
+    Center,
+    /// エッジを越えた地点（画面端から`EDGE_WARP_INSET_PX`内側）に留めてカーソルを隠す
+    Park,
+    /// 移譲エッジから少し内側に留めてカーソルを隠す
+    ///
+    /// 移動量は常にエッジ際の同じ基準点から測る。基準点はエッジから
+    /// `EDGE_WARP_INSET_PX` 内側なので、1イベントでそれ以上エッジ方向へ動いた分は
+    /// OSにクランプされて失われる（中央ワープより速い動きに弱い）
+    Edge,
+}
+
+impl WarpStrategy {
+    /// リモート操作中に物理カーソルを隠すか
+    ///
+    /// Linuxではマウスを掴むのでカーソルは元から動かず、隠す必要がない
+    #[cfg(target_os = "macos")]
+    pub fn hides_cursor(&self) -> bool {
+        *self != WarpStrategy::Center
+    }
+}
+
+/// `WarpStrategy::Edge`/`Park` で物理カーソルを留める、画面端からの距離（px）
+pub const EDGE_WARP_INSET_PX: f64 = 32.0;
+
+/// イベントを運ぶトランスポート
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Transport {
+    #[default]
+    Udp,
+    /// 長さプレフィックス付きフレームで送る。不安定な回線でもイベントが落ちない
+    Tcp,
+}
+
+/// ネットワーク上のイベントの形式
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum WireFormat {
+    #[default]
+    Bincode,
+    /// 1行1パケットのJSON。圧縮はしない
+    ///
+    /// 受信側は通し番号なしの`MouseEvent`だけの行（例: `{"Move":{"x":100,"y":200}}`）も受け付けるので、
+    /// netcatなどから直接イベントを送れる
+    Json,
+}
+
+/// 制御セッション外で受信したイベントの扱い
+#[derive(Debug, Clone, Copy, Default, PartialEq, clap::ValueEnum)]
+pub enum SessionPolicy {
+    /// 常に注入する
+    #[default]
+    Inject,
+    /// ControlEnterを受信するまで注入しない
+    IgnoreUntilEnter,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, PartialEq)]
 #[serde(rename_all = "lowercase")]
 pub enum HostPosition {
     Left,
@@ -28,32 +466,383 @@ pub enum HostPosition {
 impl Config {
     pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
         let content = fs::read_to_string(path)?;
-        let config: Config = serde_yaml::from_str(&content)?;
+        let mut config: Config = serde_yaml::from_str(&content)?;
+        if let Some(cmd) = &config.remote_screen_cmd {
+            let (width, height) = query_screen_size(cmd)?;
+            log::info!(
+                "remote_screen set to {}x{} by remote_screen_cmd",
+                width,
+                height
+            );
+            config.remote_screen.width = width;
+            config.remote_screen.height = height;
+        }
+        if config.remote_ip == discovery::DISCOVER {
+            let found = discovery::discover(config.discovery_name.as_deref())?;
+            config.remote_ip = found.ip().to_string();
+            config.remote_port = found.port();
+        }
+        config.validate()?;
         Ok(config)
     }
 
+    pub fn validate(&self) -> Result<()> {
+        self.validate_remote_addr()?;
+        if self.remote_port == 0 {
+            anyhow::bail!("remote_port must be between 1 and 65535, got 0");
+        }
+        self.edge.to_remote.validate("to_remote")?;
+        self.edge.to_host.validate("to_host")?;
+        for (name, screen) in [
+            ("screen", &self.screen),
+            ("remote_screen", &self.remote_screen),
+        ] {
+            if screen.width == 0 || screen.height == 0 {
+                anyhow::bail!(
+                    "{} must have a non-zero size, got {}x{}",
+                    name,
+                    screen.width,
+                    screen.height
+                );
+            }
+            if !matches!(screen.rotation, 0 | 90 | 180 | 270) {
+                anyhow::bail!(
+                    "{}.rotation must be one of 0, 90, 180, 270, got {}",
+                    name,
+                    screen.rotation
+                );
+            }
+        }
+        if self.edge_min_velocity.is_nan() || self.edge_min_velocity < 0.0 {
+            anyhow::bail!(
+                "edge_min_velocity must be non-negative, got {}",
+                self.edge_min_velocity
+            );
+        }
+        if self.normalized_coordinates && self.remote_monitor.is_some() {
+            // 割合はremote_screen全体に対するものなので、モニターのオフセットと両立しない
+            anyhow::bail!("normalized_coordinates cannot be combined with remote_monitor");
+        }
+        if let Some(monitor) = &self.remote_monitor {
+            if !monitor.x.is_finite() || !monitor.y.is_finite() {
+                anyhow::bail!(
+                    "remote_monitor {}: position must be finite, got ({}, {})",
+                    monitor.name,
+                    monitor.x,
+                    monitor.y
+                );
+            }
+        }
+        if let Some(home) = &self.home_position {
+            let (width, height) = self.screen.os_size();
+            if !(0.0..width).contains(&home.x) || !(0.0..height).contains(&home.y) {
+                anyhow::bail!(
+                    "home_position ({}, {}) must be inside the screen ({}x{})",
+                    home.x,
+                    home.y,
+                    width,
+                    height
+                );
+            }
+        }
+        if let InitialPosition::Point(point) = &self.initial_position {
+            let (width, height) = self.screen.os_size();
+            if !(0.0..width).contains(&point.x) || !(0.0..height).contains(&point.y) {
+                anyhow::bail!(
+                    "initial_position ({}, {}) must be inside the screen ({}x{})",
+                    point.x,
+                    point.y,
+                    width,
+                    height
+                );
+            }
+        }
+        if self.psk.as_deref().is_some_and(str::is_empty) {
+            anyhow::bail!("psk must not be empty");
+        }
+        if self.center_deadband_px.is_nan() || self.center_deadband_px < 0.0 {
+            anyhow::bail!(
+                "center_deadband_px must be non-negative, got {}",
+                self.center_deadband_px
+            );
+        }
+        if self.noise_floor_px.is_nan() || self.noise_floor_px < 0.0 {
+            anyhow::bail!(
+                "noise_floor_px must be non-negative, got {}",
+                self.noise_floor_px
+            );
+        }
+        for (name, value) in [
+            ("move_threshold", self.move_threshold),
+            ("secondary_move_threshold", self.secondary_move_threshold),
+            ("edge_margin", self.edge_margin),
+        ] {
+            if value.is_nan() || value < 0.0 {
+                anyhow::bail!("{} must be non-negative, got {}", name, value);
+            }
+        }
+        if self.edge_preview_px.is_nan() || self.edge_preview_px < 0.0 {
+            anyhow::bail!(
+                "edge_preview_px must be non-negative, got {}",
+                self.edge_preview_px
+            );
+        }
+        if !self.sensitivity.is_finite() || self.sensitivity <= 0.0 {
+            anyhow::bail!("sensitivity must be positive, got {}", self.sensitivity);
+        }
+        if !self.acceleration.is_finite() || self.acceleration < 0.0 {
+            anyhow::bail!(
+                "acceleration must be non-negative, got {}",
+                self.acceleration
+            );
+        }
+        if self.entry_margin_px.is_nan() || self.entry_margin_px < 0.0 {
+            anyhow::bail!(
+                "entry_margin_px must be non-negative, got {}",
+                self.entry_margin_px
+            );
+        }
+        Ok(())
+    }
+
+    /// remote_ipがIPアドレスか、解決できるホスト名か
+    fn validate_remote_addr(&self) -> Result<()> {
+        use std::net::{IpAddr, ToSocketAddrs};
+
+        match self.remote_host().parse::<IpAddr>() {
+            Ok(ip) if ip.is_unspecified() => anyhow::bail!(
+                "remote_ip '{}' is not a valid address: set the receiver's address",
+                self.remote_ip
+            ),
+            Ok(_) => return Ok(()),
+            Err(_) => {}
+        }
+        // "000"のような数字だけの値はgetaddrinfoがIPアドレスとして解釈してしまうので、ホスト名とみなさない
+        if !self.remote_ip.chars().any(|c| c.is_ascii_alphabetic()) {
+            anyhow::bail!("remote_ip '{}' is not a valid address", self.remote_ip);
+        }
+        let resolved = (self.remote_host(), self.remote_port)
+            .to_socket_addrs()
+            .map_err(|e| anyhow::anyhow!("remote_ip '{}' does not resolve: {}", self.remote_ip, e))?
+            .next();
+        if resolved.is_none() {
+            anyhow::bail!(
+                "remote_ip '{}' does not resolve to any address",
+                self.remote_ip
+            );
+        }
+        Ok(())
+    }
+
     pub fn create_template<P: AsRef<Path>>(path: P) -> Result<()> {
-        let template = Config {
+        let yaml = serde_yaml::to_string(&Self::template())?;
+        fs::write(path, yaml)?;
+        Ok(())
+    }
+    /// テンプレートの設定（全ての項目を既定値で埋めたもの）
+    pub fn template() -> Self {
+        Config {
             remote_ip: "192.168.1.100".to_string(),
             remote_port: 5000,
+            transport: Transport::Udp,
             screen: Screen {
                 width: 2600,
                 height: 1440,
+                flip_x: false,
+                flip_y: false,
+                rotation: 0,
             },
             remote_screen: Screen {
                 width: 1920,
                 height: 1080,
+                flip_x: false,
+                flip_y: false,
+                rotation: 0,
             },
             host_position: HostPosition::Left,
+            confirm_first_transfer: false,
+            edge: Edge::default(),
+            edge_min_velocity: 0.0,
+            edge_dwell_ms: 0,
+            warp_strategy: WarpStrategy::Center,
+            entry_margin_px: default_entry_margin_px(),
+            remote_launch: None,
+            quiet_hours: None,
+            remote_screen_cmd: None,
+            remote_monitor: None,
+            wake_remote_on_enter: false,
+            resync_interval_ms: 0,
+            center_deadband_px: default_center_deadband_px(),
+            noise_floor_px: 0.0,
+            poll_interval_ms: default_poll_interval_ms(),
+            move_threshold: default_move_threshold(),
+            secondary_move_threshold: default_secondary_move_threshold(),
+            edge_margin: default_edge_margin(),
+            transfer_cooldown_ms: 0,
+            edge_preview_px: 0.0,
+            normalized_coordinates: false,
+            home_position: None,
+            wire_format: WireFormat::Bincode,
+            capture_device: None,
+            keyboard_device: None,
+            sensitivity: default_sensitivity(),
+            acceleration: 0.0,
+            scale_on_transfer: false,
+            toggle_hotkey: None,
+            share_clipboard: false,
+            coalesce_ms: 0,
+            psk: None,
+            initial_position: InitialPosition::default(),
+            listen_port: None,
+            bind_address: None,
+            allowed_senders: Vec::new(),
+            discovery_name: None,
+            local_bind: None,
+        }
+    }
+    /// 名前解決に渡すremote_ip（IPv6の角括弧は外す）
+    pub fn remote_host(&self) -> &str {
+        self.remote_ip
+            .strip_prefix('[')
+            .and_then(|host| host.strip_suffix(']'))
+            .unwrap_or(&self.remote_ip)
+    }
+    pub fn host_center(&self) -> (f64, f64) {
+        let (width, height) = self.screen.os_size();
+        let x = width / 2.0;
+        let y = height / 2.0;
+        (x, y)
+    }
+    /// 移譲エッジのうち有効な区間の両端（ホストのOS座標）
+    ///
+    /// `edge.to_remote` の範囲制限を反映する。オーバーレイ表示などに使う
+    pub fn transfer_edge_segment(&self) -> ((f64, f64), (f64, f64)) {
+        let screen = &self.screen;
+        let range = &self.edge.to_remote;
+        let x = match self.host_position {
+            // 左側画面の場合は右端、右側画面の場合は左端
+            HostPosition::Left => screen.width as f64 - 1.0,
+            HostPosition::Right => 0.0,
         };
+        let max_y = screen.height as f64 - 1.0;
+        let start_y = (range.start * screen.height as f64).min(max_y);
+        let end_y = (range.end * screen.height as f64).min(max_y);
+        (screen.to_os(x, start_y), screen.to_os(x, end_y))
+    }
+    /// 起動時にカーソルを置く位置。今の位置のままならNone
+    pub fn initial_cursor(&self) -> Option<(f64, f64)> {
+        match self.initial_position {
+            InitialPosition::Mode(InitialMode::Current) => None,
+            InitialPosition::Mode(InitialMode::Center) => Some(self.host_center()),
+            InitialPosition::Point(point) => Some((point.x, point.y)),
+        }
+    }
+}
 
-        let yaml = serde_yaml::to_string(&template)?;
-        fs::write(path, yaml)?;
-        Ok(())
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn screen(rotation: u16, flip_x: bool, flip_y: bool) -> Screen {
+        Screen {
+            width: 1080,
+            height: 1920,
+            flip_x,
+            flip_y,
+            rotation,
+        }
     }
-    pub fn host_center(&self) -> (f64, f64) {
-        let x = self.screen.width as f64 / 2.0;
-        let y = self.screen.height as f64 / 2.0;
-        return (x, y);
+
+    #[test]
+    fn os_and_physical_round_trip() {
+        for rotation in [0, 90, 180, 270] {
+            for (flip_x, flip_y) in [(false, false), (true, false), (false, true), (true, true)] {
+                let screen = screen(rotation, flip_x, flip_y);
+                let (width, height) = screen.os_size();
+                for (x, y) in [(0.0, 0.0), (width - 1.0, height - 1.0), (12.5, 700.0)] {
+                    let (px, py) = screen.to_physical(x, y);
+                    assert!(
+                        (0.0..screen.width as f64).contains(&px)
+                            && (0.0..screen.height as f64).contains(&py),
+                        "rotation {} flip ({}, {}): ({}, {}) -> ({}, {})",
+                        rotation,
+                        flip_x,
+                        flip_y,
+                        x,
+                        y,
+                        px,
+                        py
+                    );
+                    assert_eq!(screen.to_os(px, py), (x, y));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn rotated_screen_swaps_axes() {
+        let screen = screen(90, false, false);
+        assert_eq!(screen.os_size(), (1920.0, 1080.0));
+        // OS座標で右へ動くと、物理的には下へ動く
+        let (x0, y0) = screen.to_physical(100.0, 100.0);
+        let (x1, y1) = screen.to_physical(110.0, 100.0);
+        assert_eq!((x1 - x0, y1 - y0), (0.0, 10.0));
+    }
+
+    #[test]
+    fn flipped_screen_mirrors_each_axis() {
+        let upside_down = screen(0, false, true);
+        // 上下反転した画面では、OS座標の上端が物理的な下端になる
+        assert_eq!(upside_down.to_physical(10.0, 0.0), (10.0, 1919.0));
+        assert_eq!(upside_down.to_physical(10.0, 1919.0), (10.0, 0.0));
+        let mirrored = screen(0, true, false);
+        assert_eq!(mirrored.to_physical(0.0, 25.0), (1079.0, 25.0));
+        assert_eq!(mirrored.flip(1079.0, 25.0), (0.0, 25.0));
+        // 反転なしなら何もしない
+        assert_eq!(screen(0, false, false).flip(3.0, 4.0), (3.0, 4.0));
+    }
+
+    #[test]
+    fn transfer_edge_segment_follows_layout_and_range() {
+        let mut config = Config::template();
+        config.screen = screen(0, false, false);
+        config.edge.to_remote = EdgeRange {
+            start: 0.25,
+            end: 0.5,
+        };
+        config.host_position = HostPosition::Left;
+        assert_eq!(
+            config.transfer_edge_segment(),
+            ((1079.0, 480.0), (1079.0, 960.0))
+        );
+        config.host_position = HostPosition::Right;
+        assert_eq!(config.transfer_edge_segment(), ((0.0, 480.0), (0.0, 960.0)));
+        // 上下反転した画面では区間もOS座標で反転する
+        config.screen = screen(0, false, true);
+        assert_eq!(
+            config.transfer_edge_segment(),
+            ((0.0, 1439.0), (0.0, 959.0))
+        );
+    }
+
+    #[test]
+    fn template_round_trips_through_yaml() {
+        let yaml = serde_yaml::to_string(&Config::template()).unwrap();
+        let config: Config = serde_yaml::from_str(&yaml).unwrap();
+        config.validate().unwrap();
+        assert_eq!(config.screen.width, 2600);
+        assert_eq!(config.entry_margin_px, default_entry_margin_px());
+        assert_eq!(config.poll_interval_ms, 5);
+        assert_eq!(config.secondary_move_threshold, 2.0);
+    }
+
+    #[test]
+    fn negative_capture_thresholds_are_rejected() {
+        let mut config = Config::template();
+        config.edge_margin = -1.0;
+        assert!(config.validate().is_err());
+        config.edge_margin = default_edge_margin();
+        config.move_threshold = f64::NAN;
+        assert!(config.validate().is_err());
     }
 }
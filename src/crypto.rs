@@ -0,0 +1,213 @@
+use anyhow::Result;
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use sha2::{Digest, Sha256};
+use std::collections::{HashSet, VecDeque};
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// nonceの長さ
+const NONCE_LEN: usize = 12;
+
+/// 平文の先頭に入れる送信時刻（UNIXミリ秒, u64 LE）の長さ
+const TIMESTAMP_LEN: usize = 8;
+
+/// 送信時刻とのずれをこれ以上許さない（両マシンの時計のずれも含む）
+const MAX_AGE: Duration = Duration::from_secs(30);
+
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+/// 共有鍵（psk）でパケットを暗号化・認証する
+///
+/// 形式は nonce(12) || 暗号文。平文の先頭に送信時刻を入れ、古いパケットを弾く
+#[derive(Clone)]
+pub struct Cipher {
+    cipher: ChaCha20Poly1305,
+}
+
+/// 復号したパケット
+pub struct Opened {
+    pub nonce: [u8; NONCE_LEN],
+    /// 送信時刻（UNIXミリ秒）
+    pub sent_at: u64,
+    pub payload: Vec<u8>,
+}
+
+impl Cipher {
+    pub fn new(psk: &str) -> Self {
+        // 用途の文字列を混ぜ、同じpskを別の用途に使っても鍵が一致しないようにする
+        let key = Sha256::new()
+            .chain_update(b"sharemouse-psk-v1")
+            .chain_update(psk.as_bytes())
+            .finalize();
+        Self {
+            cipher: ChaCha20Poly1305::new(Key::from_slice(&key)),
+        }
+    }
+
+    /// ファイルからpskを読む（末尾の改行は除く）
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let psk = std::fs::read_to_string(path)
+            .map_err(|e| anyhow::anyhow!("Failed to read psk from {:?}: {}", path, e))?;
+        let psk = psk.trim_end_matches(['\r', '\n']);
+        if psk.is_empty() {
+            anyhow::bail!("psk file {:?} is empty", path);
+        }
+        Ok(Self::new(psk))
+    }
+
+    /// 暗号化する。nonceは毎回ランダムに作る
+    pub fn seal(&self, payload: &[u8]) -> Result<Vec<u8>> {
+        let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let mut plaintext = Vec::with_capacity(TIMESTAMP_LEN + payload.len());
+        plaintext.extend_from_slice(&now_millis().to_le_bytes());
+        plaintext.extend_from_slice(payload);
+        let ciphertext = self
+            .cipher
+            .encrypt(&nonce, plaintext.as_slice())
+            .map_err(|_| anyhow::anyhow!("Failed to encrypt packet"))?;
+        let mut sealed = nonce.to_vec();
+        sealed.extend(ciphertext);
+        Ok(sealed)
+    }
+
+    /// 復号して認証する。鍵が違う、改ざんされている、古すぎるパケットは`Err`
+    pub fn open(&self, data: &[u8]) -> Result<Opened> {
+        if data.len() < NONCE_LEN {
+            anyhow::bail!("packet is too short to be encrypted");
+        }
+        let (nonce, ciphertext) = data.split_at(NONCE_LEN);
+        let plaintext = self
+            .cipher
+            .decrypt(Nonce::from_slice(nonce), ciphertext)
+            .map_err(|_| anyhow::anyhow!("authentication failed (wrong psk?)"))?;
+        if plaintext.len() < TIMESTAMP_LEN {
+            anyhow::bail!("packet has no timestamp");
+        }
+        let (timestamp, payload) = plaintext.split_at(TIMESTAMP_LEN);
+        let sent_at = u64::from_le_bytes(timestamp.try_into()?);
+        if now_millis().abs_diff(sent_at) > MAX_AGE.as_millis() as u64 {
+            anyhow::bail!(
+                "packet is older than {:?} or the clocks of the two machines differ",
+                MAX_AGE
+            );
+        }
+        Ok(Opened {
+            nonce: nonce.try_into()?,
+            sent_at,
+            payload: payload.to_vec(),
+        })
+    }
+}
+
+/// 受信したnonceを覚えておき、同じパケットの再送（リプレイ）を弾く
+///
+/// `MAX_AGE`より古いパケットは`Cipher::open`で弾かれるので、その間だけ覚えておけばよい
+#[derive(Default)]
+pub struct ReplayGuard {
+    seen: HashSet<[u8; NONCE_LEN]>,
+    /// 送信時刻の順（おおむね）に並んだnonce
+    order: VecDeque<(u64, [u8; NONCE_LEN])>,
+}
+
+impl ReplayGuard {
+    /// 初めて見るパケットなら`true`
+    pub fn accept(&mut self, opened: &Opened) -> bool {
+        let expired = now_millis().saturating_sub(MAX_AGE.as_millis() as u64);
+        while let Some(&(sent_at, nonce)) = self.order.front() {
+            if sent_at >= expired {
+                break;
+            }
+            self.seen.remove(&nonce);
+            self.order.pop_front();
+        }
+        if !self.seen.insert(opened.nonce) {
+            return false;
+        }
+        self.order.push_back((opened.sent_at, opened.nonce));
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 送信時刻を指定して暗号化する
+    fn seal_at(cipher: &Cipher, sent_at: u64, payload: &[u8]) -> Vec<u8> {
+        let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let mut plaintext = sent_at.to_le_bytes().to_vec();
+        plaintext.extend_from_slice(payload);
+        let mut sealed = nonce.to_vec();
+        sealed.extend(cipher.cipher.encrypt(&nonce, plaintext.as_slice()).unwrap());
+        sealed
+    }
+
+    #[test]
+    fn sealed_packets_open_only_with_the_same_psk() {
+        let cipher = Cipher::new("secret");
+        let sealed = cipher.seal(b"hello").unwrap();
+        let opened = cipher.open(&sealed).unwrap();
+        assert_eq!(opened.payload, b"hello");
+        assert_eq!(opened.nonce, sealed[..NONCE_LEN]);
+        // nonceは毎回変わる
+        assert_ne!(cipher.seal(b"hello").unwrap(), sealed);
+        assert!(Cipher::new("other").open(&sealed).is_err());
+        let mut tampered = sealed.clone();
+        *tampered.last_mut().unwrap() ^= 1;
+        assert!(cipher.open(&tampered).is_err());
+        assert!(cipher.open(&sealed[..NONCE_LEN - 1]).is_err());
+    }
+
+    #[test]
+    fn stale_packets_are_rejected() {
+        let cipher = Cipher::new("secret");
+        let now = now_millis();
+        let max_age = MAX_AGE.as_millis() as u64;
+        assert!(cipher
+            .open(&seal_at(&cipher, now - max_age / 2, b""))
+            .is_ok());
+        assert!(cipher
+            .open(&seal_at(&cipher, now - max_age - 1000, b""))
+            .is_err());
+        // 相手の時計が進みすぎている場合も
+        assert!(cipher
+            .open(&seal_at(&cipher, now + max_age + 1000, b""))
+            .is_err());
+    }
+
+    #[test]
+    fn replay_guard_rejects_repeated_nonces() {
+        let cipher = Cipher::new("secret");
+        let mut guard = ReplayGuard::default();
+        let sealed = cipher.seal(b"click").unwrap();
+        assert!(guard.accept(&cipher.open(&sealed).unwrap()));
+        assert!(!guard.accept(&cipher.open(&sealed).unwrap()));
+        assert!(guard.accept(&cipher.open(&cipher.seal(b"click").unwrap()).unwrap()));
+    }
+
+    #[test]
+    fn replay_guard_forgets_expired_nonces() {
+        let mut guard = ReplayGuard::default();
+        let old = Opened {
+            nonce: [1; NONCE_LEN],
+            sent_at: now_millis() - MAX_AGE.as_millis() as u64 - 1000,
+            payload: Vec::new(),
+        };
+        assert!(guard.accept(&old));
+        let fresh = Opened {
+            nonce: [2; NONCE_LEN],
+            sent_at: now_millis(),
+            payload: Vec::new(),
+        };
+        assert!(guard.accept(&fresh));
+        // 古いnonceは次の受信時に捨てられている（Cipher::openが先に弾くので問題ない）
+        assert_eq!(guard.seen.len(), 1);
+        assert!(!guard.accept(&fresh));
+    }
+}
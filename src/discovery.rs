@@ -0,0 +1,103 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::net::{Ipv4Addr, SocketAddr};
+use std::time::{Duration, Instant};
+
+/// ビーコンをやり取りするUDPポート
+pub const DISCOVERY_PORT: u16 = 47800;
+
+/// `remote_ip`にこの値を書くと、ビーコンでreceiverを探す
+pub const DISCOVER: &str = "discover";
+
+/// ビーコンを送る間隔
+const ANNOUNCE_INTERVAL: Duration = Duration::from_secs(2);
+
+/// receiverを探すのを諦めるまでの時間（ビーコンを1回は受け取れる長さ）
+const DISCOVER_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// 他のアプリのブロードキャストと区別するための印
+const SERVICE: &str = "sharemouse";
+
+/// receiverがブロードキャストする自己紹介
+#[derive(Debug, Serialize, Deserialize)]
+struct Beacon {
+    service: String,
+    name: String,
+    port: u16,
+}
+
+/// 名前と待ち受けポートをLANにブロードキャストし続ける
+pub async fn announce(name: String, port: u16) -> Result<()> {
+    let socket = tokio::net::UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).await?;
+    socket.set_broadcast(true)?;
+    let beacon = serde_json::to_vec(&Beacon {
+        service: SERVICE.to_string(),
+        name: name.clone(),
+        port,
+    })?;
+    log::info!(
+        "Announcing '{}' (port {}) on UDP port {}",
+        name,
+        port,
+        DISCOVERY_PORT
+    );
+    let mut interval = tokio::time::interval(ANNOUNCE_INTERVAL);
+    loop {
+        interval.tick().await;
+        if let Err(e) = socket
+            .send_to(&beacon, (Ipv4Addr::BROADCAST, DISCOVERY_PORT))
+            .await
+        {
+            log::debug!("Failed to send a discovery beacon: {}", e);
+        }
+    }
+}
+
+/// ビーコンを待ってreceiverのアドレスを探す。`name`があればその名前のものだけを選ぶ
+pub fn discover(name: Option<&str>) -> Result<SocketAddr> {
+    let socket =
+        std::net::UdpSocket::bind((Ipv4Addr::UNSPECIFIED, DISCOVERY_PORT)).map_err(|e| {
+            anyhow::anyhow!(
+                "Failed to listen for receivers on UDP port {}: {}",
+                DISCOVERY_PORT,
+                e
+            )
+        })?;
+    match name {
+        Some(name) => log::info!("Looking for receiver '{}' on the LAN...", name),
+        None => log::info!("Looking for a receiver on the LAN..."),
+    }
+    let deadline = Instant::now() + DISCOVER_TIMEOUT;
+    let mut buf = [0u8; 1024];
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            anyhow::bail!(
+                "No receiver announced itself within {:?}: run `sharemouse receive --announce <NAME>` there or set remote_ip to its address",
+                DISCOVER_TIMEOUT
+            );
+        }
+        socket.set_read_timeout(Some(remaining))?;
+        let (len, addr) = match socket.recv_from(&mut buf) {
+            Ok(received) => received,
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut
+                ) =>
+            {
+                continue;
+            }
+            Err(e) => return Err(e.into()),
+        };
+        let Ok(beacon) = serde_json::from_slice::<Beacon>(&buf[..len]) else {
+            continue;
+        };
+        if beacon.service != SERVICE || name.is_some_and(|name| name != beacon.name) {
+            continue;
+        }
+        let found = SocketAddr::new(addr.ip(), beacon.port);
+        log::info!("Discovered receiver '{}' at {}", beacon.name, found);
+        return Ok(found);
+    }
+}
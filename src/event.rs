@@ -1,13 +1,271 @@
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum MouseEvent {
-    Move { x: f64, y: f64 },
+    Move {
+        x: f64,
+        y: f64,
+    },
     LeftClick,
     RightClick,
     MiddleClick,
     LeftRelease,
     RightRelease,
     MiddleRelease,
-    Scroll { delta_x: i64, delta_y: i64 },
+    Scroll {
+        x: f64,
+        y: f64,
+        /// 横スクロール量（正で右。単位は`SCROLL_UNITS_PER_NOTCH`）
+        delta_x: i64,
+        /// 縦スクロール量（正で上。単位は`SCROLL_UNITS_PER_NOTCH`）
+        delta_y: i64,
+    },
+    /// 制御権がリモートに移った
+    ControlEnter,
+    /// 制御権がホストに戻った
+    ControlLeave,
+    /// 送信側が押下中と認識しているボタン（`BUTTON_*` のビットマスク）
+    ///
+    /// 定期的に送られ、受信側はこれに合わせて押下状態を同期する
+    Buttons {
+        held: u8,
+    },
+    /// ホスト側のカーソルが移譲エッジに近づいた。座標はリモートに入ったときの位置
+    ///
+    /// 受信側はゴーストカーソルの表示などに使える
+    EdgeApproach {
+        x: f64,
+        y: f64,
+    },
+    /// 移譲せずにエッジから離れた
+    EdgeRetreat,
+    /// キーの押下・解放
+    ///
+    /// codeはLinuxのevdevキーコード（input-event-codes.h）。macOSとの変換は`keymap`で行う
+    Key {
+        code: u16,
+        pressed: bool,
+    },
+    /// 送信側のロックキー（`LOCK_*` のビットマスク）の状態
+    ///
+    /// ControlEnterの直後に送られ、受信側はknownのうち食い違うものを切り替えて揃える
+    Locks {
+        /// 送信側で状態を読めたロックキー
+        known: u8,
+        /// knownのうちオンのもの
+        on: u8,
+    },
+    /// このバージョンでは未対応のイベント
+    ///
+    /// 新しい送信側が追加したイベントを`Move`などと誤解釈しないために、
+    /// 未知のvariantは全てここにデシリアライズされる。必ず最後に置くこと
+    ///
+    /// JSONでフィールドを持つ未知のvariantはここに読めないので、受信側（`network`）で置き換える
+    #[serde(other)]
+    Unknown,
+}
+
+/// ホイール1ノッチのスクロール量（evdevのREL_WHEEL_HI_RESと同じ単位）
+///
+/// トラックパッドの細かいスクロールもノッチ単位に丸めずに送るため
+pub const SCROLL_UNITS_PER_NOTCH: i64 = 120;
+
+/// macOSのピクセル単位のスクロール1pxに相当する量（1ノッチをおよそ10pxとする）
+#[cfg(target_os = "macos")]
+pub const SCROLL_UNITS_PER_MAC_PIXEL: i64 = SCROLL_UNITS_PER_NOTCH / 10;
+
+/// ボタンのビット（押下中のボタンをビットマスクで管理する）
+pub const BUTTON_LEFT: u8 = 1 << 0;
+pub const BUTTON_RIGHT: u8 = 1 << 1;
+pub const BUTTON_MIDDLE: u8 = 1 << 2;
+
+/// ロックキーのビット
+pub const LOCK_CAPS: u8 = 1 << 0;
+pub const LOCK_NUM: u8 = 1 << 1;
+pub const LOCK_SCROLL: u8 = 1 << 2;
+
+impl MouseEvent {
+    /// ボタンイベントなら (ボタンのビット, 押下かどうか) を返す
+    pub fn button(&self) -> Option<(u8, bool)> {
+        match self {
+            MouseEvent::LeftClick => Some((BUTTON_LEFT, true)),
+            MouseEvent::RightClick => Some((BUTTON_RIGHT, true)),
+            MouseEvent::MiddleClick => Some((BUTTON_MIDDLE, true)),
+            MouseEvent::LeftRelease => Some((BUTTON_LEFT, false)),
+            MouseEvent::RightRelease => Some((BUTTON_RIGHT, false)),
+            MouseEvent::MiddleRelease => Some((BUTTON_MIDDLE, false)),
+            _ => None,
+        }
+    }
+
+    /// 押下中のボタンやキー、制御を手放すだけのイベントか
+    ///
+    /// 注入を止めている間もこれらを通せば、押しっぱなしや制御中のまま残らない
+    pub fn is_release(&self) -> bool {
+        matches!(
+            self,
+            MouseEvent::LeftRelease
+                | MouseEvent::RightRelease
+                | MouseEvent::MiddleRelease
+                | MouseEvent::ControlLeave
+                | MouseEvent::Key { pressed: false, .. }
+        )
+    }
+
+    /// ボタンのビットと押下状態からボタンイベントを作る
+    pub fn from_button(button: u8, pressed: bool) -> Option<MouseEvent> {
+        match (button, pressed) {
+            (BUTTON_LEFT, true) => Some(MouseEvent::LeftClick),
+            (BUTTON_RIGHT, true) => Some(MouseEvent::RightClick),
+            (BUTTON_MIDDLE, true) => Some(MouseEvent::MiddleClick),
+            (BUTTON_LEFT, false) => Some(MouseEvent::LeftRelease),
+            (BUTTON_RIGHT, false) => Some(MouseEvent::RightRelease),
+            (BUTTON_MIDDLE, false) => Some(MouseEvent::MiddleRelease),
+            _ => None,
+        }
+    }
+
+    /// 位置を持つイベントの座標を変換する
+    pub fn map_position(self, mut f: impl FnMut(f64, f64) -> (f64, f64)) -> MouseEvent {
+        match self {
+            MouseEvent::Move { x, y } => {
+                let (x, y) = f(x, y);
+                MouseEvent::Move { x, y }
+            }
+            MouseEvent::Scroll {
+                x,
+                y,
+                delta_x,
+                delta_y,
+            } => {
+                let (x, y) = f(x, y);
+                MouseEvent::Scroll {
+                    x,
+                    y,
+                    delta_x,
+                    delta_y,
+                }
+            }
+            MouseEvent::EdgeApproach { x, y } => {
+                let (x, y) = f(x, y);
+                MouseEvent::EdgeApproach { x, y }
+            }
+            event => event,
+        }
+    }
+
+    /// 位置をwidth x heightの画面に対する割合にする
+    ///
+    /// 端の画素が0と1になるので、解像度の違う画面でも端は端に写る
+    pub fn normalize(self, width: f64, height: f64) -> MouseEvent {
+        let (w, h) = ((width - 1.0).max(1.0), (height - 1.0).max(1.0));
+        self.map_position(|x, y| (x / w, y / h))
+    }
+
+    /// `normalize`した位置をwidth x heightの画面の座標に戻す
+    pub fn denormalize(self, width: f64, height: f64) -> MouseEvent {
+        let (w, h) = ((width - 1.0).max(1.0), (height - 1.0).max(1.0));
+        self.map_position(|x, y| (x * w, y * h))
+    }
+
+    /// イベントの種別名
+    pub fn kind(&self) -> &'static str {
+        match self {
+            MouseEvent::Move { .. } => "Move",
+            MouseEvent::LeftClick => "LeftClick",
+            MouseEvent::RightClick => "RightClick",
+            MouseEvent::MiddleClick => "MiddleClick",
+            MouseEvent::LeftRelease => "LeftRelease",
+            MouseEvent::RightRelease => "RightRelease",
+            MouseEvent::MiddleRelease => "MiddleRelease",
+            MouseEvent::Scroll { .. } => "Scroll",
+            MouseEvent::ControlEnter => "ControlEnter",
+            MouseEvent::ControlLeave => "ControlLeave",
+            MouseEvent::Buttons { .. } => "Buttons",
+            MouseEvent::EdgeApproach { .. } => "EdgeApproach",
+            MouseEvent::EdgeRetreat => "EdgeRetreat",
+            MouseEvent::Key { .. } => "Key",
+            MouseEvent::Locks { .. } => "Locks",
+            MouseEvent::Unknown => "Unknown",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn position(event: MouseEvent) -> (f64, f64) {
+        match event {
+            MouseEvent::Move { x, y } => (x, y),
+            event => panic!("not a move: {:?}", event),
+        }
+    }
+
+    #[test]
+    fn unknown_variants_are_not_mistaken_for_known_ones() {
+        for json in ["\"Teleport\"", "{\"Teleport\":null}"] {
+            assert_eq!(
+                serde_json::from_str::<MouseEvent>(json).unwrap(),
+                MouseEvent::Unknown,
+                "{}",
+                json
+            );
+        }
+        // bincodeではvariant番号が範囲外なら未知とみなす（後ろのフィールドは読まない）
+        let mut data = 99u32.to_le_bytes().to_vec();
+        data.extend(1.5f64.to_le_bytes());
+        assert_eq!(
+            bincode::deserialize::<MouseEvent>(&data).unwrap(),
+            MouseEvent::Unknown
+        );
+        // 既知のvariantはそのまま
+        let key = MouseEvent::Key {
+            code: 30,
+            pressed: true,
+        };
+        let json = serde_json::to_string(&key).unwrap();
+        assert_eq!(serde_json::from_str::<MouseEvent>(&json).unwrap(), key);
+    }
+
+    #[test]
+    fn edge_pixels_survive_a_resolution_change() {
+        let corners = [(0.0, 0.0), (1919.0, 1079.0), (0.0, 1079.0), (1919.0, 0.0)];
+        for (x, y) in corners {
+            let wire = MouseEvent::Move { x, y }.normalize(1920.0, 1080.0);
+            let (x2, y2) = position(wire.clone().denormalize(3840.0, 2160.0));
+            assert_eq!((x2, y2), (x / 1919.0 * 3839.0, y / 1079.0 * 2159.0));
+            assert!(x2 == 0.0 || x2 == 3839.0);
+            assert!(y2 == 0.0 || y2 == 2159.0);
+            let (x3, y3) = position(wire.denormalize(1280.0, 720.0));
+            assert!(x3 == 0.0 || x3 == 1279.0);
+            assert!(y3 == 0.0 || y3 == 719.0);
+        }
+    }
+
+    #[test]
+    fn normalize_round_trips_on_the_same_screen() {
+        let event = MouseEvent::Scroll {
+            x: 960.0,
+            y: 333.0,
+            delta_x: 0,
+            delta_y: -120,
+        };
+        let back = event
+            .clone()
+            .normalize(1920.0, 1080.0)
+            .denormalize(1920.0, 1080.0);
+        match back {
+            MouseEvent::Scroll { x, y, delta_y, .. } => {
+                assert!((x - 960.0).abs() < 1e-9 && (y - 333.0).abs() < 1e-9);
+                assert_eq!(delta_y, -120);
+            }
+            event => panic!("not a scroll: {:?}", event),
+        }
+        // 位置を持たないイベントはそのまま
+        assert_eq!(
+            MouseEvent::LeftClick.normalize(1920.0, 1080.0),
+            MouseEvent::LeftClick
+        );
+    }
 }
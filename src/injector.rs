@@ -1,34 +1,441 @@
-use crate::event::MouseEvent;
+use crate::clock::{Clock, SystemClock};
+use crate::event::{MouseEvent, BUTTON_LEFT, BUTTON_MIDDLE, BUTTON_RIGHT};
+use crate::lock_keys::LOCK_KEYS;
 use anyhow::Result;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
 
 pub trait MouseInjector {
     fn inject_event(&mut self, event: MouseEvent) -> Result<()>;
+    /// 実際にOSへ注入するバックエンドの名前。注入しないならNone
+    fn backend(&self) -> Option<&'static str>;
+}
+
+/// OSのカーソルを動かさず、受け取ったイベントをログに出すだけのインジェクタ
+pub struct LoggingInjector;
+
+impl MouseInjector for LoggingInjector {
+    fn inject_event(&mut self, event: MouseEvent) -> Result<()> {
+        log::info!("Received event: {:?}", event);
+        Ok(())
+    }
+
+    fn backend(&self) -> Option<&'static str> {
+        None
+    }
+}
+
+/// インジェクタの動作設定
+#[derive(Debug, Clone, Default)]
+pub struct InjectorOptions {
+    /// 連続するボタンイベント間の最小間隔
+    pub click_interval: Duration,
+    /// 注入に失敗したときのリトライ回数
+    pub max_retries: u32,
+    /// 注入したイベントの記録先
+    pub record: Option<PathBuf>,
+    /// 記録を圧縮するか
+    pub record_compress: bool,
+    /// 受信した座標を画面内に収めるか
+    pub clamp_to_screen: bool,
+    /// 画面サイズの指定（Noneなら自動で取得する）
+    pub screen_size: Option<(u32, u32)>,
+    /// OSへ注入せず、受信したイベントをログに出すだけにする
+    pub dry_run: bool,
+    /// 受信したMoveの間をこの時間かけて補間する。ゼロで無効
+    pub interpolate: Duration,
+}
+
+/// 連続するボタンイベントの間に最低限の間隔を空ける
+///
+/// 速いダブルクリックを連続で注入すると取りこぼすアプリがあるため
+pub struct ButtonPacer {
+    min_interval: Duration,
+    last_button: Option<Instant>,
+    clock: Box<dyn Clock>,
+}
+
+impl ButtonPacer {
+    pub fn new(min_interval: Duration) -> Self {
+        Self::with_clock(min_interval, Box::new(SystemClock))
+    }
+
+    pub fn with_clock(min_interval: Duration, clock: Box<dyn Clock>) -> Self {
+        Self {
+            min_interval,
+            last_button: None,
+            clock,
+        }
+    }
+
+    /// ボタンイベントの注入前に呼ぶ。前回から間隔が足りなければ待つ
+    pub fn wait(&mut self) {
+        if let Some(last) = self.last_button {
+            let elapsed = self.clock.now().saturating_duration_since(last);
+            if elapsed < self.min_interval {
+                // 受信ループは注入中にワーカースレッドを明け渡すので、ここで待っても受信は止まらない
+                std::thread::sleep(self.min_interval - elapsed);
+            }
+        }
+        self.last_button = Some(self.clock.now());
+    }
+}
+
+/// `BUTTON_*` と各OSのボタンの対応表
+///
+/// | ビット          | macOS (CGMouseButton / 押下 / リリース)       | Linux (ydotool) |
+/// |----------------|----------------------------------------------|-----------------|
+/// | `BUTTON_LEFT`   | `Left` / `LeftMouseDown` / `LeftMouseUp`     | 1               |
+/// | `BUTTON_RIGHT`  | `Right` / `RightMouseDown` / `RightMouseUp`  | 3               |
+/// | `BUTTON_MIDDLE` | `Center` / `OtherMouseDown` / `OtherMouseUp` | 2               |
+///
+/// Linuxの2と3はX11のボタン番号（2が中、3が右）で、macOSの並び（右が1、中が2）とは異なる。
+/// 対応は各プラットフォームの `native_button` にだけ書くこと
+pub const BUTTON_TABLE: [u8; 3] = [BUTTON_LEFT, BUTTON_RIGHT, BUTTON_MIDDLE];
+
+/// 細かいスクロール量を注入先の単位（ノッチやピクセル）に変換する。端数は次回に繰り越す
+#[derive(Default)]
+pub struct ScrollAccumulator {
+    x: i64,
+    y: i64,
+}
+
+impl ScrollAccumulator {
+    /// `units_per_step`ごとに1ステップとして、(横, 縦)のステップ数を返す
+    pub fn steps(&mut self, delta_x: i64, delta_y: i64, units_per_step: i64) -> (i64, i64) {
+        self.x += delta_x;
+        self.y += delta_y;
+        let steps = (self.x / units_per_step, self.y / units_per_step);
+        self.x %= units_per_step;
+        self.y %= units_per_step;
+        steps
+    }
+}
+
+fn is_button_event(event: &MouseEvent) -> bool {
+    event.button().is_some()
+}
+
+/// 注入したボタンの押下状態を追跡し、`MouseEvent::Buttons` を受け取ったら差分を注入して揃える
+///
+/// 押下・リリースが取りこぼされても、送信側が押下中と認識しているボタンの組み合わせに戻る。
+/// 制御が戻るとき（ControlLeave）は押下中のボタンを全て離す
+pub struct ButtonSyncInjector {
+    inner: Box<dyn MouseInjector>,
+    held: u8,
+}
+
+impl ButtonSyncInjector {
+    pub fn new(inner: Box<dyn MouseInjector>) -> Self {
+        Self { inner, held: 0 }
+    }
+}
+
+impl MouseInjector for ButtonSyncInjector {
+    fn inject_event(&mut self, event: MouseEvent) -> Result<()> {
+        if let MouseEvent::Buttons { held } = event {
+            let changed = self.held ^ held;
+            for bit in BUTTON_TABLE {
+                if changed & bit == 0 {
+                    continue;
+                }
+                let pressed = held & bit != 0;
+                log::warn!(
+                    "Button state out of sync, {} button {:#x}",
+                    if pressed { "pressing" } else { "releasing" },
+                    bit
+                );
+                if let Some(event) = MouseEvent::from_button(bit, pressed) {
+                    self.inject_event(event)?;
+                }
+            }
+            return Ok(());
+        }
+        if matches!(event, MouseEvent::ControlLeave) {
+            // リリースを取りこぼしたまま制御が戻ると、ボタンが押しっぱなしで残る
+            self.inject_event(MouseEvent::Buttons { held: 0 })?;
+        }
+        if let Some((button, pressed)) = event.button() {
+            if pressed {
+                self.held |= button;
+            } else {
+                self.held &= !button;
+            }
+        }
+        self.inner.inject_event(event)
+    }
+
+    fn backend(&self) -> Option<&'static str> {
+        self.inner.backend()
+    }
+}
+
+/// `MouseEvent::Locks` を受け取ったら、このマシンのロックキーを送信側に合わせる
+///
+/// 両方で状態を読めたロックキーのうち、食い違うものだけを押して離して切り替える。
+/// 起動時から状態が違っていても、最初の移譲で揃う
+pub struct LockSyncInjector {
+    inner: Box<dyn MouseInjector>,
+    read: fn() -> MouseEvent,
+}
+
+impl LockSyncInjector {
+    pub fn new(inner: Box<dyn MouseInjector>) -> Self {
+        Self::with_reader(inner, crate::lock_keys::read)
+    }
+
+    /// ロックキーの状態の読み方を差し替える（テスト用）
+    pub fn with_reader(inner: Box<dyn MouseInjector>, read: fn() -> MouseEvent) -> Self {
+        Self { inner, read }
+    }
+}
+
+impl MouseInjector for LockSyncInjector {
+    fn inject_event(&mut self, event: MouseEvent) -> Result<()> {
+        let MouseEvent::Locks { known, on } = event else {
+            return self.inner.inject_event(event);
+        };
+        let MouseEvent::Locks {
+            known: local_known,
+            on: local_on,
+        } = (self.read)()
+        else {
+            return Ok(());
+        };
+        let changed = (on ^ local_on) & known & local_known;
+        for (bit, code) in LOCK_KEYS {
+            if changed & bit == 0 {
+                continue;
+            }
+            log::info!(
+                "Lock key {} differs from the sender, turning it {}",
+                code,
+                if on & bit != 0 { "on" } else { "off" }
+            );
+            for pressed in [true, false] {
+                self.inner.inject_event(MouseEvent::Key { code, pressed })?;
+            }
+        }
+        Ok(())
+    }
+
+    fn backend(&self) -> Option<&'static str> {
+        self.inner.backend()
+    }
+}
+
+/// 絶対座標を受信側の画面内に収めてから注入する
+///
+/// 送信側の画面サイズ設定が間違っていても、カーソルが画面外に行方不明にならないようにする
+pub struct ClampInjector {
+    inner: Box<dyn MouseInjector>,
+    width: f64,
+    height: f64,
+    clamping: bool,
+}
+
+impl ClampInjector {
+    pub fn new(inner: Box<dyn MouseInjector>, (width, height): (u32, u32)) -> Self {
+        Self {
+            inner,
+            width: width as f64,
+            height: height as f64,
+            clamping: false,
+        }
+    }
+
+    fn clamp(&mut self, x: f64, y: f64) -> (f64, f64) {
+        let clamped = (
+            x.max(0.0).min(self.width - 1.0),
+            y.max(0.0).min(self.height - 1.0),
+        );
+        let clamping = clamped != (x, y);
+        if clamping && !self.clamping {
+            log::warn!(
+                "Clamping ({:.1}, {:.1}) to the {}x{} screen: check remote_screen on the sender",
+                x,
+                y,
+                self.width,
+                self.height
+            );
+        }
+        self.clamping = clamping;
+        clamped
+    }
+}
+
+impl MouseInjector for ClampInjector {
+    fn inject_event(&mut self, event: MouseEvent) -> Result<()> {
+        let event = match event {
+            MouseEvent::Move { .. } | MouseEvent::Scroll { .. } => {
+                event.map_position(|x, y| self.clamp(x, y))
+            }
+            event => event,
+        };
+        self.inner.inject_event(event)
+    }
+
+    fn backend(&self) -> Option<&'static str> {
+        self.inner.backend()
+    }
+}
+
+/// 受信したMoveの間をなめらかにつなぐ
+///
+/// 飛び飛びに届く位置へそのままジャンプすると、回線が揺らいだときにカーソルがカクつくため
+pub struct Interpolator {
+    duration: Duration,
+    /// 最後に注入した位置
+    current: Option<(f64, f64)>,
+    segment: Option<Segment>,
+}
+
+/// 補間中の区間
+#[derive(Clone, Copy)]
+struct Segment {
+    from: (f64, f64),
+    to: (f64, f64),
+    started: Instant,
+}
+
+impl Interpolator {
+    /// `duration`がゼロなら補間せず、常にすぐ目標位置を返す
+    pub fn new(duration: Duration) -> Self {
+        Self {
+            duration,
+            current: None,
+            segment: None,
+        }
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.segment.is_some()
+    }
+
+    /// 新しい目標位置を受け取る。すぐに注入すべき位置があれば返す
+    pub fn target(&mut self, x: f64, y: f64) -> Option<(f64, f64)> {
+        match self.current {
+            Some(from) if !self.duration.is_zero() => {
+                // 補間の途中なら、今いる位置から新しい目標へ向かい直す
+                self.segment = Some(Segment {
+                    from,
+                    to: (x, y),
+                    started: Instant::now(),
+                });
+                None
+            }
+            _ => {
+                self.current = Some((x, y));
+                Some((x, y))
+            }
+        }
+    }
+
+    /// 補間中なら、今注入する位置
+    pub fn frame(&mut self) -> Option<(f64, f64)> {
+        let Segment { from, to, started } = self.segment?;
+        let t = (started.elapsed().as_secs_f64() / self.duration.as_secs_f64()).min(1.0);
+        if t >= 1.0 {
+            self.segment = None;
+        }
+        let position = (from.0 + (to.0 - from.0) * t, from.1 + (to.1 - from.1) * t);
+        self.current = Some(position);
+        Some(position)
+    }
+
+    /// 補間を打ち切り、途中だったら終点を返す（クリックを目標位置で注入するため）
+    pub fn finish(&mut self) -> Option<(f64, f64)> {
+        let to = self.segment.take()?.to;
+        self.current = Some(to);
+        Some(to)
+    }
+
+    /// 制御が切り替わり、前の位置から続かなくなったときに呼ぶ
+    pub fn reset(&mut self) {
+        self.current = None;
+        self.segment = None;
+    }
+}
+
+/// 注入先の画面サイズ（OS座標）
+#[cfg(target_os = "macos")]
+pub fn screen_size() -> Option<(u32, u32)> {
+    let bounds = core_graphics::display::CGDisplay::main().bounds();
+    Some((bounds.size.width as u32, bounds.size.height as u32))
+}
+
+/// 注入先の画面サイズ。xrandrで取得できなければNone
+#[cfg(target_os = "linux")]
+pub fn screen_size() -> Option<(u32, u32)> {
+    let output = std::process::Command::new("xrandr")
+        .arg("--current")
+        .output()
+        .ok()?;
+    // 例: "Screen 0: minimum 320 x 200, current 1920 x 1080, maximum 16384 x 16384"
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let current = stdout.split("current ").nth(1)?;
+    let mut parts = current.split([',', 'x']).map(str::trim);
+    let width = parts.next()?.parse().ok()?;
+    let height = parts.next()?.parse().ok()?;
+    Some((width, height))
 }
 
 #[cfg(target_os = "macos")]
 pub mod macos {
     use super::*;
-    use crate::event::MouseEvent;
+    use crate::event::{MouseEvent, SCROLL_UNITS_PER_MAC_PIXEL};
     use core_graphics::event::{
-        CGEvent, CGEventTapLocation, CGEventType, CGMouseButton, EventField,
+        CGEvent, CGEventTapLocation, CGEventType, CGMouseButton, ScrollEventUnit,
     };
     use core_graphics::event_source::{CGEventSource, CGEventSourceStateID};
     use core_graphics::geometry::CGPoint;
 
+    /// `BUTTON_*` に対応するCGEventの種類とボタン（対応表は [`super::BUTTON_TABLE`]）
+    fn native_button(bit: u8, pressed: bool) -> Option<(CGEventType, CGMouseButton)> {
+        match (bit, pressed) {
+            (BUTTON_LEFT, true) => Some((CGEventType::LeftMouseDown, CGMouseButton::Left)),
+            (BUTTON_LEFT, false) => Some((CGEventType::LeftMouseUp, CGMouseButton::Left)),
+            (BUTTON_RIGHT, true) => Some((CGEventType::RightMouseDown, CGMouseButton::Right)),
+            (BUTTON_RIGHT, false) => Some((CGEventType::RightMouseUp, CGMouseButton::Right)),
+            (BUTTON_MIDDLE, true) => Some((CGEventType::OtherMouseDown, CGMouseButton::Center)),
+            (BUTTON_MIDDLE, false) => Some((CGEventType::OtherMouseUp, CGMouseButton::Center)),
+            _ => None,
+        }
+    }
+
     pub struct MacOSInjector {
         event_source: CGEventSource,
+        pacer: ButtonPacer,
+        scroll: ScrollAccumulator,
     }
 
     impl MacOSInjector {
-        pub fn new() -> Result<Self> {
+        pub fn new(options: &InjectorOptions) -> Result<Self> {
             let event_source = CGEventSource::new(CGEventSourceStateID::HIDSystemState)
                 .map_err(|_| anyhow::anyhow!("Failed to create event source"))?;
-            Ok(Self { event_source })
+            Ok(Self {
+                event_source,
+                pacer: ButtonPacer::new(options.click_interval),
+                scroll: ScrollAccumulator::default(),
+            })
+        }
+
+        /// 現在のマウス位置を取得
+        ///
+        /// `NSEvent::mouseLocation` は左下原点なので使わず、
+        /// CGEventと同じ左上原点の座標を返す
+        fn current_position(&self) -> Result<CGPoint> {
+            let event = CGEvent::new(self.event_source.clone())
+                .map_err(|_| anyhow::anyhow!("Failed to create event"))?;
+            Ok(event.location())
         }
     }
 
     impl MouseInjector for MacOSInjector {
         fn inject_event(&mut self, event: MouseEvent) -> Result<()> {
+            if is_button_event(&event) {
+                self.pacer.wait();
+            }
             let cg_event = match event {
                 MouseEvent::Move { x, y } => {
                     let location = CGPoint::new(x, y);
@@ -40,122 +447,151 @@ pub mod macos {
                     )
                     .map_err(|_| anyhow::anyhow!("Failed to create mouse move event"))?
                 }
-                MouseEvent::LeftClick => {
-                    // クリック時は現在のマウス位置を使用
-                    let current_pos = unsafe {
-                        use cocoa::appkit::NSEvent;
-                        use cocoa::base::nil;
-                        let location = NSEvent::mouseLocation(nil);
-                        CGPoint::new(location.x, location.y)
-                    };
+                MouseEvent::LeftClick
+                | MouseEvent::LeftRelease
+                | MouseEvent::RightClick
+                | MouseEvent::RightRelease
+                | MouseEvent::MiddleClick
+                | MouseEvent::MiddleRelease => {
+                    let (event_type, button) = event
+                        .button()
+                        .and_then(|(bit, pressed)| native_button(bit, pressed))
+                        .ok_or_else(|| anyhow::anyhow!("No native button for {:?}", event))?;
+                    let current_pos = self.current_position()?;
                     CGEvent::new_mouse_event(
                         self.event_source.clone(),
-                        CGEventType::LeftMouseDown,
+                        event_type,
                         current_pos,
-                        CGMouseButton::Left,
+                        button,
                     )
-                    .map_err(|_| anyhow::anyhow!("Failed to create left click event"))?
-                }
-                MouseEvent::LeftRelease => {
-                    let current_pos = unsafe {
-                        use cocoa::appkit::NSEvent;
-                        use cocoa::base::nil;
-                        let location = NSEvent::mouseLocation(nil);
-                        CGPoint::new(location.x, location.y)
-                    };
+                    .map_err(|_| anyhow::anyhow!("Failed to create {} event", event.kind()))?
+                }
+                MouseEvent::Scroll {
+                    x,
+                    y,
+                    delta_x,
+                    delta_y,
+                } => {
+                    // スクロール位置にカーソルを合わせてからスクロールする
                     CGEvent::new_mouse_event(
                         self.event_source.clone(),
-                        CGEventType::LeftMouseUp,
-                        current_pos,
+                        CGEventType::MouseMoved,
+                        CGPoint::new(x, y),
                         CGMouseButton::Left,
                     )
-                    .map_err(|_| anyhow::anyhow!("Failed to create left release event"))?
-                }
-                MouseEvent::RightClick => {
-                    let current_pos = unsafe {
-                        use cocoa::appkit::NSEvent;
-                        use cocoa::base::nil;
-                        let location = NSEvent::mouseLocation(nil);
-                        CGPoint::new(location.x, location.y)
-                    };
-                    CGEvent::new_mouse_event(
-                        self.event_source.clone(),
-                        CGEventType::RightMouseDown,
-                        current_pos,
-                        CGMouseButton::Right,
-                    )
-                    .map_err(|_| anyhow::anyhow!("Failed to create right click event"))?
-                }
-                MouseEvent::RightRelease => {
-                    let current_pos = unsafe {
-                        use cocoa::appkit::NSEvent;
-                        use cocoa::base::nil;
-                        let location = NSEvent::mouseLocation(nil);
-                        CGPoint::new(location.x, location.y)
-                    };
-                    CGEvent::new_mouse_event(
-                        self.event_source.clone(),
-                        CGEventType::RightMouseUp,
-                        current_pos,
-                        CGMouseButton::Right,
-                    )
-                    .map_err(|_| anyhow::anyhow!("Failed to create right release event"))?
-                }
-                MouseEvent::MiddleClick => {
-                    let current_pos = unsafe {
-                        use cocoa::appkit::NSEvent;
-                        use cocoa::base::nil;
-                        let location = NSEvent::mouseLocation(nil);
-                        CGPoint::new(location.x, location.y)
-                    };
-                    CGEvent::new_mouse_event(
-                        self.event_source.clone(),
-                        CGEventType::OtherMouseDown,
-                        current_pos,
-                        CGMouseButton::Center,
-                    )
-                    .map_err(|_| anyhow::anyhow!("Failed to create middle click event"))?
-                }
-                MouseEvent::MiddleRelease => {
-                    let current_pos = unsafe {
-                        use cocoa::appkit::NSEvent;
-                        use cocoa::base::nil;
-                        let location = NSEvent::mouseLocation(nil);
-                        CGPoint::new(location.x, location.y)
-                    };
-                    CGEvent::new_mouse_event(
+                    .map_err(|_| anyhow::anyhow!("Failed to create mouse move event"))?
+                    .post(CGEventTapLocation::HID);
+
+                    // ピクセル単位で送り、トラックパッドの慣性スクロールも滑らかにする
+                    let (pixels_x, pixels_y) =
+                        self.scroll
+                            .steps(delta_x, delta_y, SCROLL_UNITS_PER_MAC_PIXEL);
+                    if (pixels_x, pixels_y) == (0, 0) {
+                        return Ok(());
+                    }
+                    // 横スクロール（wheel2）は正が左
+                    CGEvent::new_scroll_event(
                         self.event_source.clone(),
-                        CGEventType::OtherMouseUp,
-                        current_pos,
-                        CGMouseButton::Center,
+                        ScrollEventUnit::PIXEL,
+                        2,
+                        pixels_y as i32,
+                        -pixels_x as i32,
+                        0,
                     )
-                    .map_err(|_| anyhow::anyhow!("Failed to create middle release event"))?
+                    .map_err(|_| anyhow::anyhow!("Failed to create scroll event"))?
                 }
-                MouseEvent::Scroll { delta_x: _, delta_y } => {
-                    let event = CGEvent::new(self.event_source.clone())
-                        .map_err(|_| anyhow::anyhow!("Failed to create scroll event"))?;
-                    event.set_type(CGEventType::ScrollWheel);
-                    event.set_integer_value_field(EventField::SCROLL_WHEEL_EVENT_DELTA_AXIS_1, delta_y);
-                    event
+                MouseEvent::Key { code, pressed } => {
+                    let keycode = crate::keymap::to_mac(code)
+                        .ok_or_else(|| anyhow::anyhow!("No macOS key for evdev code {}", code))?;
+                    CGEvent::new_keyboard_event(self.event_source.clone(), keycode, pressed)
+                        .map_err(|_| anyhow::anyhow!("Failed to create key event"))?
                 }
+                MouseEvent::ControlEnter
+                | MouseEvent::ControlLeave
+                | MouseEvent::Buttons { .. }
+                | MouseEvent::Locks { .. }
+                | MouseEvent::EdgeApproach { .. }
+                | MouseEvent::EdgeRetreat
+                | MouseEvent::Unknown => return Ok(()),
             };
 
             cg_event.post(CGEventTapLocation::HID);
             Ok(())
         }
+
+        fn backend(&self) -> Option<&'static str> {
+            Some("CoreGraphics")
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn every_table_button_has_a_native_event() {
+            for &bit in &BUTTON_TABLE {
+                let (down, button) = native_button(bit, true).unwrap();
+                let (up, released) = native_button(bit, false).unwrap();
+                assert_eq!(button as u32, released as u32);
+                assert_ne!(down as u32, up as u32);
+            }
+            let buttons: Vec<u32> = BUTTON_TABLE
+                .iter()
+                .map(|&bit| native_button(bit, true).unwrap().1 as u32)
+                .collect();
+            // macOSの並びなので、右が1で中が2
+            assert_eq!(buttons, vec![0, 1, 2]);
+            assert!(native_button(0, true).is_none());
+        }
+
+        #[test]
+        fn current_position_uses_the_top_left_origin() {
+            let injector = MacOSInjector::new(&InjectorOptions::default()).unwrap();
+            let position = injector.current_position().unwrap();
+            // 左上原点なら、カーソルはどれかのディスプレイの範囲（CGDisplayBoundsと同じ座標系）に入る
+            let inside = core_graphics::display::CGDisplay::active_displays()
+                .unwrap()
+                .into_iter()
+                .map(|id| core_graphics::display::CGDisplay::new(id).bounds())
+                .any(|b| {
+                    (b.origin.x..=b.origin.x + b.size.width).contains(&position.x)
+                        && (b.origin.y..=b.origin.y + b.size.height).contains(&position.y)
+                });
+            assert!(inside, "{:?}", (position.x, position.y));
+        }
     }
 }
 
 #[cfg(target_os = "linux")]
 pub mod linux {
     use super::*;
-    use crate::event::MouseEvent;
+    use crate::event::{MouseEvent, SCROLL_UNITS_PER_NOTCH};
     use std::process::Command;
 
-    pub struct LinuxInjector;
+    /// リトライ待ちの初期値
+    const RETRY_BACKOFF: Duration = Duration::from_millis(5);
+    /// 1イベントのリトライに使ってよい時間の上限（後続イベントを詰まらせないため）
+    const RETRY_BUDGET: Duration = Duration::from_millis(50);
+
+    /// `BUTTON_*` に対応するydotoolのボタン番号（対応表は [`super::BUTTON_TABLE`]）
+    fn native_button(bit: u8) -> Option<i32> {
+        match bit {
+            BUTTON_LEFT => Some(1),
+            BUTTON_RIGHT => Some(3),
+            BUTTON_MIDDLE => Some(2),
+            _ => None,
+        }
+    }
+
+    pub struct LinuxInjector {
+        pacer: ButtonPacer,
+        max_retries: u32,
+        scroll: ScrollAccumulator,
+    }
 
     impl LinuxInjector {
-        pub fn new() -> Result<Self> {
+        pub fn new(options: &InjectorOptions) -> Result<Self> {
             // ydotoolデーモンの可用性をチェック
             let output = Command::new("ydotool")
                 .args(["--help"])
@@ -166,13 +602,20 @@ pub mod linux {
                 return Err(anyhow::anyhow!("ydotool command failed"));
             }
 
-            Ok(Self)
+            Ok(Self {
+                pacer: ButtonPacer::new(options.click_interval),
+                max_retries: options.max_retries,
+                scroll: ScrollAccumulator::default(),
+            })
         }
     }
 
     impl MouseInjector for LinuxInjector {
         fn inject_event(&mut self, event: MouseEvent) -> Result<()> {
-            log::info!("Injecting event: {:?}", event);
+            log::debug!("Injecting event: {:?}", event);
+            if is_button_event(&event) {
+                self.pacer.wait();
+            }
 
             match event {
                 MouseEvent::Move { x, y } => {
@@ -183,45 +626,108 @@ pub mod linux {
                         log::debug!("Ignoring invalid coordinates ({}, {})", x, y);
                     }
                 }
-                MouseEvent::LeftClick => {
-                    self.click_wayland(1, true)?;
-                }
-                MouseEvent::LeftRelease => {
-                    self.click_wayland(1, false)?;
-                }
-                MouseEvent::RightClick => {
-                    self.click_wayland(3, true)?;
-                }
-                MouseEvent::RightRelease => {
-                    self.click_wayland(3, false)?;
-                }
-                MouseEvent::MiddleClick => {
-                    self.click_wayland(2, true)?;
-                }
-                MouseEvent::MiddleRelease => {
-                    self.click_wayland(2, false)?;
+                MouseEvent::LeftClick
+                | MouseEvent::LeftRelease
+                | MouseEvent::RightClick
+                | MouseEvent::RightRelease
+                | MouseEvent::MiddleClick
+                | MouseEvent::MiddleRelease => {
+                    let (bit, pressed) = event
+                        .button()
+                        .ok_or_else(|| anyhow::anyhow!("No button for {:?}", event))?;
+                    let button = native_button(bit)
+                        .ok_or_else(|| anyhow::anyhow!("No native button for {:?}", event))?;
+                    self.click_wayland(button, pressed)?;
                 }
-                MouseEvent::Scroll { delta_x: _, delta_y } => {
+                MouseEvent::Scroll {
+                    x,
+                    y,
+                    delta_x,
+                    delta_y,
+                } => {
+                    // スクロール位置にカーソルを合わせてからスクロールする
+                    if x >= 0.0 && y >= 0.0 {
+                        self.move_cursor_wayland(x as i32, y as i32)?;
+                    }
+                    // ydotoolはノッチ単位でしかスクロールできないので、端数は繰り越す
+                    let (notches_x, notches_y) =
+                        self.scroll.steps(delta_x, delta_y, SCROLL_UNITS_PER_NOTCH);
                     // delta_yが正の場合は上スクロール、負の場合は下スクロール
-                    let direction = if delta_y > 0 { 1 } else { -1 };
-                    self.scroll_wayland(direction)?;
+                    self.scroll_wayland(notches_y, "4", "5")?;
+                    // 6が左、7が右（X11のボタン番号）
+                    self.scroll_wayland(notches_x, "7", "6")?;
                 }
+                MouseEvent::Key { code, pressed } => {
+                    // ydotoolのkeyはevdevキーコードをそのまま受け付ける
+                    let state = if pressed { 1 } else { 0 };
+                    self.ydotool(&["key", &format!("{}:{}", code, state)])?;
+                }
+                MouseEvent::ControlEnter
+                | MouseEvent::ControlLeave
+                | MouseEvent::Buttons { .. }
+                | MouseEvent::Locks { .. }
+                | MouseEvent::EdgeApproach { .. }
+                | MouseEvent::EdgeRetreat
+                | MouseEvent::Unknown => {}
             }
 
             Ok(())
         }
+
+        fn backend(&self) -> Option<&'static str> {
+            Some("ydotool")
+        }
     }
 
     impl LinuxInjector {
+        /// ydotoolを実行する。失敗したらバックオフしながらリトライする
+        fn ydotool(&self, args: &[&str]) -> Result<()> {
+            let started = Instant::now();
+            let mut backoff = RETRY_BACKOFF;
+            let mut attempt = 0;
+            loop {
+                let result = Command::new("ydotool")
+                    .args(args)
+                    .output()
+                    .map_err(|e| anyhow::anyhow!("Failed to execute ydotool: {}", e))
+                    .and_then(|output| {
+                        if output.status.success() {
+                            Ok(())
+                        } else {
+                            Err(anyhow::anyhow!(
+                                "ydotool {:?} failed: {}",
+                                args,
+                                String::from_utf8_lossy(&output.stderr).trim()
+                            ))
+                        }
+                    });
+                match result {
+                    Ok(()) => return Ok(()),
+                    Err(e)
+                        if attempt < self.max_retries
+                            && started.elapsed() + backoff <= RETRY_BUDGET =>
+                    {
+                        attempt += 1;
+                        log::warn!(
+                            "{} (retry {}/{} in {:?})",
+                            e,
+                            attempt,
+                            self.max_retries,
+                            backoff
+                        );
+                        // 待つ間も受信ループは進む（注入中はワーカースレッドを明け渡している）
+                        std::thread::sleep(backoff);
+                        backoff *= 2;
+                    }
+                    Err(e) => return Err(e),
+                }
+            }
+        }
+
         fn move_cursor_wayland(&self, x: i32, y: i32) -> Result<()> {
             log::debug!("Moving cursor to ({}, {}) with ydotool", x, y);
 
-            Command::new("ydotool")
-                .args(["mousemove", "-a", &x.to_string(), &y.to_string()])
-                .output()
-                .map_err(|e| anyhow::anyhow!("Failed to execute ydotool: {}", e))?;
-
-            Ok(())
+            self.ydotool(&["mousemove", "-a", &x.to_string(), &y.to_string()])
         }
 
         fn click_wayland(&self, button: i32, press: bool) -> Result<()> {
@@ -229,26 +735,508 @@ pub mod linux {
             log::debug!("Mouse {} button {} with ydotool", action, button);
 
             if press {
-                Command::new("ydotool")
-                    .args(["click", &button.to_string()])
-                    .output()
-                    .map_err(|e| anyhow::anyhow!("Failed to execute ydotool: {}", e))?;
+                self.ydotool(&["click", &button.to_string()])?;
             }
             // releaseは通常clickで自動的に処理される
 
             Ok(())
         }
 
-        fn scroll_wayland(&self, direction: i32) -> Result<()> {
-            log::debug!("Scroll direction {} with ydotool", direction);
+        /// スクロールボタン（4: 上, 5: 下, 6: 左, 7: 右）をノッチ数だけクリックする
+        fn scroll_wayland(&self, notches: i64, positive: &str, negative: &str) -> Result<()> {
+            let button = if notches > 0 { positive } else { negative };
+            for _ in 0..notches.unsigned_abs().min(MAX_SCROLL_CLICKS as u64) {
+                log::debug!("Scroll button {} with ydotool", button);
+                self.ydotool(&["click", button])?;
+            }
+            Ok(())
+        }
+    }
+
+    /// 1イベントで押すスクロールボタンの回数の上限
+    const MAX_SCROLL_CLICKS: u32 = 10;
 
-            let scroll_dir = if direction > 0 { "4" } else { "5" };
-            Command::new("ydotool")
-                .args(["click", scroll_dir])
-                .output()
-                .map_err(|e| anyhow::anyhow!("Failed to execute ydotool: {}", e))?;
+    /// X11セッションか（XDG_SESSION_TYPEがなければWAYLAND_DISPLAYとDISPLAYで判断する）
+    pub fn is_x11_session() -> bool {
+        match std::env::var("XDG_SESSION_TYPE") {
+            Ok(session) => session == "x11",
+            Err(_) => {
+                std::env::var_os("WAYLAND_DISPLAY").is_none()
+                    && std::env::var_os("DISPLAY").is_some()
+            }
+        }
+    }
+
+    /// XTest拡張で注入するインジェクタ（X11セッション用）
+    ///
+    /// ydotoolなどWayland向けのツールはX11ではカーソルを正しく動かせないため
+    pub struct X11Injector {
+        conn: x11rb::rust_connection::RustConnection,
+        root: u32,
+        pacer: ButtonPacer,
+        scroll: ScrollAccumulator,
+    }
 
+    impl X11Injector {
+        pub fn new(options: &InjectorOptions) -> Result<Self> {
+            use x11rb::connection::Connection;
+            use x11rb::protocol::xtest::ConnectionExt;
+
+            let (conn, screen) = x11rb::connect(None)
+                .map_err(|e| anyhow::anyhow!("Failed to connect to the X server: {}", e))?;
+            let root = conn.setup().roots[screen].root;
+            conn.xtest_get_version(2, 2)?
+                .reply()
+                .map_err(|e| anyhow::anyhow!("XTest extension is unavailable: {}", e))?;
+            Ok(Self {
+                conn,
+                root,
+                pacer: ButtonPacer::new(options.click_interval),
+                scroll: ScrollAccumulator::default(),
+            })
+        }
+
+        fn fake_input(&self, kind: u8, detail: u8, x: i16, y: i16) -> Result<()> {
+            use x11rb::protocol::xtest::ConnectionExt;
+
+            self.conn
+                .xtest_fake_input(kind, detail, x11rb::CURRENT_TIME, self.root, x, y, 0)?;
+            Ok(())
+        }
+
+        fn move_to(&self, x: f64, y: f64) -> Result<()> {
+            use x11rb::protocol::xproto::MOTION_NOTIFY_EVENT;
+
+            // detail 0は絶対座標
+            self.fake_input(MOTION_NOTIFY_EVENT, 0, x as i16, y as i16)
+        }
+
+        fn button(&self, button: u8, pressed: bool) -> Result<()> {
+            use x11rb::protocol::xproto::{BUTTON_PRESS_EVENT, BUTTON_RELEASE_EVENT};
+
+            let kind = if pressed {
+                BUTTON_PRESS_EVENT
+            } else {
+                BUTTON_RELEASE_EVENT
+            };
+            self.fake_input(kind, button, 0, 0)
+        }
+
+        /// X11のスクロールはボタン（4: 上, 5: 下, 6: 左, 7: 右）をノッチ数だけクリックする
+        fn scroll_notches(&self, notches: i64, positive: u8, negative: u8) -> Result<()> {
+            let button = if notches > 0 { positive } else { negative };
+            for _ in 0..notches.unsigned_abs().min(MAX_SCROLL_CLICKS as u64) {
+                self.button(button, true)?;
+                self.button(button, false)?;
+            }
             Ok(())
         }
     }
+
+    impl MouseInjector for X11Injector {
+        fn inject_event(&mut self, event: MouseEvent) -> Result<()> {
+            use x11rb::connection::Connection;
+            use x11rb::protocol::xproto::{KEY_PRESS_EVENT, KEY_RELEASE_EVENT};
+
+            log::debug!("Injecting event: {:?}", event);
+            if is_button_event(&event) {
+                self.pacer.wait();
+            }
+
+            match event {
+                MouseEvent::Move { x, y } => {
+                    if x >= 0.0 && y >= 0.0 {
+                        self.move_to(x, y)?;
+                    } else {
+                        log::debug!("Ignoring invalid coordinates ({}, {})", x, y);
+                    }
+                }
+                MouseEvent::LeftClick
+                | MouseEvent::LeftRelease
+                | MouseEvent::RightClick
+                | MouseEvent::RightRelease
+                | MouseEvent::MiddleClick
+                | MouseEvent::MiddleRelease => {
+                    let (bit, pressed) = event
+                        .button()
+                        .ok_or_else(|| anyhow::anyhow!("No button for {:?}", event))?;
+                    // X11のボタン番号はydotoolと同じ
+                    let button = native_button(bit)
+                        .ok_or_else(|| anyhow::anyhow!("No native button for {:?}", event))?;
+                    self.button(button as u8, pressed)?;
+                }
+                MouseEvent::Scroll {
+                    x,
+                    y,
+                    delta_x,
+                    delta_y,
+                } => {
+                    // スクロール位置にカーソルを合わせてからスクロールする
+                    if x >= 0.0 && y >= 0.0 {
+                        self.move_to(x, y)?;
+                    }
+                    let (notches_x, notches_y) =
+                        self.scroll.steps(delta_x, delta_y, SCROLL_UNITS_PER_NOTCH);
+                    self.scroll_notches(notches_y, 4, 5)?;
+                    self.scroll_notches(notches_x, 7, 6)?;
+                }
+                MouseEvent::Key { code, pressed } => {
+                    // X11のキーコードはevdevキーコード+8
+                    let Some(keycode) = code.checked_add(8).and_then(|c| u8::try_from(c).ok())
+                    else {
+                        log::debug!("No X11 keycode for evdev key {}", code);
+                        return Ok(());
+                    };
+                    let kind = if pressed {
+                        KEY_PRESS_EVENT
+                    } else {
+                        KEY_RELEASE_EVENT
+                    };
+                    self.fake_input(kind, keycode, 0, 0)?;
+                }
+                MouseEvent::ControlEnter
+                | MouseEvent::ControlLeave
+                | MouseEvent::Buttons { .. }
+                | MouseEvent::Locks { .. }
+                | MouseEvent::EdgeApproach { .. }
+                | MouseEvent::EdgeRetreat
+                | MouseEvent::Unknown => {}
+            }
+
+            self.conn.flush()?;
+            Ok(())
+        }
+
+        fn backend(&self) -> Option<&'static str> {
+            Some("xtest")
+        }
+    }
+
+    /// `BUTTON_*` に対応するevdevのボタン
+    fn evdev_button(bit: u8) -> Option<evdev::Key> {
+        match bit {
+            BUTTON_LEFT => Some(evdev::Key::BTN_LEFT),
+            BUTTON_RIGHT => Some(evdev::Key::BTN_RIGHT),
+            BUTTON_MIDDLE => Some(evdev::Key::BTN_MIDDLE),
+            _ => None,
+        }
+    }
+
+    /// uinputの仮想デバイスで直接注入するインジェクタ
+    ///
+    /// イベントごとに外部コマンドを起動しないので速い。
+    /// ポインタは絶対座標のデバイス（VMのタブレットと同じ構成）として作る
+    pub struct UinputInjector {
+        pointer: evdev::uinput::VirtualDevice,
+        keyboard: evdev::uinput::VirtualDevice,
+        pacer: ButtonPacer,
+        scroll: ScrollAccumulator,
+    }
+
+    impl UinputInjector {
+        pub fn new(options: &InjectorOptions) -> Result<Self> {
+            use evdev::uinput::VirtualDeviceBuilder;
+            use evdev::{
+                AbsInfo, AbsoluteAxisType, AttributeSet, RelativeAxisType, UinputAbsSetup,
+            };
+
+            // 絶対座標の範囲として画面サイズが必要
+            let (width, height) = options
+                .screen_size
+                .or_else(screen_size)
+                .ok_or_else(|| anyhow::anyhow!("screen size is unknown, pass --screen-size"))?;
+            let axis = |code, max: u32| {
+                UinputAbsSetup::new(code, AbsInfo::new(0, 0, max as i32 - 1, 0, 0, 1))
+            };
+            let mut buttons = AttributeSet::<evdev::Key>::new();
+            for bit in [BUTTON_LEFT, BUTTON_RIGHT, BUTTON_MIDDLE] {
+                if let Some(button) = evdev_button(bit) {
+                    buttons.insert(button);
+                }
+            }
+            let mut wheels = AttributeSet::<RelativeAxisType>::new();
+            wheels.insert(RelativeAxisType::REL_WHEEL);
+            wheels.insert(RelativeAxisType::REL_HWHEEL);
+            wheels.insert(RelativeAxisType::REL_WHEEL_HI_RES);
+            wheels.insert(RelativeAxisType::REL_HWHEEL_HI_RES);
+            let pointer = VirtualDeviceBuilder::new()
+                .map_err(|e| anyhow::anyhow!("Failed to open /dev/uinput: {}", e))?
+                .name("sharemouse pointer")
+                .with_keys(&buttons)?
+                .with_relative_axes(&wheels)?
+                .with_absolute_axis(&axis(AbsoluteAxisType::ABS_X, width))?
+                .with_absolute_axis(&axis(AbsoluteAxisType::ABS_Y, height))?
+                .build()?;
+
+            // キーボードはポインタと混ぜるとタブレット扱いされかねないので別デバイスにする
+            let mut keys = AttributeSet::<evdev::Key>::new();
+            for code in 1..=248 {
+                keys.insert(evdev::Key::new(code));
+            }
+            let keyboard = VirtualDeviceBuilder::new()?
+                .name("sharemouse keyboard")
+                .with_keys(&keys)?
+                .build()?;
+
+            log::info!("Created uinput devices for a {}x{} screen", width, height);
+            Ok(Self {
+                pointer,
+                keyboard,
+                pacer: ButtonPacer::new(options.click_interval),
+                scroll: ScrollAccumulator::default(),
+            })
+        }
+
+        fn move_to(&mut self, x: f64, y: f64) -> Result<()> {
+            use evdev::{AbsoluteAxisType, EventType, InputEvent};
+            self.pointer.emit(&[
+                InputEvent::new(EventType::ABSOLUTE, AbsoluteAxisType::ABS_X.0, x as i32),
+                InputEvent::new(EventType::ABSOLUTE, AbsoluteAxisType::ABS_Y.0, y as i32),
+            ])?;
+            Ok(())
+        }
+    }
+
+    impl MouseInjector for UinputInjector {
+        fn inject_event(&mut self, event: MouseEvent) -> Result<()> {
+            use evdev::{EventType, InputEvent, RelativeAxisType};
+
+            log::debug!("Injecting event: {:?}", event);
+            if is_button_event(&event) {
+                self.pacer.wait();
+            }
+            match event {
+                MouseEvent::Move { x, y } => self.move_to(x, y)?,
+                MouseEvent::LeftClick
+                | MouseEvent::LeftRelease
+                | MouseEvent::RightClick
+                | MouseEvent::RightRelease
+                | MouseEvent::MiddleClick
+                | MouseEvent::MiddleRelease => {
+                    let (button, pressed) = event
+                        .button()
+                        .and_then(|(bit, pressed)| Some((evdev_button(bit)?, pressed)))
+                        .ok_or_else(|| anyhow::anyhow!("No native button for {:?}", event))?;
+                    self.pointer.emit(&[InputEvent::new(
+                        EventType::KEY,
+                        button.code(),
+                        pressed as i32,
+                    )])?;
+                }
+                MouseEvent::Scroll {
+                    x,
+                    y,
+                    delta_x,
+                    delta_y,
+                } => {
+                    // スクロール位置にカーソルを合わせてからスクロールする
+                    self.move_to(x, y)?;
+                    // 高分解能の値をそのまま送り、それに対応しないアプリ向けにノッチ単位の値も送る
+                    let (notches_x, notches_y) =
+                        self.scroll.steps(delta_x, delta_y, SCROLL_UNITS_PER_NOTCH);
+                    let wheel = |axis: RelativeAxisType, value: i64| {
+                        InputEvent::new(EventType::RELATIVE, axis.0, value as i32)
+                    };
+                    self.pointer.emit(&[
+                        wheel(RelativeAxisType::REL_WHEEL_HI_RES, delta_y),
+                        wheel(RelativeAxisType::REL_HWHEEL_HI_RES, delta_x),
+                        wheel(RelativeAxisType::REL_WHEEL, notches_y),
+                        wheel(RelativeAxisType::REL_HWHEEL, notches_x),
+                    ])?;
+                }
+                MouseEvent::Key { code, pressed } => {
+                    self.keyboard
+                        .emit(&[InputEvent::new(EventType::KEY, code, pressed as i32)])?;
+                }
+                MouseEvent::ControlEnter
+                | MouseEvent::ControlLeave
+                | MouseEvent::Buttons { .. }
+                | MouseEvent::Locks { .. }
+                | MouseEvent::EdgeApproach { .. }
+                | MouseEvent::EdgeRetreat
+                | MouseEvent::Unknown => {}
+            }
+            Ok(())
+        }
+
+        fn backend(&self) -> Option<&'static str> {
+            Some("uinput")
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn every_table_button_has_a_native_number() {
+            let numbers: Vec<i32> = BUTTON_TABLE
+                .iter()
+                .map(|&bit| native_button(bit).unwrap())
+                .collect();
+            // X11の並びなので、中が2で右が3
+            assert_eq!(numbers, vec![1, 3, 2]);
+            assert_eq!(native_button(0), None);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::FakeClock;
+    use std::sync::{Arc, Mutex};
+
+    /// 注入されたイベントを記録するだけのインジェクタ
+    #[derive(Clone, Default)]
+    struct Recorder(Arc<Mutex<Vec<MouseEvent>>>);
+
+    impl Recorder {
+        fn take(&self) -> Vec<MouseEvent> {
+            std::mem::take(&mut self.0.lock().unwrap())
+        }
+    }
+
+    impl MouseInjector for Recorder {
+        fn inject_event(&mut self, event: MouseEvent) -> Result<()> {
+            self.0.lock().unwrap().push(event);
+            Ok(())
+        }
+
+        fn backend(&self) -> Option<&'static str> {
+            None
+        }
+    }
+
+    #[test]
+    fn button_state_is_reconciled_with_the_sender() {
+        let recorder = Recorder::default();
+        let mut injector = ButtonSyncInjector::new(Box::new(recorder.clone()));
+        injector.inject_event(MouseEvent::LeftClick).unwrap();
+        injector
+            .inject_event(MouseEvent::Buttons { held: BUTTON_LEFT })
+            .unwrap();
+        // 揃っていれば何も注入しない
+        assert_eq!(recorder.take(), vec![MouseEvent::LeftClick]);
+        // 左のリリースと右・中の押下を取りこぼした
+        injector
+            .inject_event(MouseEvent::Buttons {
+                held: BUTTON_RIGHT | BUTTON_MIDDLE,
+            })
+            .unwrap();
+        assert_eq!(
+            recorder.take(),
+            vec![
+                MouseEvent::LeftRelease,
+                MouseEvent::RightClick,
+                MouseEvent::MiddleClick
+            ]
+        );
+        injector.inject_event(MouseEvent::RightRelease).unwrap();
+        injector
+            .inject_event(MouseEvent::Buttons {
+                held: BUTTON_MIDDLE,
+            })
+            .unwrap();
+        assert_eq!(recorder.take(), vec![MouseEvent::RightRelease]);
+    }
+
+    #[test]
+    fn control_leave_releases_held_buttons() {
+        let recorder = Recorder::default();
+        let mut injector = ButtonSyncInjector::new(Box::new(recorder.clone()));
+        injector.inject_event(MouseEvent::LeftClick).unwrap();
+        injector.inject_event(MouseEvent::MiddleClick).unwrap();
+        injector.inject_event(MouseEvent::ControlLeave).unwrap();
+        assert_eq!(
+            recorder.take(),
+            vec![
+                MouseEvent::LeftClick,
+                MouseEvent::MiddleClick,
+                MouseEvent::LeftRelease,
+                MouseEvent::MiddleRelease,
+                MouseEvent::ControlLeave
+            ]
+        );
+        // 離した後は何も残っていない
+        injector.inject_event(MouseEvent::ControlLeave).unwrap();
+        assert_eq!(recorder.take(), vec![MouseEvent::ControlLeave]);
+    }
+
+    #[test]
+    fn lock_keys_are_toggled_to_match_the_sender() {
+        use crate::event::{LOCK_CAPS, LOCK_NUM, LOCK_SCROLL};
+
+        // このマシンはNumLockだけオンで、ScrollLockの状態は読めない
+        fn local() -> MouseEvent {
+            MouseEvent::Locks {
+                known: LOCK_CAPS | LOCK_NUM,
+                on: LOCK_NUM,
+            }
+        }
+        let recorder = Recorder::default();
+        let mut injector = LockSyncInjector::with_reader(Box::new(recorder.clone()), local);
+        // 送信側はCapsLockとScrollLockがオンで、NumLockがオフ
+        injector
+            .inject_event(MouseEvent::Locks {
+                known: LOCK_CAPS | LOCK_NUM | LOCK_SCROLL,
+                on: LOCK_CAPS | LOCK_SCROLL,
+            })
+            .unwrap();
+        let key = |code, pressed| MouseEvent::Key { code, pressed };
+        assert_eq!(
+            recorder.take(),
+            vec![key(58, true), key(58, false), key(69, true), key(69, false)]
+        );
+        // 揃っているか、送信側で読めなかったロックキーは切り替えない（macOSにはNumLockがない）
+        injector
+            .inject_event(MouseEvent::Locks {
+                known: LOCK_CAPS,
+                on: 0,
+            })
+            .unwrap();
+        injector
+            .inject_event(MouseEvent::Locks {
+                known: LOCK_NUM,
+                on: LOCK_NUM,
+            })
+            .unwrap();
+        assert_eq!(recorder.take(), vec![]);
+        // ほかのイベントはそのまま通す
+        injector.inject_event(MouseEvent::ControlEnter).unwrap();
+        assert_eq!(recorder.take(), vec![MouseEvent::ControlEnter]);
+    }
+
+    #[test]
+    fn scroll_remainders_carry_over_in_both_directions() {
+        let mut scroll = ScrollAccumulator::default();
+        // 1ノッチに満たない量は溜めておく
+        assert_eq!(scroll.steps(0, -60, 120), (0, 0));
+        assert_eq!(scroll.steps(0, -60, 120), (0, -1));
+        assert_eq!(scroll.steps(-150, 0, 120), (-1, 0));
+        // 逆向きに動かすと、溜まっていた端数と打ち消し合う
+        assert_eq!(scroll.steps(30, 0, 120), (0, 0));
+        assert_eq!(scroll.steps(119, 0, 120), (0, 0));
+        assert_eq!(scroll.steps(1, 0, 120), (1, 0));
+        // 何ノッチ分送っても、合計が入力の合計と一致する
+        let mut total = (0, 0);
+        for delta in [-7, 13, -250, 61, -3, 299, -120, 7] {
+            let (x, y) = scroll.steps(delta, -delta, 120);
+            total = (total.0 + x, total.1 + y);
+        }
+        assert_eq!(total, (0, 0));
+        assert_eq!(scroll.steps(0, 0, 120), (0, 0));
+    }
+
+    #[test]
+    fn pacer_waits_only_within_the_interval() {
+        let clock = FakeClock::default();
+        let mut pacer = ButtonPacer::with_clock(Duration::from_secs(60), Box::new(clock.clone()));
+        let started = Instant::now();
+        pacer.wait();
+        // 時計の上では間隔が空いているので、実際には待たない
+        clock.advance(Duration::from_secs(60));
+        pacer.wait();
+        assert!(started.elapsed() < Duration::from_secs(1));
+    }
 }
@@ -0,0 +1,301 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::str::FromStr;
+
+/// evdevキーコードとmacOS仮想キーコード（kVK_*）の対応表
+///
+/// ネットワーク上のキーコードはevdevに統一しているので、macOSでだけ変換する。
+/// macOSのCommandはLinuxのSuper（Meta）、OptionはAltに対応させる
+#[cfg(target_os = "macos")]
+const KEYMAP: &[(u16, u16)] = &[
+    (1, 0x35),   // ESC
+    (2, 0x12),   // 1
+    (3, 0x13),   // 2
+    (4, 0x14),   // 3
+    (5, 0x15),   // 4
+    (6, 0x17),   // 5
+    (7, 0x16),   // 6
+    (8, 0x1A),   // 7
+    (9, 0x1C),   // 8
+    (10, 0x19),  // 9
+    (11, 0x1D),  // 0
+    (12, 0x1B),  // MINUS
+    (13, 0x18),  // EQUAL
+    (14, 0x33),  // BACKSPACE
+    (15, 0x30),  // TAB
+    (16, 0x0C),  // Q
+    (17, 0x0D),  // W
+    (18, 0x0E),  // E
+    (19, 0x0F),  // R
+    (20, 0x11),  // T
+    (21, 0x10),  // Y
+    (22, 0x20),  // U
+    (23, 0x22),  // I
+    (24, 0x1F),  // O
+    (25, 0x23),  // P
+    (26, 0x21),  // LEFTBRACE
+    (27, 0x1E),  // RIGHTBRACE
+    (28, 0x24),  // ENTER
+    (29, 0x3B),  // LEFTCTRL
+    (30, 0x00),  // A
+    (31, 0x01),  // S
+    (32, 0x02),  // D
+    (33, 0x03),  // F
+    (34, 0x05),  // G
+    (35, 0x04),  // H
+    (36, 0x26),  // J
+    (37, 0x28),  // K
+    (38, 0x25),  // L
+    (39, 0x29),  // SEMICOLON
+    (40, 0x27),  // APOSTROPHE
+    (41, 0x32),  // GRAVE
+    (42, 0x38),  // LEFTSHIFT
+    (43, 0x2A),  // BACKSLASH
+    (44, 0x06),  // Z
+    (45, 0x07),  // X
+    (46, 0x08),  // C
+    (47, 0x09),  // V
+    (48, 0x0B),  // B
+    (49, 0x2D),  // N
+    (50, 0x2E),  // M
+    (51, 0x2B),  // COMMA
+    (52, 0x2F),  // DOT
+    (53, 0x2C),  // SLASH
+    (54, 0x3C),  // RIGHTSHIFT
+    (56, 0x3A),  // LEFTALT
+    (57, 0x31),  // SPACE
+    (58, 0x39),  // CAPSLOCK
+    (59, 0x7A),  // F1
+    (60, 0x78),  // F2
+    (61, 0x63),  // F3
+    (62, 0x76),  // F4
+    (63, 0x60),  // F5
+    (64, 0x61),  // F6
+    (65, 0x62),  // F7
+    (66, 0x64),  // F8
+    (67, 0x65),  // F9
+    (68, 0x6D),  // F10
+    (86, 0x0A),  // 102ND
+    (87, 0x67),  // F11
+    (88, 0x6F),  // F12
+    (97, 0x3E),  // RIGHTCTRL
+    (100, 0x3D), // RIGHTALT
+    (102, 0x73), // HOME
+    (103, 0x7E), // UP
+    (104, 0x74), // PAGEUP
+    (105, 0x7B), // LEFT
+    (106, 0x7C), // RIGHT
+    (107, 0x77), // END
+    (108, 0x7D), // DOWN
+    (109, 0x79), // PAGEDOWN
+    (110, 0x72), // INSERT（macOSではHelp）
+    (111, 0x75), // DELETE
+    (125, 0x37), // LEFTMETA
+    (126, 0x36), // RIGHTMETA
+];
+
+/// evdevキーコード → macOS仮想キーコード
+#[cfg(target_os = "macos")]
+pub fn to_mac(code: u16) -> Option<u16> {
+    KEYMAP
+        .iter()
+        .find(|(evdev, _)| *evdev == code)
+        .map(|(_, mac)| *mac)
+}
+
+/// macOS仮想キーコード → evdevキーコード
+#[cfg(target_os = "macos")]
+pub fn from_mac(code: u16) -> Option<u16> {
+    KEYMAP
+        .iter()
+        .find(|(_, mac)| *mac == code)
+        .map(|(evdev, _)| *evdev)
+}
+
+/// 修飾キーの名前と、対応するevdevキーコード（左右）
+const MODIFIERS: &[(&str, [u16; 2])] = &[
+    ("ctrl", [29, 97]),
+    ("shift", [42, 54]),
+    ("alt", [56, 100]),
+    ("meta", [125, 126]),
+];
+
+/// キーの名前 → evdevキーコード
+fn key_code(name: &str) -> Option<u16> {
+    const ROWS: &[(&str, u16)] = &[("qwertyuiop", 16), ("asdfghjkl", 30), ("zxcvbnm", 44)];
+    let mut chars = name.chars();
+    if let (Some(c), None) = (chars.next(), chars.next()) {
+        if let Some(code) = ROWS
+            .iter()
+            .find_map(|(row, first)| row.find(c).map(|i| first + i as u16))
+        {
+            return Some(code);
+        }
+        return match c {
+            '1'..='9' => Some(c as u16 - '1' as u16 + 2),
+            '0' => Some(11),
+            _ => None,
+        };
+    }
+    if let Some(n) = name.strip_prefix('f').and_then(|n| n.parse::<u16>().ok()) {
+        return match n {
+            1..=10 => Some(58 + n),
+            11 => Some(87),
+            12 => Some(88),
+            _ => None,
+        };
+    }
+    match name {
+        "esc" | "escape" => Some(1),
+        "tab" => Some(15),
+        "enter" | "return" => Some(28),
+        "space" => Some(57),
+        _ => None,
+    }
+}
+
+/// 制御側を切り替えるホットキー（例: `ctrl+shift+m`, `cmd+shift+m`）
+///
+/// 修飾キーは ctrl, shift, alt（option）, meta（cmd, super）。左右は区別しない
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Hotkey {
+    spec: String,
+    /// `MODIFIERS`のインデックス
+    modifiers: Vec<usize>,
+    key: u16,
+}
+
+impl FromStr for Hotkey {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let spec = s.trim().to_lowercase();
+        let mut parts: Vec<&str> = spec.split('+').map(str::trim).collect();
+        let key = parts.pop().unwrap_or_default();
+        let key =
+            key_code(key).ok_or_else(|| anyhow::anyhow!("unknown key {:?} in hotkey", key))?;
+        let modifiers = parts
+            .iter()
+            .map(|name| {
+                let name = match *name {
+                    "control" => "ctrl",
+                    "option" => "alt",
+                    "cmd" | "command" | "super" => "meta",
+                    name => name,
+                };
+                MODIFIERS
+                    .iter()
+                    .position(|(modifier, _)| *modifier == name)
+                    .ok_or_else(|| anyhow::anyhow!("unknown modifier {:?} in hotkey", name))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        Ok(Self {
+            spec,
+            modifiers,
+            key,
+        })
+    }
+}
+
+impl TryFrom<String> for Hotkey {
+    type Error = anyhow::Error;
+
+    fn try_from(s: String) -> anyhow::Result<Self> {
+        s.parse()
+    }
+}
+
+impl From<Hotkey> for String {
+    fn from(hotkey: Hotkey) -> Self {
+        hotkey.spec
+    }
+}
+
+/// 押下中のキーを追跡し、ホットキーが押された瞬間を検出する
+#[derive(Default)]
+pub struct HotkeyTracker {
+    pressed: HashSet<u16>,
+}
+
+impl HotkeyTracker {
+    /// キーの押下・解放を反映する。ホットキーが押されたらtrue
+    pub fn key(&mut self, hotkey: Option<&Hotkey>, code: u16, pressed: bool) -> bool {
+        if !pressed {
+            self.pressed.remove(&code);
+            return false;
+        }
+        // オートリピートでは発火させない
+        let Some(hotkey) = hotkey.filter(|hotkey| hotkey.key == code) else {
+            self.pressed.insert(code);
+            return false;
+        };
+        if !self.pressed.insert(code) {
+            return false;
+        }
+        hotkey.modifiers.iter().all(|&i| {
+            MODIFIERS[i]
+                .1
+                .iter()
+                .any(|code| self.pressed.contains(code))
+        })
+    }
+
+    /// 押下中のキー（制御を戻すときや終了時に相手側で離すため）
+    pub fn pressed(&self) -> impl Iterator<Item = u16> + '_ {
+        self.pressed.iter().copied()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hotkeys_are_parsed_with_aliases() {
+        let hotkey: Hotkey = " Cmd+Shift+M ".parse().unwrap();
+        assert_eq!(hotkey.key, 50);
+        assert_eq!(hotkey.modifiers, vec![3, 1]);
+        assert_eq!(String::from(hotkey), "cmd+shift+m");
+        for (spec, key) in [
+            ("ctrl+f12", 88),
+            ("option+f1", 59),
+            ("super+esc", 1),
+            ("0", 11),
+        ] {
+            assert_eq!(spec.parse::<Hotkey>().unwrap().key, key, "{}", spec);
+        }
+        for spec in ["", "ctrl+", "hyper+m", "ctrl+f13", "ctrl+mm"] {
+            assert!(spec.parse::<Hotkey>().is_err(), "{}", spec);
+        }
+    }
+
+    #[test]
+    fn tracker_fires_once_when_the_combination_completes() {
+        let hotkey: Hotkey = "ctrl+shift+m".parse().unwrap();
+        let mut tracker = HotkeyTracker::default();
+        // 修飾キーが揃うまでは発火しない
+        assert!(!tracker.key(Some(&hotkey), 29, true));
+        assert!(!tracker.key(Some(&hotkey), 50, true));
+        assert!(!tracker.key(Some(&hotkey), 50, false));
+        // 右Shiftでも左と同じに扱う
+        assert!(!tracker.key(Some(&hotkey), 54, true));
+        assert!(tracker.key(Some(&hotkey), 50, true));
+        // オートリピートでは発火しない
+        assert!(!tracker.key(Some(&hotkey), 50, true));
+        assert!(!tracker.key(Some(&hotkey), 50, false));
+        assert!(tracker.key(Some(&hotkey), 50, true));
+        let mut pressed: Vec<u16> = tracker.pressed().collect();
+        pressed.sort_unstable();
+        assert_eq!(pressed, vec![29, 50, 54]);
+    }
+
+    #[test]
+    fn tracker_follows_keys_without_a_hotkey() {
+        let mut tracker = HotkeyTracker::default();
+        assert!(!tracker.key(None, 30, true));
+        assert!(!tracker.key(None, 42, true));
+        assert!(!tracker.key(None, 30, false));
+        assert_eq!(tracker.pressed().collect::<Vec<_>>(), vec![42]);
+    }
+}
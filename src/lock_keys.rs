@@ -0,0 +1,66 @@
+use crate::event::{MouseEvent, LOCK_CAPS, LOCK_NUM, LOCK_SCROLL};
+
+/// ロックキーのビットと、切り替えるときに押すevdevキーコード
+pub const LOCK_KEYS: [(u8, u16); 3] = [(LOCK_CAPS, 58), (LOCK_NUM, 69), (LOCK_SCROLL, 70)];
+
+/// このマシンのロックキーの状態を`MouseEvent::Locks`として読む
+///
+/// macOSにはNumLockとScrollLockがないので、CapsLockだけをknownにする
+#[cfg(target_os = "macos")]
+pub fn read() -> MouseEvent {
+    use core_graphics::event::CGEventFlags;
+
+    #[link(name = "CoreGraphics", kind = "framework")]
+    extern "C" {
+        fn CGEventSourceFlagsState(state_id: i32) -> u64;
+    }
+    // kCGEventSourceStateCombinedSessionState
+    let flags = unsafe { CGEventSourceFlagsState(0) };
+    let caps = flags & CGEventFlags::CGEventFlagAlphaShift.bits() != 0;
+    MouseEvent::Locks {
+        known: LOCK_CAPS,
+        on: if caps { LOCK_CAPS } else { 0 },
+    }
+}
+
+/// このマシンのロックキーの状態を`MouseEvent::Locks`として読む
+///
+/// ロックキーの状態はキーボードのLEDに反映されるので、LEDを持つ最初のキーボードから読む。
+/// 読めるキーボードがなければknownは空になる
+#[cfg(target_os = "linux")]
+pub fn read() -> MouseEvent {
+    use evdev::LedType;
+
+    const LEDS: [(u8, LedType); 3] = [
+        (LOCK_CAPS, LedType::LED_CAPSL),
+        (LOCK_NUM, LedType::LED_NUML),
+        (LOCK_SCROLL, LedType::LED_SCROLLL),
+    ];
+    for (path, device) in evdev::enumerate() {
+        let Some(supported) = device.supported_leds() else {
+            continue;
+        };
+        if !supported.contains(LedType::LED_CAPSL) {
+            continue;
+        }
+        let leds = match device.get_led_state() {
+            Ok(leds) => leds,
+            Err(e) => {
+                log::debug!("Failed to read the LEDs of {}: {}", path.display(), e);
+                continue;
+            }
+        };
+        let (mut known, mut on) = (0, 0);
+        for (bit, led) in LEDS {
+            if supported.contains(led) {
+                known |= bit;
+                if leds.contains(led) {
+                    on |= bit;
+                }
+            }
+        }
+        return MouseEvent::Locks { known, on };
+    }
+    log::debug!("No keyboard with lock LEDs found in /dev/input");
+    MouseEvent::Locks { known: 0, on: 0 }
+}
@@ -0,0 +1,96 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+/// サイズで切り替えるログファイル
+///
+/// `path` が `max_bytes` を超えたら `path.1` に、`path.1` は `path.2` に…と順にずらし、
+/// `keep` 個より古いものは消す
+pub struct RotatingFile {
+    path: PathBuf,
+    max_bytes: u64,
+    keep: usize,
+    file: File,
+    written: u64,
+}
+
+impl RotatingFile {
+    pub fn open(path: &Path, max_bytes: u64, keep: usize) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let written = file.metadata()?.len();
+        Ok(Self {
+            path: path.to_path_buf(),
+            max_bytes,
+            keep,
+            file,
+            written,
+        })
+    }
+
+    fn rotated(&self, index: usize) -> PathBuf {
+        let mut name = self.path.clone().into_os_string();
+        name.push(format!(".{}", index));
+        PathBuf::from(name)
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        if self.keep == 0 {
+            self.file = File::create(&self.path)?;
+        } else {
+            let _ = fs::remove_file(self.rotated(self.keep));
+            for index in (1..self.keep).rev() {
+                let from = self.rotated(index);
+                if from.exists() {
+                    fs::rename(&from, self.rotated(index + 1))?;
+                }
+            }
+            fs::rename(&self.path, self.rotated(1))?;
+            self.file = File::create(&self.path)?;
+        }
+        self.written = 0;
+        Ok(())
+    }
+}
+
+impl Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.written > 0 && self.written + buf.len() as u64 > self.max_bytes {
+            self.rotate()?;
+        }
+        let n = self.file.write(buf)?;
+        self.written += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+/// 標準エラー出力とログファイルの両方に書く
+pub struct Tee {
+    file: RotatingFile,
+}
+
+impl Tee {
+    pub fn new(file: RotatingFile) -> Self {
+        Self { file }
+    }
+}
+
+impl Write for Tee {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        io::stderr().write_all(buf)?;
+        // ファイルに書けなくても標準エラー出力のログは止めない
+        if let Err(e) = self.file.write_all(buf) {
+            eprintln!("Failed to write log file: {}", e);
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        io::stderr().flush()?;
+        self.file.flush()
+    }
+}
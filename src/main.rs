@@ -3,13 +3,28 @@ use clap::{Parser, Subcommand};
 use injector::MouseInjector;
 use log::{error, info};
 use std::path::PathBuf;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
 
 mod capturer;
+mod clipboard;
+mod clock;
 mod config;
 mod coordinate;
+mod crypto;
+mod discovery;
 mod event;
 mod injector;
+mod keymap;
+mod lock_keys;
+mod logfile;
+mod metrics;
 mod network;
+mod permissions;
+mod query;
+mod recording;
+mod schedule;
+mod service;
 mod virtual_model;
 
 use virtual_model::{SharedVirtualModel, VirtualModel};
@@ -22,6 +37,22 @@ struct Cli {
 
     #[arg(short, long, default_value = "info")]
     log_level: String,
+
+    /// 標準エラー出力に加えてログを書き出すファイル
+    #[arg(long, global = true)]
+    log_file: Option<PathBuf>,
+
+    /// ログファイルを切り替えるサイズ（MB）
+    #[arg(long, global = true, default_value = "10")]
+    log_file_max_mb: u64,
+
+    /// 残しておく古いログファイルの数
+    #[arg(long, global = true, default_value = "5")]
+    log_file_count: usize,
+
+    /// 指定したポートでHTTPの`/metrics`を公開する
+    #[arg(long, global = true)]
+    metrics_port: Option<u16>,
 }
 
 #[derive(Subcommand)]
@@ -33,40 +64,328 @@ enum Commands {
     Receive {
         #[arg(short, long, default_value = "5000")]
         port: u16,
+        /// 連続するボタンイベント間の最小間隔（ミリ秒）
+        #[arg(long, default_value = "0")]
+        click_interval_ms: u64,
+        /// 注入に失敗したときのリトライ回数
+        #[arg(long, default_value = "2")]
+        inject_retries: u32,
+        /// 制御セッション外で受信したイベントの扱い
+        #[arg(long, value_enum, default_value_t = config::SessionPolicy::Inject)]
+        session_policy: config::SessionPolicy,
+        /// 注入したイベントを記録するファイル
+        #[arg(long)]
+        record: Option<PathBuf>,
+        /// 記録を圧縮する
+        #[arg(long)]
+        record_compress: bool,
+        /// 注入を止める時間帯（例: "sat,sun 00:00-23:59", "18:00-09:00"）
+        #[arg(long)]
+        quiet_hours: Option<schedule::QuietHours>,
+        /// 受け付ける送信元のIPアドレスかCIDR（例: 192.168.1.0/24。複数指定可。省略時は全て受け付ける）
+        #[arg(long = "allow-sender")]
+        allowed_senders: Vec<network::IpNet>,
+        /// 受信した座標を画面内に収めてから注入する
+        #[arg(long)]
+        clamp_to_screen: bool,
+        /// 画面サイズ（例: 1920x1080）。省略時は自動で取得する
+        #[arg(long, value_parser = parse_screen_size)]
+        screen_size: Option<(u32, u32)>,
+        /// 送信側とクリップボードを共有する
+        #[arg(long)]
+        share_clipboard: bool,
+        /// 送信側の`psk`と同じ共有鍵を書いたファイル。指定すると認証できないパケットを捨てる
+        #[arg(long)]
+        psk_file: Option<PathBuf>,
+        /// この名前でLANに告知し、送信側の`remote_ip: discover`で見つけられるようにする
+        #[arg(long, value_name = "NAME")]
+        announce: Option<String>,
+        /// 待ち受けるアドレス（例: "::" でIPv6も受け付ける）
+        #[arg(long, default_value = "0.0.0.0")]
+        bind_address: std::net::IpAddr,
+        /// 受信した移動の間をこの時間（ミリ秒）かけて補間し、カーソルをなめらかに動かす。0で無効
+        #[arg(long, default_value = "0")]
+        interpolate_ms: u64,
+        /// これより遅れて届いた移動イベントを捨てる（ミリ秒）。クリックなどは常に注入する。0で無効
+        ///
+        /// 送信時刻を付けない古い送信側からのイベントは捨てない
+        #[arg(long, default_value = "0")]
+        max_event_age_ms: u64,
+        /// カーソルを動かさず、受信したイベントをログに出すだけにする
+        #[arg(long)]
+        dry_run: bool,
     },
     Template {
         #[arg(short, long, default_value = "config.yaml")]
         config: PathBuf,
     },
+    /// 受信したイベントを注入せずに表示する
+    ///
+    /// 送信側には応答しない（ハンドシェイクもしない）ので、送信側の状態は変わらない
+    Sniff {
+        #[arg(short, long, default_value = "5000")]
+        port: u16,
+        /// 表示するイベント種別（例: Move, LeftClick, Scroll）
+        #[arg(short = 't', long)]
+        event_type: Option<String>,
+        /// 暗号化されたイベントを読むための共有鍵のファイル
+        #[arg(long)]
+        psk_file: Option<PathBuf>,
+    },
+    /// 必要な権限と注入手段が揃っているか確認する
+    #[command(visible_alias = "doctor")]
+    Permissions,
+    /// 実行中のsenderに制御側を問い合わせる
+    ///
+    /// 終了コード: 0 = ローカルが制御中, 1 = リモートが制御中, 2 = senderが起動していない
+    Status,
+    /// 起動時に実行するためのサービス定義（macOSはlaunchd、Linuxはsystemd）を出力する
+    InstallService {
+        #[arg(value_enum)]
+        mode: service::ServiceMode,
+        #[arg(short, long, default_value = "config.yaml")]
+        config: PathBuf,
+        #[arg(short, long, default_value = "5000")]
+        port: u16,
+        /// 出力先（省略時は標準出力）
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// 記録したイベントを再生する
+    Replay {
+        file: PathBuf,
+        /// 再生速度の倍率
+        #[arg(long, default_value = "1.0")]
+        speed: f64,
+    },
 }
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     let cli = Cli::parse();
 
-    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or(&cli.log_level))
-        .init();
+    let mut logger =
+        env_logger::Builder::from_env(env_logger::Env::default().default_filter_or(&cli.log_level));
+    if let Some(path) = &cli.log_file {
+        let file = logfile::RotatingFile::open(
+            path,
+            cli.log_file_max_mb * 1024 * 1024,
+            cli.log_file_count,
+        )?;
+        logger.target(env_logger::Target::Pipe(Box::new(logfile::Tee::new(file))));
+    }
+    logger.init();
+
+    if let Some(port) = cli.metrics_port {
+        tokio::spawn(async move {
+            if let Err(e) = metrics::serve(port).await {
+                error!("Metrics endpoint stopped: {}", e);
+            }
+        });
+    }
 
     match cli.command {
         Commands::Send { config } => {
             info!("Starting Sending");
             let config = config::Config::load(&config)?;
+            let ((start_x, start_y), (end_x, end_y)) = config.transfer_edge_segment();
+            info!(
+                "Transferring to the remote along ({:.0}, {:.0})-({:.0}, {:.0})",
+                start_x, start_y, end_x, end_y
+            );
+            if let Some(monitor) = &config.remote_monitor {
+                info!(
+                    "Targeting remote monitor {} at ({}, {})",
+                    monitor.name, monitor.x, monitor.y
+                );
+            }
             start_sender(config).await?;
         }
-        Commands::Receive { port } => {
+        Commands::Receive {
+            port,
+            click_interval_ms,
+            inject_retries,
+            session_policy,
+            record,
+            record_compress,
+            quiet_hours,
+            allowed_senders,
+            clamp_to_screen,
+            screen_size,
+            share_clipboard,
+            psk_file,
+            announce,
+            bind_address,
+            interpolate_ms,
+            max_event_age_ms,
+            dry_run,
+        } => {
             info!("Start Receiving on port {}", port);
-            start_receiver(port).await?;
+            if let Some(name) = announce {
+                tokio::spawn(async move {
+                    if let Err(e) = discovery::announce(name, port).await {
+                        error!("Discovery announcement stopped: {}", e);
+                    }
+                });
+            }
+            let options = injector::InjectorOptions {
+                click_interval: Duration::from_millis(click_interval_ms),
+                max_retries: inject_retries,
+                record,
+                record_compress,
+                clamp_to_screen,
+                screen_size,
+                dry_run,
+                interpolate: Duration::from_millis(interpolate_ms),
+            };
+            let receiver_options = network::ReceiverOptions {
+                session_policy,
+                allowed_senders,
+                share_clipboard,
+                max_event_age: Duration::from_millis(max_event_age_ms),
+                bind_address: Some(bind_address),
+            };
+            start_receiver(port, options, receiver_options, quiet_hours, psk_file).await?;
         }
         Commands::Template { config } => {
             config::Config::create_template(&config)?;
             info!("Template config created at {:?}", config);
         }
+        Commands::Sniff {
+            port,
+            event_type,
+            psk_file,
+        } => {
+            info!("Sniffing events on port {}", port);
+            sniff(port, event_type, psk_file).await?;
+        }
+        Commands::Permissions => {
+            if !permissions::report() {
+                std::process::exit(1);
+            }
+        }
+        Commands::Status => match query::query().await? {
+            Some(status) => {
+                println!("{} ({:.0}, {:.0})", status.side, status.x, status.y);
+                let peer = &status.peer;
+                match (peer.connected, peer.last_seen_ms, peer.rtt_ms) {
+                    (true, _, Some(rtt)) => println!("receiver connected, RTT {:.1} ms", rtt),
+                    (false, Some(last_seen), _) => {
+                        println!(
+                            "receiver lost, last seen {:.1}s ago",
+                            last_seen as f64 / 1000.0
+                        )
+                    }
+                    _ => println!("receiver not seen yet"),
+                }
+                std::process::exit(if status.side == "local" { 0 } else { 1 });
+            }
+            None => {
+                eprintln!("No running sender found at {:?}", query::socket_path());
+                std::process::exit(2);
+            }
+        },
+        Commands::InstallService {
+            mode,
+            config,
+            port,
+            output,
+        } => {
+            let definition = service::render(mode, &config, port, &cli.log_level)?;
+            match output {
+                Some(path) => {
+                    std::fs::write(&path, definition)?;
+                    info!("Service definition written to {:?}", path);
+                }
+                None => print!("{}", definition),
+            }
+        }
+        Commands::Replay { file, speed } => {
+            info!("Replaying {:?} at {}x", file, speed);
+            replay(&file, speed).await?;
+        }
     }
 
     Ok(())
 }
 
-#[cfg(target_os = "macos")]
+async fn sniff(
+    port: u16,
+    event_type: Option<String>,
+    psk_file: Option<PathBuf>,
+) -> anyhow::Result<()> {
+    use tokio::sync::mpsc;
+
+    let cipher = psk_file
+        .map(|path| crypto::Cipher::from_file(&path))
+        .transpose()?;
+
+    let (network_tx, mut network_rx) = mpsc::unbounded_channel();
+    let network_receiver = network::NetworkReceiver::new(
+        port,
+        network::ReceiverOptions::default(),
+        network::ReceiverInfo::default(),
+        cipher,
+    );
+
+    tokio::spawn(async move {
+        if let Err(e) = network_receiver.sniff(network_tx).await {
+            error!("Network receiver error: {}", e);
+        }
+    });
+
+    let started = std::time::Instant::now();
+    println!(
+        "{:>10}  {:<21} {:>10} {:>10}  {:<14} {:>9} {:>9} {:>6} {:>6}",
+        "time_ms", "from", "seq", "sent_ms", "type", "x", "y", "dx", "dy"
+    );
+    while let Some(received) = network_rx.recv().await {
+        let event = &received.event;
+        if let Some(filter) = &event_type {
+            if !event.kind().eq_ignore_ascii_case(filter) {
+                continue;
+            }
+        }
+        let (x, y, dx, dy) = match event {
+            event::MouseEvent::Move { x, y } => (
+                format!("{:.1}", x),
+                format!("{:.1}", y),
+                String::new(),
+                String::new(),
+            ),
+            event::MouseEvent::Scroll {
+                x,
+                y,
+                delta_x,
+                delta_y,
+            } => (
+                format!("{:.1}", x),
+                format!("{:.1}", y),
+                delta_x.to_string(),
+                delta_y.to_string(),
+            ),
+            _ => (String::new(), String::new(), String::new(), String::new()),
+        };
+        // 通し番号や送信時刻を付けない送信側もある
+        let optional = |value: Option<String>| value.unwrap_or_else(|| "-".to_string());
+        println!(
+            "{:>10}  {:<21} {:>10} {:>10}  {:<14} {:>9} {:>9} {:>6} {:>6}",
+            started.elapsed().as_millis(),
+            received.addr.to_string(),
+            optional(received.seq.map(|seq| seq.to_string())),
+            optional(received.sent_ms.map(|ms| ms.to_string())),
+            event.kind(),
+            x,
+            y,
+            dx,
+            dy
+        );
+    }
+
+    Ok(())
+}
+
+/// 送信側を起動する
 async fn start_sender(config: config::Config) -> anyhow::Result<()> {
     use std::sync::{Arc, Mutex};
     use tokio::sync::mpsc;
@@ -75,43 +394,316 @@ async fn start_sender(config: config::Config) -> anyhow::Result<()> {
 
     let (network_tx, network_rx) = mpsc::unbounded_channel();
 
-    let capturer = capturer::macos::MacOSCapturer::new();
+    #[cfg(target_os = "macos")]
+    let capturer = capturer::macos::MacOSCapturer::new(
+        config.poll_interval_ms,
+        config.move_threshold,
+        config.secondary_move_threshold,
+        config.edge_margin,
+    );
+    #[cfg(target_os = "linux")]
+    let capturer = capturer::linux::LinuxCapturer::new(
+        config.capture_device.clone(),
+        config.keyboard_device.clone(),
+    );
 
     let network_sender = network::NetworkSender::new(config.clone());
 
+    let query_config = config.clone();
+    let query_model = virtual_model.clone();
+    let heartbeat = network_sender.heartbeat();
     tokio::spawn(async move {
-        if let Err(e) = capturer
-            .start_capture_with_model(&config, network_tx, virtual_model)
-            .await
-        {
-            error!("Capture error: {}", e);
+        if let Err(e) = query::serve(query_config, query_model, heartbeat).await {
+            error!("Query socket error: {}", e);
         }
     });
 
-    network_sender.start(network_rx).await?;
+    // 双方向モードでは、相手のsenderからのイベントもこのプロセスで注入する
+    let peer = {
+        let (config, vm) = (config.clone(), virtual_model.clone());
+        async move {
+            match config.listen_port {
+                Some(port) => {
+                    info!("Also receiving on port {} (bidirectional)", port);
+                    start_peer_receiver(port, &config, vm).await
+                }
+                None => std::future::pending().await,
+            }
+        }
+    };
+    tokio::pin!(peer);
 
-    Ok(())
+    let capturer = Arc::new(capturer);
+    let mut capture = tokio::spawn({
+        let capturer = capturer.clone();
+        async move {
+            if let Err(e) = capturer
+                .start_capture_with_model(&config, network_tx, virtual_model)
+                .await
+            {
+                error!("Capture error: {}", e);
+            }
+        }
+    });
+
+    let sending = network_sender.start(network_rx);
+    tokio::pin!(sending);
+    // キャプチャが止まったら、動いているように見えたまま残らないようプロセスごと終了する
+    let result = tokio::select! {
+        result = &mut sending => Some(result),
+        _ = &mut capture => anyhow::bail!("Mouse capture stopped, shutting down"),
+        Err(e) = &mut peer => Some(Err(e)),
+        _ = shutdown_signal() => {
+            info!("Shutting down");
+            None
+        }
+    };
+    // キャプチャを止め、掴んだデバイスを離して制御をホストに戻してから終わる
+    capturer.stop_capture();
+    let _ = capture.await;
+    match result {
+        Some(result) => result,
+        // キャプチャが手放したチャネルが閉じるまで、残りのイベント（制御の返却など）を送り切る
+        None => match tokio::time::timeout(SHUTDOWN_FLUSH_TIMEOUT, &mut sending).await {
+            Ok(result) => result,
+            Err(_) => {
+                error!("Timed out sending the remaining events to the receiver");
+                Ok(())
+            }
+        },
+    }
 }
 
-#[cfg(target_os = "linux")]
-async fn start_sender(_: config::Config) -> anyhow::Result<()> {
-    todo!()
+/// 送信側は押下中のボタンを1秒ごとに送ってくるので、これだけ途絶えたらボタンを離す
+///
+/// 双方向モードでは、相手からの操作がこれだけ途絶えたら操作されていないとみなす
+const SENDER_SILENCE_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// 補間中にカーソル位置を注入する間隔
+const INTERPOLATION_FRAME: Duration = Duration::from_millis(8);
+
+/// 終了時に残りのイベントを送り切るのを待つ時間
+const SHUTDOWN_FLUSH_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// SIGINTかSIGTERMを受け取るまで待つ
+async fn shutdown_signal() {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    match signal(SignalKind::terminate()) {
+        Ok(mut terminate) => {
+            tokio::select! {
+                _ = tokio::signal::ctrl_c() => {}
+                _ = terminate.recv() => {}
+            }
+        }
+        Err(e) => {
+            error!("Failed to listen for SIGTERM: {}", e);
+            let _ = tokio::signal::ctrl_c().await;
+        }
+    }
+}
+
+/// 受信側を起動する。注入はプラットフォームごとの`native_injector`で行う
+async fn start_receiver(
+    port: u16,
+    options: injector::InjectorOptions,
+    receiver_options: network::ReceiverOptions,
+    quiet_hours: Option<schedule::QuietHours>,
+    psk_file: Option<PathBuf>,
+) -> anyhow::Result<()> {
+    // 鍵を読めないまま平文を受け付けることがないよう、注入の準備より先に読む
+    let cipher = psk_file
+        .map(|path| crypto::Cipher::from_file(&path))
+        .transpose()?;
+    let injector: Box<dyn MouseInjector> = if options.dry_run {
+        info!("Dry run: events will only be logged");
+        Box::new(injector::LoggingInjector)
+    } else {
+        match native_injector(&options) {
+            Ok(injector) => injector,
+            Err(e) => {
+                // 送信側に注入できないことを伝えるため、終了せずにログだけ出す
+                error!(
+                    "No working injection backend, events will only be logged: {}",
+                    e
+                );
+                Box::new(injector::LoggingInjector)
+            }
+        }
+    };
+    if let Some(backend) = injector.backend() {
+        info!("Injecting with {}", backend);
+    }
+    let injector = with_recording(injector, &options)?;
+    let screen = options.screen_size.or_else(injector::screen_size);
+    let injector: Box<dyn MouseInjector> = match (options.clamp_to_screen, screen) {
+        (true, Some(screen)) => Box::new(injector::ClampInjector::new(injector, screen)),
+        (true, None) => {
+            error!("Cannot clamp to the screen: its size is unknown, pass --screen-size");
+            injector
+        }
+        (false, _) => injector,
+    };
+    let injector = Box::new(injector::ButtonSyncInjector::new(Box::new(
+        injector::LockSyncInjector::new(injector),
+    )));
+    let network_receiver = network::NetworkReceiver::new(
+        port,
+        receiver_options,
+        network::ReceiverInfo {
+            backend: injector.backend().map(String::from),
+            screen,
+        },
+        cipher,
+    );
+    run_receiver(
+        network_receiver,
+        injector,
+        quiet_hours,
+        None,
+        options.interpolate,
+    )
+    .await
+}
+
+/// 双方向モードで、相手のsenderからのイベントを受信してこのマシンに注入する
+async fn start_peer_receiver(
+    port: u16,
+    config: &config::Config,
+    virtual_model: SharedVirtualModel,
+) -> anyhow::Result<()> {
+    let injector = native_injector(&injector::InjectorOptions::default())?;
+    if let Some(backend) = injector.backend() {
+        info!("Injecting with {}", backend);
+    }
+    let injector = Box::new(injector::ButtonSyncInjector::new(Box::new(
+        injector::LockSyncInjector::new(injector),
+    )));
+    let network_receiver = network::NetworkReceiver::new(
+        port,
+        network::ReceiverOptions {
+            share_clipboard: config.share_clipboard,
+            bind_address: config.bind_address,
+            allowed_senders: config.allowed_senders.clone(),
+            ..Default::default()
+        },
+        network::ReceiverInfo {
+            backend: injector.backend().map(String::from),
+            screen: injector::screen_size(),
+        },
+        config.psk.as_deref().map(crypto::Cipher::new),
+    );
+    run_receiver(
+        network_receiver,
+        injector,
+        config.quiet_hours.clone(),
+        Some(virtual_model),
+        Duration::ZERO,
+    )
+    .await
 }
 
 #[cfg(target_os = "macos")]
-async fn start_receiver(_: u16) -> anyhow::Result<()> {
-    todo!()
+fn native_injector(options: &injector::InjectorOptions) -> anyhow::Result<Box<dyn MouseInjector>> {
+    Ok(Box::new(injector::macos::MacOSInjector::new(options)?))
 }
 
 #[cfg(target_os = "linux")]
-async fn start_receiver(port: u16) -> anyhow::Result<()> {
-    use tokio::sync::mpsc;
+fn native_injector(options: &injector::InjectorOptions) -> anyhow::Result<Box<dyn MouseInjector>> {
+    if injector::linux::is_x11_session() {
+        match injector::linux::X11Injector::new(options) {
+            Ok(injector) => return Ok(Box::new(injector)),
+            Err(e) => log::warn!(
+                "X11 session but XTest is unavailable ({}), trying uinput",
+                e
+            ),
+        }
+    }
+    match injector::linux::UinputInjector::new(options) {
+        Ok(injector) => return Ok(Box::new(injector)),
+        // /dev/uinputに書き込めない環境ではydotoolに頼る
+        Err(e) => log::warn!("uinput is unavailable ({}), falling back to ydotool", e),
+    }
+    Ok(Box::new(injector::linux::LinuxInjector::new(options)?))
+}
 
-    let (network_tx, mut network_rx) = mpsc::unbounded_channel();
+/// `1920x1080` 形式の画面サイズ
+fn parse_screen_size(s: &str) -> Result<(u32, u32), String> {
+    let (width, height) = s
+        .split_once('x')
+        .ok_or_else(|| format!("expected WIDTHxHEIGHT, got {:?}", s))?;
+    let width: u32 = width.parse().map_err(|e| format!("invalid width: {}", e))?;
+    let height: u32 = height
+        .parse()
+        .map_err(|e| format!("invalid height: {}", e))?;
+    if width == 0 || height == 0 {
+        return Err(format!("screen size must be non-zero, got {}", s));
+    }
+    Ok((width, height))
+}
+
+/// 記録先が指定されていれば、注入したイベントを記録するようにする
+fn with_recording(
+    injector: Box<dyn MouseInjector>,
+    options: &injector::InjectorOptions,
+) -> anyhow::Result<Box<dyn MouseInjector>> {
+    match &options.record {
+        Some(path) => {
+            info!("Recording injected events to {:?}", path);
+            let writer = recording::RecordingWriter::create(path, options.record_compress)?;
+            Ok(Box::new(recording::RecordingInjector::new(
+                injector, writer,
+            )))
+        }
+        None => Ok(injector),
+    }
+}
+
+/// 記録したイベントを元のタイミングで注入する
+async fn replay(path: &std::path::Path, speed: f64) -> anyhow::Result<()> {
+    if speed.is_nan() || speed <= 0.0 {
+        anyhow::bail!("speed must be positive, got {}", speed);
+    }
+    let mut reader = recording::RecordingReader::open(path)?;
+    let mut injector = native_injector(&injector::InjectorOptions::default())?;
+    let started = tokio::time::Instant::now();
+    while let Some(recorded) = reader.next_event()? {
+        tokio::time::sleep_until(started + recorded.elapsed.div_f64(speed)).await;
+        if let Err(e) = injector.inject_event(recorded.event) {
+            error!("Injection error: {}", e);
+        }
+    }
+    info!("Replay finished");
+    Ok(())
+}
+
+/// 注入する
+///
+/// クリック間隔の調整やydotoolの再試行はスレッドを止めて待つので、
+/// その間も受信やタイマーが進むよう、待つ間はワーカースレッドを他のタスクに明け渡す
+fn inject(injector: &mut dyn MouseInjector, event: event::MouseEvent) -> anyhow::Result<()> {
+    tokio::task::block_in_place(|| injector.inject_event(event))
+}
 
-    let mut injector = injector::linux::LinuxInjector::new()?;
+/// 双方向モードで、相手に操作されているかを仮想マウスに伝える
+fn set_remote_controlled(virtual_model: &Option<SharedVirtualModel>, controlled: bool) {
+    if let Some(Ok(mut vm)) = virtual_model.as_ref().map(|vm| vm.lock()) {
+        vm.remote_controlled = controlled;
+    }
+}
 
-    let network_receiver = network::NetworkReceiver::new(port);
+/// 受信したイベントを任意のインジェクタに流し込む
+async fn run_receiver(
+    network_receiver: network::NetworkReceiver,
+    mut injector: Box<dyn MouseInjector>,
+    quiet_hours: Option<schedule::QuietHours>,
+    virtual_model: Option<SharedVirtualModel>,
+    interpolate: Duration,
+) -> anyhow::Result<()> {
+    use event::MouseEvent;
+    use tokio::sync::mpsc;
+
+    let (network_tx, mut network_rx) = mpsc::unbounded_channel();
 
     tokio::spawn(async move {
         if let Err(e) = network_receiver.start(network_tx).await {
@@ -119,9 +711,135 @@ async fn start_receiver(port: u16) -> anyhow::Result<()> {
         }
     });
 
-    while let Some(event) = network_rx.recv().await {
-        if let Err(e) = injector.inject_event(event) {
-            error!("Injection error: {}", e);
+    let mut quiet = schedule::QuietGate::new(quiet_hours);
+    // 注入したボタンの押下状態（静音時間帯に新たに押さないようにするため）
+    let mut held = 0u8;
+    // 双方向モードで、相手に操作されているとみなす期限。ControlLeaveを取りこぼしても移譲できなくならないようにする
+    let mut controlled_until: Option<tokio::time::Instant> = None;
+    let mut interpolator = injector::Interpolator::new(interpolate);
+    let mut frames = tokio::time::interval(INTERPOLATION_FRAME);
+    frames.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+    let shutdown = shutdown_signal();
+    tokio::pin!(shutdown);
+    loop {
+        let event = tokio::select! {
+            event = network_rx.recv() => match event {
+                Some(event) => event,
+                None => break,
+            },
+            _ = tokio::time::sleep(SENDER_SILENCE_TIMEOUT) => {
+                // 送信側が落ちたりリリースが届かなかったりしても、押しっぱなしにしない
+                if let Err(e) = inject(injector.as_mut(), event::MouseEvent::Buttons { held: 0 }) {
+                    error!("Failed to release buttons: {}", e);
+                }
+                held = 0;
+                continue;
+            }
+            _ = tokio::time::sleep_until(controlled_until.unwrap_or_else(tokio::time::Instant::now)),
+                if controlled_until.is_some() =>
+            {
+                info!("No input from the sender for {:?}, allowing transfers to it again", SENDER_SILENCE_TIMEOUT);
+                controlled_until = None;
+                set_remote_controlled(&virtual_model, false);
+                continue;
+            }
+            _ = frames.tick(), if interpolator.is_active() => {
+                if let Some((x, y)) = interpolator.frame() {
+                    if let Err(e) = inject(injector.as_mut(), MouseEvent::Move { x, y }) {
+                        error!("Injection error: {}", e);
+                    }
+                }
+                continue;
+            }
+            _ = &mut shutdown => {
+                info!("Shutting down");
+                // 送信側が止まる前に押されたボタンが押しっぱなしにならないよう離す
+                if let Err(e) = inject(injector.as_mut(), event::MouseEvent::Buttons { held: 0 }) {
+                    error!("Failed to release buttons: {}", e);
+                }
+                break;
+            }
+        };
+        let event = if quiet.is_quiet() {
+            // 押しっぱなしや制御中のまま止まらないよう、離す方向のイベントだけは通す
+            match event {
+                MouseEvent::Buttons { held: sync } => MouseEvent::Buttons { held: held & sync },
+                event if event.is_release() => event,
+                _ => continue,
+            }
+        } else {
+            event
+        };
+        match &event {
+            MouseEvent::Buttons { held: sync } => held = *sync,
+            // ButtonSyncInjectorが残ったボタンを離す
+            MouseEvent::ControlLeave => held = 0,
+            event => {
+                if let Some((button, pressed)) = event.button() {
+                    if pressed {
+                        held |= button;
+                    } else {
+                        held &= !button;
+                    }
+                }
+            }
+        }
+        // 双方向モードでは、相手に操作されている間こちらのキャプチャから移譲しない
+        if virtual_model.is_some() {
+            match &event {
+                MouseEvent::ControlLeave => {
+                    controlled_until = None;
+                    set_remote_controlled(&virtual_model, false);
+                }
+                // 送信側は制御中にしか操作を送ってこない
+                event
+                    if event.button().is_some()
+                        || matches!(
+                            event,
+                            MouseEvent::ControlEnter
+                                | MouseEvent::Move { .. }
+                                | MouseEvent::Scroll { .. }
+                                | MouseEvent::Key { .. }
+                        ) =>
+                {
+                    controlled_until = Some(tokio::time::Instant::now() + SENDER_SILENCE_TIMEOUT);
+                    set_remote_controlled(&virtual_model, true);
+                }
+                _ => {}
+            }
+        }
+        let event = match event {
+            MouseEvent::Move { x, y } => match interpolator.target(x, y) {
+                Some((x, y)) => MouseEvent::Move { x, y },
+                None => continue,
+            },
+            MouseEvent::ControlEnter | MouseEvent::ControlLeave => {
+                interpolator.reset();
+                event
+            }
+            event if event.button().is_some() || matches!(event, MouseEvent::Scroll { .. }) => {
+                // クリックは補間の途中ではなく、送信側が意図した位置で注入する
+                if let Some((x, y)) = interpolator.finish() {
+                    if let Err(e) = inject(injector.as_mut(), MouseEvent::Move { x, y }) {
+                        error!("Injection error: {}", e);
+                    }
+                }
+                event
+            }
+            event => event,
+        };
+        match inject(injector.as_mut(), event) {
+            Ok(()) => {
+                metrics::METRICS
+                    .events_injected
+                    .fetch_add(1, Ordering::Relaxed);
+            }
+            Err(e) => {
+                metrics::METRICS
+                    .inject_errors
+                    .fetch_add(1, Ordering::Relaxed);
+                error!("Injection error: {}", e);
+            }
         }
     }
 
@@ -0,0 +1,90 @@
+use anyhow::Result;
+use std::fmt::Write as _;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::atomic::{AtomicU64, Ordering};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+/// 送信側と受信側で共有する集計。送受信のたびに加算するだけなのでロックは使わない
+pub struct Metrics {
+    pub events_sent: AtomicU64,
+    pub bytes_sent: AtomicU64,
+    pub send_errors: AtomicU64,
+    pub events_received: AtomicU64,
+    pub events_injected: AtomicU64,
+    pub inject_errors: AtomicU64,
+    pub decode_errors: AtomicU64,
+    /// 直近のハートビートの往復時間（マイクロ秒）。まだ測れていなければ0
+    pub rtt_us: AtomicU64,
+}
+
+pub static METRICS: Metrics = Metrics {
+    events_sent: AtomicU64::new(0),
+    bytes_sent: AtomicU64::new(0),
+    send_errors: AtomicU64::new(0),
+    events_received: AtomicU64::new(0),
+    events_injected: AtomicU64::new(0),
+    inject_errors: AtomicU64::new(0),
+    decode_errors: AtomicU64::new(0),
+    rtt_us: AtomicU64::new(0),
+};
+
+impl Metrics {
+    /// Prometheusのテキスト形式で書き出す
+    fn render(&self) -> String {
+        let entries = [
+            ("events_sent_total", "counter", &self.events_sent),
+            ("bytes_sent_total", "counter", &self.bytes_sent),
+            ("send_errors_total", "counter", &self.send_errors),
+            ("events_received_total", "counter", &self.events_received),
+            ("events_injected_total", "counter", &self.events_injected),
+            ("inject_errors_total", "counter", &self.inject_errors),
+            ("decode_errors_total", "counter", &self.decode_errors),
+            ("rtt_microseconds", "gauge", &self.rtt_us),
+        ];
+        let mut out = String::new();
+        for (name, kind, value) in entries {
+            let _ = writeln!(out, "# TYPE sharemouse_{} {}", name, kind);
+            let _ = writeln!(out, "sharemouse_{} {}", name, value.load(Ordering::Relaxed));
+        }
+        out
+    }
+}
+
+/// `/metrics`をHTTPで返し続ける
+pub async fn serve(port: u16) -> Result<()> {
+    let addr = SocketAddr::from((Ipv4Addr::UNSPECIFIED, port));
+    let listener = tokio::net::TcpListener::bind(addr)
+        .await
+        .map_err(|e| anyhow::anyhow!("Failed to listen for metrics on {}: {}", addr, e))?;
+    log::info!("Serving metrics on http://{}/metrics", addr);
+    loop {
+        let (mut stream, peer) = listener.accept().await?;
+        tokio::spawn(async move {
+            // リクエスト行だけ見ればよいので、先頭を読めれば十分
+            let mut buf = [0u8; 1024];
+            let len = match stream.read(&mut buf).await {
+                Ok(len) => len,
+                Err(e) => {
+                    log::debug!("Failed to read metrics request from {}: {}", peer, e);
+                    return;
+                }
+            };
+            let request = String::from_utf8_lossy(&buf[..len]);
+            let path = request.split_whitespace().nth(1).unwrap_or("");
+            let response = if path == "/metrics" {
+                let body = METRICS.render();
+                format!(
+                    "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    body.len(),
+                    body
+                )
+            } else {
+                "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+                    .to_string()
+            };
+            if let Err(e) = stream.write_all(response.as_bytes()).await {
+                log::debug!("Failed to write metrics response to {}: {}", peer, e);
+            }
+        });
+    }
+}
@@ -1,85 +1,2166 @@
-use crate::config::Config;
-use crate::event::MouseEvent;
+use crate::clipboard;
+use crate::config::{Config, LocalBind, RemoteLaunch, SessionPolicy, Transport, WireFormat};
+use crate::crypto::{Cipher, ReplayGuard};
+use crate::event::{MouseEvent, SCROLL_UNITS_PER_NOTCH};
+use crate::injector::ScrollAccumulator;
+use crate::metrics::METRICS;
+use crate::schedule::QuietGate;
 use anyhow::Result;
-use std::net::SocketAddr;
-use tokio::net::{TcpListener, TcpStream, UdpSocket};
+use bincode::Options;
+use flate2::read::DeflateDecoder;
+use flate2::write::DeflateEncoder;
+use flate2::Compression;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::io::{Read, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::str::FromStr;
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpSocket, TcpStream, UdpSocket};
+use tokio::process::Command;
 use tokio::sync::mpsc;
 
+/// このビルドのプロトコルバージョン。ハンドシェイクで伝え合い、両方が対応している機能だけを使う
+///
+/// 1: `Hello`/`HelloAck`にバージョンがない。イベントは`Packet::Event`で送り、スクロール量はノッチ単位
+/// 2: 送信時刻付きの`Packet::TimedEvent`を受け付ける。スクロール量は1/`SCROLL_UNITS_PER_NOTCH`ノッチ単位。
+///    受信側は`Packet::HelloRequest`でハンドシェイクのやり直しを求められる
+const PROTOCOL_VERSION: u32 = 2;
+
+/// バージョンを伝えてこない相手のプロトコルバージョン
+fn legacy_version() -> u32 {
+    1
+}
+
+/// ハンドシェイクの応答待ち時間
+const HELLO_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// SSHでreceiverを起動したあと、コマンドの終了を待つ時間
+const REMOTE_LAUNCH_WAIT: Duration = Duration::from_secs(2);
+
+/// 押下中のボタンを受信側と同期する間隔
+const BUTTON_SYNC_INTERVAL: Duration = Duration::from_secs(1);
+
+/// 送信の集計をログに出す間隔
+const STATS_INTERVAL: Duration = Duration::from_secs(10);
+
+/// 受信側の生存確認を送る間隔
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(2);
+
+/// この時間応答がなければ受信側を見失ったとみなす
+const HEARTBEAT_TIMEOUT: Duration = Duration::from_secs(6);
+
+/// 再接続を試みる間隔の初期値と上限
+const RECONNECT_BACKOFF: Duration = Duration::from_millis(250);
+const RECONNECT_BACKOFF_MAX: Duration = Duration::from_secs(5);
+
+/// イベントの圧縮方式
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Codec {
+    None,
+    Deflate,
+}
+
+/// 自分が対応している圧縮方式（優先順）
+const SUPPORTED_CODECS: &[Codec] = &[Codec::Deflate, Codec::None];
+
+impl Codec {
+    /// 相手の対応リストと突き合わせて使用する方式を決める
+    fn negotiate(remote: &[Codec]) -> Codec {
+        SUPPORTED_CODECS
+            .iter()
+            .copied()
+            .find(|codec| remote.contains(codec))
+            .unwrap_or(Codec::None)
+    }
+
+    fn compress(&self, data: &[u8]) -> Result<Vec<u8>> {
+        match self {
+            Codec::None => Ok(data.to_vec()),
+            Codec::Deflate => {
+                let mut encoder = DeflateEncoder::new(Vec::new(), Compression::fast());
+                encoder.write_all(data)?;
+                Ok(encoder.finish()?)
+            }
+        }
+    }
+
+    fn decompress(&self, data: &[u8]) -> Result<Vec<u8>> {
+        match self {
+            Codec::None => Ok(data.to_vec()),
+            Codec::Deflate => {
+                // 小さなパケットが巨大に展開されても、1フレーム分より先は読まない
+                let mut out = Vec::new();
+                DeflateDecoder::new(data)
+                    .take(MAX_FRAME_LEN as u64 + 1)
+                    .read_to_end(&mut out)?;
+                if out.len() > MAX_FRAME_LEN {
+                    anyhow::bail!("Decompressed packet exceeds limit {}", MAX_FRAME_LEN);
+                }
+                Ok(out)
+            }
+        }
+    }
+}
+
+/// ハンドシェイクで受信側が送信側に伝える情報
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ReceiverInfo {
+    /// 注入バックエンド。注入できないならNone
+    pub backend: Option<String>,
+    /// 受信側の実際の画面サイズ（取得できなければNone）
+    pub screen: Option<(u32, u32)>,
+}
+
+/// ネットワーク上でやり取りするパケット
+///
+/// 以前の送信側は`MouseEvent`を単体でbincodeにして送っていた。この形式は`Packet`と互換がないので、
+/// 読めなかったときだけ`MouseEvent`単体として読み直す（`decode_packet`）。
+/// 古い受信側は`Packet`を読めないため、送信側を先に更新してはいけない
+#[derive(Debug, Clone, Serialize, Deserialize)]
+enum Packet {
+    Hello {
+        codecs: Vec<Codec>,
+        /// 座標を受信側の画面に対する0..1の割合で送るか
+        normalized: bool,
+        /// bincodeは位置で読むので、フィールドは末尾にだけ追加する
+        #[serde(default = "legacy_version")]
+        version: u32,
+    },
+    HelloAck {
+        codec: Codec,
+        info: ReceiverInfo,
+        #[serde(default = "legacy_version")]
+        version: u32,
+    },
+    Event {
+        /// 送信側の起動ごとに変わるセッションID
+        epoch: u64,
+        /// セッション内の通し番号
+        seq: u32,
+        event: MouseEvent,
+    },
+    /// 圧縮された `Packet::Event`
+    Compressed(Vec<u8>),
+    /// 通し番号なしのイベント（JSONでスクリプトなどから送られる）。スクロール量はノッチ単位
+    Raw(MouseEvent),
+    /// 生存確認。受信側は同じidの`Pong`を返す
+    Ping {
+        id: u64,
+    },
+    Pong {
+        id: u64,
+    },
+    /// クリップボードのテキスト。制御が相手側に移るときに送る
+    Clipboard {
+        text: String,
+    },
+    /// 送信時刻付きの`Event`。受信側がバージョン2以上のときだけ送る
+    ///
+    /// `Event`と違い、スクロール量は1/`SCROLL_UNITS_PER_NOTCH`ノッチ単位
+    TimedEvent {
+        epoch: u64,
+        seq: u32,
+        /// 送信時刻（セッション開始からのミリ秒）
+        sent_ms: u64,
+        event: MouseEvent,
+    },
+    /// 取り決めた内容を持っていない受信側（再起動したなど）が、ハンドシェイクのやり直しを求める
+    HelloRequest {
+        version: u32,
+    },
+}
+
+impl Packet {
+    /// イベントを運ぶパケットなら、イベントと通し番号・送信時刻を取り出す
+    fn into_received(self, addr: SocketAddr) -> Option<ReceivedEvent> {
+        let (seq, sent_ms, event) = match self {
+            Packet::Event { seq, event, .. } => (Some(seq), None, event),
+            Packet::TimedEvent {
+                seq,
+                sent_ms,
+                event,
+                ..
+            } => (Some(seq), Some(sent_ms), event),
+            Packet::Raw(event) => (None, None, event),
+            _ => return None,
+        };
+        Some(ReceivedEvent {
+            addr,
+            seq,
+            sent_ms,
+            event,
+        })
+    }
+}
+
+/// 受信したイベントと、それを運んだパケットの情報（sniffで表示する）
+#[derive(Debug, Clone)]
+pub struct ReceivedEvent {
+    /// 送信元
+    pub addr: SocketAddr,
+    /// セッション内の通し番号。`Packet::Raw`にはない
+    pub seq: Option<u32>,
+    /// 送信時刻（送信側のセッション開始からのミリ秒）。バージョン2以上の受信側にだけ付く
+    pub sent_ms: Option<u64>,
+    pub event: MouseEvent,
+}
+
+/// プロトコルバージョン1の`Hello`/`HelloAck`。variantの順番を`Packet`と揃えている
+#[derive(Deserialize)]
+enum LegacyHandshake {
+    Hello {
+        codecs: Vec<Codec>,
+        normalized: bool,
+    },
+    HelloAck {
+        codec: Codec,
+        info: ReceiverInfo,
+    },
+}
+
+impl From<LegacyHandshake> for Packet {
+    fn from(legacy: LegacyHandshake) -> Self {
+        match legacy {
+            LegacyHandshake::Hello { codecs, normalized } => Packet::Hello {
+                codecs,
+                normalized,
+                version: legacy_version(),
+            },
+            LegacyHandshake::HelloAck { codec, info } => Packet::HelloAck {
+                codec,
+                info,
+                version: legacy_version(),
+            },
+        }
+    }
+}
+
+/// 受信側との接続状態（`sharemouse status`で表示する）
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PeerStatus {
+    /// 直近の生存確認に応答があるか
+    pub connected: bool,
+    /// 最後に応答があってからの時間（ミリ秒）
+    pub last_seen_ms: Option<u64>,
+    /// 直近の往復時間（ミリ秒）
+    pub rtt_ms: Option<f64>,
+}
+
+/// 生存確認の状態
+#[derive(Default)]
+pub struct Heartbeat {
+    next_id: u64,
+    /// 応答待ちのidと送信時刻
+    pending: Option<(u64, Instant)>,
+    last_seen: Option<Instant>,
+    rtt: Option<Duration>,
+    /// 見失ったことをログに出したか
+    lost: bool,
+}
+
+pub type SharedHeartbeat = Arc<Mutex<Heartbeat>>;
+
+impl Heartbeat {
+    /// 次に送る生存確認のid
+    fn ping(&mut self) -> u64 {
+        self.next_id = self.next_id.wrapping_add(1);
+        self.pending = Some((self.next_id, Instant::now()));
+        self.next_id
+    }
+
+    fn pong(&mut self, id: u64, remote_addr: SocketAddr) {
+        let Some((pending, sent)) = self.pending else {
+            return;
+        };
+        if pending != id {
+            return;
+        }
+        self.pending = None;
+        self.rtt = Some(sent.elapsed());
+        METRICS
+            .rtt_us
+            .store(sent.elapsed().as_micros() as u64, Ordering::Relaxed);
+        self.last_seen = Some(Instant::now());
+        if self.lost {
+            self.lost = false;
+            log::info!("Receiver at {} is answering heartbeats again", remote_addr);
+        }
+    }
+
+    fn is_connected(&self) -> bool {
+        self.last_seen
+            .is_some_and(|seen| seen.elapsed() < HEARTBEAT_TIMEOUT)
+    }
+
+    /// 応答が途絶えたら一度だけ警告し、`true`を返す
+    fn check(&mut self, remote_addr: SocketAddr) -> bool {
+        if self.last_seen.is_some() && !self.is_connected() && !self.lost {
+            self.lost = true;
+            log::warn!(
+                "!!! Receiver at {} stopped answering heartbeats !!!",
+                remote_addr
+            );
+            return true;
+        }
+        false
+    }
+
+    /// 接続し直した受信側がハンドシェイクに応答した
+    fn reconnected(&mut self) {
+        self.pending = None;
+        self.last_seen = Some(Instant::now());
+        self.lost = false;
+    }
+
+    pub fn status(&self) -> PeerStatus {
+        PeerStatus {
+            connected: self.is_connected(),
+            last_seen_ms: self.last_seen.map(|seen| seen.elapsed().as_millis() as u64),
+            rtt_ms: self.rtt.map(|rtt| rtt.as_secs_f64() * 1000.0),
+        }
+    }
+}
+
+/// bincodeのパケットを読む
+///
+/// 単体の`MouseEvent`は偶然`Packet`としても読めてしまうことがあるので、まず余りのバイトを許さずに
+/// `Packet`、バージョン1のハンドシェイク、`MouseEvent`の順に試す。
+/// どれでもなければ、末尾にフィールドが追加された新しい形式として読む
+fn decode_packet(data: &[u8]) -> Result<Packet> {
+    let exact = bincode::DefaultOptions::new().with_fixint_encoding();
+    if let Ok(packet) = exact.deserialize::<Packet>(data) {
+        return Ok(packet);
+    }
+    if let Ok(legacy) = exact.deserialize::<LegacyHandshake>(data) {
+        return Ok(legacy.into());
+    }
+    if let Ok(event) = exact.deserialize::<MouseEvent>(data) {
+        return Ok(Packet::Raw(event));
+    }
+    Ok(bincode::deserialize::<Packet>(data)?)
+}
+
+/// JSON形式の1行。パケットでなければ`MouseEvent`単体として読む
+///
+/// どちらでもなければ、新しい送信側が追加したフィールド付きのイベントとみなす
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum JsonLine {
+    Packet(Packet),
+    Event(MouseEvent),
+    Unknown(serde::de::IgnoredAny),
+}
+
+/// 受信したデータグラムをパケットに分解する
+///
+/// bincodeのパケットは先頭がvariant番号（u32 LE）なので、`{`や`"`で始まっていればJSONとみなす
+fn decode_datagram(data: &[u8]) -> Result<Vec<Packet>> {
+    let is_json = data
+        .iter()
+        .find(|b| !b.is_ascii_whitespace())
+        .is_some_and(|b| matches!(b, b'{' | b'"'));
+    if !is_json {
+        return Ok(vec![decode_packet(data)?]);
+    }
+    std::str::from_utf8(data)?
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| {
+            Ok(match serde_json::from_str::<JsonLine>(line)? {
+                JsonLine::Packet(packet) => packet,
+                JsonLine::Event(event) => Packet::Raw(event),
+                JsonLine::Unknown(_) => Packet::Raw(MouseEvent::Unknown),
+            })
+        })
+        .collect()
+}
+
+/// 受信したイベントの通し番号を追跡し、古いパケットを弾く
+#[derive(Default)]
+struct SeqTracker {
+    epoch: Option<u64>,
+    last_seq: Option<u32>,
+    /// 前回の集計から順序が入れ替わって捨てたパケット数
+    dropped: u64,
+    dropped_total: u64,
+}
+
+impl SeqTracker {
+    /// 受け入れるべきパケットなら`true`
+    fn accept(&mut self, epoch: u64, seq: u32) -> bool {
+        if self.epoch != Some(epoch) {
+            // 送信側が再起動したので通し番号の追跡をやり直す
+            if self.epoch.is_some() {
+                log::info!(
+                    "New sender session {:x}, resetting sequence tracking",
+                    epoch
+                );
+            }
+            self.epoch = Some(epoch);
+            self.last_seq = Some(seq);
+            return true;
+        }
+        match self.last_seq {
+            // 一周して0に戻っても新しいものと判断できるよう、差を符号付きで見る
+            Some(last) if seq.wrapping_sub(last) as i32 <= 0 => {
+                self.dropped += 1;
+                self.dropped_total += 1;
+                false
+            }
+            _ => {
+                self.last_seq = Some(seq);
+                true
+            }
+        }
+    }
+
+    /// 前回の集計から捨てたパケット数をログに出す
+    fn report(&mut self, addr: SocketAddr) {
+        if self.dropped > 0 {
+            log::info!(
+                "Dropped {} out-of-order packets from {} in the last {:?} ({} in total)",
+                self.dropped,
+                addr,
+                STATS_INTERVAL,
+                self.dropped_total
+            );
+            self.dropped = 0;
+        }
+    }
+}
+
+/// イベントが送信されてから届くまでの遅れを見積もる
+///
+/// 両マシンの時計は合っていないので、到着時刻と送信時刻の差のうち最小のものを遅れなしとみなす。
+/// 時計の進み方の差で基準がずれ続けないよう、基準は1秒に1msずつ緩める
+struct LagTracker {
+    started: Instant,
+    epoch: Option<u64>,
+    /// 遅れなしとみなす差と、それを記録した時刻（どちらも`started`からのミリ秒）
+    baseline: Option<(i64, i64)>,
+}
+
+impl LagTracker {
+    fn new() -> Self {
+        Self {
+            started: Instant::now(),
+            epoch: None,
+            baseline: None,
+        }
+    }
+
+    /// 今届いたイベントの遅れ
+    fn lag(&mut self, epoch: u64, sent_ms: u64) -> Duration {
+        if self.epoch != Some(epoch) {
+            self.epoch = Some(epoch);
+            self.baseline = None;
+        }
+        let now = self.started.elapsed().as_millis() as i64;
+        let offset = now - sent_ms as i64;
+        let baseline = match self.baseline {
+            Some((base, at)) => base + (now - at) / 1000,
+            None => offset,
+        };
+        if offset <= baseline {
+            self.baseline = Some((offset, now));
+            return Duration::ZERO;
+        }
+        Duration::from_millis((offset - baseline) as u64)
+    }
+}
+
+/// 受け取るUDPデータグラムの最大長（クリップボードが収まる大きさ）
+const MAX_DATAGRAM_LEN: usize = 64 * 1024;
+
+/// TCPフレームの最大長
+const MAX_FRAME_LEN: usize = 64 * 1024;
+
+/// ペイロードに長さプレフィックス（u32 BE）を付けてフレーム化する
+pub fn encode_frame(payload: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(4 + payload.len());
+    frame.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+    frame.extend_from_slice(payload);
+    frame
+}
+
+/// 長さプレフィックス付きフレームの組み立て
+///
+/// TCPの`read`は途中までのフレームを返すことがあるので、
+/// 1フレーム分揃うまでバイト列を溜めてから取り出す
+#[derive(Default)]
+pub struct FrameDecoder {
+    buf: Vec<u8>,
+}
+
+impl FrameDecoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 受信したバイト列を追加する
+    pub fn extend(&mut self, data: &[u8]) {
+        self.buf.extend_from_slice(data);
+    }
+
+    /// 完全なフレームが揃っていれば取り出す
+    pub fn next_frame(&mut self) -> Result<Option<Vec<u8>>> {
+        if self.buf.len() < 4 {
+            return Ok(None);
+        }
+        let len = u32::from_be_bytes([self.buf[0], self.buf[1], self.buf[2], self.buf[3]]) as usize;
+        if len > MAX_FRAME_LEN {
+            anyhow::bail!("Frame length {} exceeds limit {}", len, MAX_FRAME_LEN);
+        }
+        if self.buf.len() < 4 + len {
+            return Ok(None);
+        }
+        let frame = self.buf[4..4 + len].to_vec();
+        self.buf.drain(..4 + len);
+        Ok(Some(frame))
+    }
+}
+
+/// ストリームから1フレーム読み出す。接続が閉じられたら`None`
+pub async fn read_frame<R: AsyncRead + Unpin>(
+    reader: &mut R,
+    decoder: &mut FrameDecoder,
+) -> Result<Option<Vec<u8>>> {
+    let mut chunk = [0u8; 4096];
+    loop {
+        if let Some(frame) = decoder.next_frame()? {
+            return Ok(Some(frame));
+        }
+        let len = reader.read(&mut chunk).await?;
+        if len == 0 {
+            return Ok(None);
+        }
+        decoder.extend(&chunk[..len]);
+    }
+}
+
+/// 送信側から受信側への接続
+enum Link {
+    Udp(UdpSocket),
+    Tcp {
+        stream: TcpStream,
+        decoder: FrameDecoder,
+    },
+}
+
+impl Link {
+    async fn connect(
+        transport: Transport,
+        remote_addr: SocketAddr,
+        local_bind: Option<LocalBind>,
+    ) -> Result<Self> {
+        if let Some(local_bind) = local_bind {
+            if local_bind.address.is_ipv6() != remote_addr.is_ipv6() {
+                anyhow::bail!(
+                    "local_bind address {} is not in the same address family as {}",
+                    local_bind.address,
+                    remote_addr
+                );
+            }
+        }
+        match transport {
+            Transport::Udp => {
+                let local = match local_bind {
+                    Some(local_bind) => local_bind.socket_addr(),
+                    // 送信先と同じアドレスファミリーで開く
+                    None if remote_addr.is_ipv6() => (Ipv6Addr::UNSPECIFIED, 0).into(),
+                    None => (Ipv4Addr::UNSPECIFIED, 0).into(),
+                };
+                let socket = UdpSocket::bind(local).await.map_err(|e| {
+                    anyhow::anyhow!("Failed to bind UDP socket to {}: {}", local, e)
+                })?;
+                log::info!(
+                    "UDP socket bound to {}, will send to {}",
+                    socket.local_addr()?,
+                    remote_addr
+                );
+                Ok(Link::Udp(socket))
+            }
+            Transport::Tcp => {
+                let connect = async {
+                    let Some(local_bind) = local_bind else {
+                        return TcpStream::connect(remote_addr).await;
+                    };
+                    let socket = if remote_addr.is_ipv6() {
+                        TcpSocket::new_v6()?
+                    } else {
+                        TcpSocket::new_v4()?
+                    };
+                    // 再接続のたびに同じ送信元ポートを使えるようにする
+                    socket.set_reuseaddr(true)?;
+                    socket.bind(local_bind.socket_addr())?;
+                    socket.connect(remote_addr).await
+                };
+                let stream = tokio::time::timeout(HELLO_TIMEOUT, connect)
+                    .await
+                    .map_err(|_| anyhow::anyhow!("Timed out connecting to {}", remote_addr))??;
+                // 小さなイベントを溜めずにすぐ送る
+                stream.set_nodelay(true)?;
+                log::info!(
+                    "TCP connected from {} to {}",
+                    stream.local_addr()?,
+                    remote_addr
+                );
+                Ok(Link::Tcp {
+                    stream,
+                    decoder: FrameDecoder::new(),
+                })
+            }
+        }
+    }
+
+    /// 1パケット送る。送ったバイト数を返す
+    async fn send(&mut self, data: &[u8], remote_addr: SocketAddr) -> Result<usize> {
+        match self {
+            Link::Udp(socket) => Ok(socket.send_to(data, remote_addr).await?),
+            Link::Tcp { stream, .. } => {
+                let frame = encode_frame(data);
+                stream.write_all(&frame).await?;
+                Ok(frame.len())
+            }
+        }
+    }
+
+    /// 切断を検知できる接続か
+    fn is_stream(&self) -> bool {
+        matches!(self, Link::Tcp { .. })
+    }
+
+    /// 1パケット受け取る。UDPで接続先以外から届いたもの、TCPで切断されたときは`None`
+    async fn recv(&mut self, remote_addr: SocketAddr) -> Result<Option<Vec<u8>>> {
+        match self {
+            Link::Udp(socket) => {
+                let mut buf = vec![0u8; MAX_DATAGRAM_LEN];
+                let (len, addr) = socket.recv_from(&mut buf).await?;
+                Ok((addr == remote_addr).then(|| buf[..len].to_vec()))
+            }
+            Link::Tcp { stream, decoder } => read_frame(stream, decoder).await,
+        }
+    }
+}
+
 pub struct NetworkSender {
     config: Config,
+    heartbeat: SharedHeartbeat,
+    /// pskが設定されていれば全てのパケットを暗号化する
+    cipher: Option<Cipher>,
 }
 
 impl NetworkSender {
     pub fn new(config: Config) -> Self {
-        Self { config }
+        let cipher = config.psk.as_deref().map(Cipher::new);
+        Self {
+            config,
+            heartbeat: SharedHeartbeat::default(),
+            cipher,
+        }
+    }
+
+    /// 受信側との接続状態。`query::serve`に渡す
+    pub fn heartbeat(&self) -> SharedHeartbeat {
+        self.heartbeat.clone()
+    }
+
+    /// 送信形式に合わせてパケットをエンコードする
+    fn encode(&self, packet: &Packet) -> Result<Vec<u8>> {
+        let data = match self.config.wire_format {
+            WireFormat::Bincode => bincode::serialize(packet)?,
+            WireFormat::Json => {
+                let mut line = serde_json::to_vec(packet)?;
+                line.push(b'\n');
+                line
+            }
+        };
+        seal(self.cipher.as_ref(), data)
+    }
+
+    /// 受信側からの返信をデコードする
+    fn decode_reply(&self, data: &[u8]) -> Result<Packet> {
+        match &self.cipher {
+            Some(cipher) => decode_packet(&cipher.open(data)?.payload),
+            None => decode_packet(data),
+        }
+    }
+
+    /// 受信側と圧縮方式を取り決める。応答がなければ`None`、あれば圧縮方式と受信側のプロトコルバージョン
+    async fn handshake(
+        &self,
+        link: &mut Link,
+        remote_addr: SocketAddr,
+    ) -> Result<Option<(Codec, u32)>> {
+        let hello = seal(
+            self.cipher.as_ref(),
+            bincode::serialize(&Packet::Hello {
+                codecs: SUPPORTED_CODECS.to_vec(),
+                normalized: self.config.normalized_coordinates,
+                version: PROTOCOL_VERSION,
+            })?,
+        )?;
+        link.send(&hello, remote_addr).await?;
+
+        match tokio::time::timeout(HELLO_TIMEOUT, link.recv(remote_addr)).await {
+            Ok(Ok(Some(data))) => match self.decode_reply(&data) {
+                Ok(Packet::HelloAck {
+                    codec,
+                    info,
+                    version,
+                }) => {
+                    self.check_receiver(&info, remote_addr);
+                    Ok(Some((codec, version)))
+                }
+                _ => Ok(Some((Codec::None, legacy_version()))),
+            },
+            Ok(Err(e)) => {
+                // ICMP port unreachableが返ってくる環境ではここに来る
+                log::debug!("Handshake with {} failed: {}", remote_addr, e);
+                Ok(None)
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// 接続してハンドシェイクする。接続できなければ`Err`、応答がなければ`Ok(None)`
+    async fn connect(&self, remote_addr: SocketAddr) -> Result<(Link, Option<(Codec, u32)>)> {
+        let mut link =
+            Link::connect(self.config.transport, remote_addr, self.config.local_bind).await?;
+        let reply = self.handshake(&mut link, remote_addr).await?;
+        Ok((link, reply))
+    }
+
+    /// 受信側の報告と自分の設定を突き合わせ、問題があれば警告する
+    fn check_receiver(&self, info: &ReceiverInfo, remote_addr: SocketAddr) {
+        match &info.backend {
+            Some(backend) => log::info!("Receiver injects with {}", backend),
+            None => log::warn!(
+                "!!! Receiver at {} has no working injection backend: nothing will move there. Run `sharemouse permissions` on the receiver !!!",
+                remote_addr
+            ),
+        }
+        // 特定のモニターを対象にしている場合、受信側の報告はデスクトップ全体のサイズなので比べない
+        if let (Some((width, height)), None) = (info.screen, &self.config.remote_monitor) {
+            let (expected_width, expected_height) = self.config.remote_screen.os_size();
+            if (width as f64, height as f64) != (expected_width, expected_height) {
+                log::warn!(
+                    "!!! Receiver reports a {}x{} screen but remote_screen is configured as {}x{}: remote positions will be off !!!",
+                    width,
+                    height,
+                    expected_width,
+                    expected_height
+                );
+            }
+        }
+    }
+
+    /// SSHで相手側のreceiverを起動する
+    async fn launch_remote(&self, launch: &RemoteLaunch) {
+        log::info!(
+            "No receiver responded, launching it via: ssh {} {}",
+            launch.ssh_target,
+            launch.command
+        );
+        let mut child = match Command::new("ssh")
+            .arg(&launch.ssh_target)
+            .arg(&launch.command)
+            .kill_on_drop(false)
+            .spawn()
+        {
+            Ok(child) => child,
+            Err(e) => {
+                log::error!("Failed to run ssh: {}", e);
+                return;
+            }
+        };
+        // receiverがフォアグラウンドで動き続ける場合もあるので、終了は少しだけ待つ
+        match tokio::time::timeout(REMOTE_LAUNCH_WAIT, child.wait()).await {
+            Ok(Ok(status)) if status.success() => log::info!("Remote launch command finished"),
+            Ok(Ok(status)) => log::error!("Remote launch command failed: {}", status),
+            Ok(Err(e)) => log::error!("Failed to wait for ssh: {}", e),
+            Err(_) => {
+                log::info!("Remote launch command is still running, assuming receiver started")
+            }
+        }
+    }
+
+    /// remote_ip:remote_portを解決する。再接続のたびに呼び、DHCPなどでのアドレス変更に追従する
+    async fn resolve(&self) -> Result<SocketAddr> {
+        tokio::net::lookup_host((self.config.remote_host(), self.config.remote_port))
+            .await?
+            .next()
+            .ok_or_else(|| {
+                anyhow::anyhow!("{} did not resolve to any address", self.config.remote_ip)
+            })
+    }
+
+    /// 取り決めた圧縮方式に送信形式の制約を反映する
+    fn wire_codec(&self, codec: Codec) -> Codec {
+        if self.config.wire_format == WireFormat::Json {
+            // 読めることが目的なので圧縮しない
+            Codec::None
+        } else {
+            codec
+        }
+    }
+
+    /// 切断後に接続し直す。受信側が応答したときだけ成功とみなす
+    async fn reconnect(&self) -> Result<(Link, SocketAddr, Codec, u32)> {
+        let remote_addr = self.resolve().await?;
+        match self.connect(remote_addr).await? {
+            (link, Some((codec, version))) => {
+                Ok((link, remote_addr, self.wire_codec(codec), version))
+            }
+            (_, None) => anyhow::bail!("no handshake reply from {}", remote_addr),
+        }
     }
 
     pub async fn start(&self, mut receiver: mpsc::UnboundedReceiver<MouseEvent>) -> Result<()> {
-        let remote_addr: SocketAddr =
-            format!("{}:{}", self.config.remote_ip, self.config.remote_port).parse()?;
+        let mut remote_addr = self.resolve().await?;
         log::info!("NetworkSender starting, will send to {}", remote_addr);
 
-        // senderは常にUDP
-        let socket = UdpSocket::bind("0.0.0.0:0").await?;
-        let local_addr = socket.local_addr()?;
+        let mut connected = self.connect(remote_addr).await;
+        if !matches!(connected, Ok((_, Some(_)))) {
+            if let Some(launch) = &self.config.remote_launch {
+                self.launch_remote(launch).await;
+                connected = self.connect(remote_addr).await;
+            }
+        }
+        // TCPは接続できなければ送りようがない
+        let (mut link, reply) = connected?;
+        let (codec, mut version) = reply.unwrap_or_else(|| {
+            // ポートの食い違いは何も起きないだけで気づきにくいので、はっきり伝える
+            log::error!(
+                "!!! No receiver on port {} at {}: check that `sharemouse receive --port {}` is running there and that remote_port matches. Sending uncompressed anyway !!!",
+                remote_addr.port(),
+                remote_addr.ip(),
+                remote_addr.port()
+            );
+            if self.cipher.is_some() {
+                log::error!("The receiver must also be started with --psk-file holding the same psk");
+            }
+            (Codec::None, legacy_version())
+        });
+        let mut codec = self.wire_codec(codec);
         log::info!(
-            "UDP socket bound to {}, will send to {}",
-            local_addr,
-            remote_addr
+            "Using codec {:?}, wire format {:?}, receiver protocol {}",
+            codec,
+            self.config.wire_format,
+            version
         );
 
-        while let Some(event) = receiver.recv().await {
-            log::info!("NetworkSender received event: {:?}", event);
-            let data = bincode::serialize(&event)?;
-            match socket.send_to(&data, remote_addr).await {
+        // 再起動を受信側が検知できるよう、起動時刻をセッションIDにする
+        let epoch = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos() as u64)
+            .unwrap_or_default();
+        let session_started = Instant::now();
+        let mut seq: u32 = 0;
+        let mut quiet = QuietGate::new(self.config.quiet_hours.clone());
+        // 送信したボタンイベントから見た押下中のボタン
+        let mut held_buttons: u8 = 0;
+        let mut button_sync = tokio::time::interval(BUTTON_SYNC_INTERVAL);
+        let mut last_position = LastPosition::default();
+        let mut resync = (self.config.resync_interval_ms > 0)
+            .then(|| tokio::time::interval(Duration::from_millis(self.config.resync_interval_ms)));
+        let mut stats = SendStats::default();
+        let mut summary = tokio::time::interval(STATS_INTERVAL);
+        // 切断中なら次に再接続を試みる時刻。その間のイベントは溜めずに捨てる
+        let mut reconnect_at: Option<tokio::time::Instant> = None;
+        let mut backoff = RECONNECT_BACKOFF;
+        let mut heartbeat = tokio::time::interval(HEARTBEAT_INTERVAL);
+        // 最後に送った、または受け取ったクリップボード（同じ内容を送り返さないため）
+        let mut last_clipboard: Option<String> = None;
+        // まとめている最中のMoveと、それを送る時刻
+        let coalesce = Duration::from_millis(self.config.coalesce_ms);
+        let mut pending_move: Option<MouseEvent> = None;
+        let mut flush_at: Option<tokio::time::Instant> = None;
+        // 溜めたMoveを先に送るため後回しにしたイベント
+        let mut deferred: Option<MouseEvent> = None;
+        // ノッチ単位で送るときに、1ノッチに満たないスクロール量を持ち越す
+        let mut legacy_scroll = ScrollAccumulator::default();
+
+        loop {
+            let event = if let Some(event) = deferred.take() {
+                event
+            } else {
+                tokio::select! {
+                    event = receiver.recv() => match event {
+                        Some(event @ MouseEvent::Move { .. }) if !coalesce.is_zero() => {
+                            if pending_move.replace(event).is_none() {
+                                flush_at = Some(tokio::time::Instant::now() + coalesce);
+                            }
+                            continue;
+                        }
+                        // クリックなどが溜めたMoveを追い越さないよう、先にMoveを送る
+                        Some(event) => match pending_move.take() {
+                            Some(pending) => {
+                                flush_at = None;
+                                deferred = Some(event);
+                                pending
+                            }
+                            None => event,
+                        },
+                        None => break,
+                    },
+                    _ = wait_until(flush_at) => {
+                        flush_at = None;
+                        match pending_move.take() {
+                            Some(pending) => pending,
+                            None => continue,
+                        }
+                    }
+                    _ = button_sync.tick() => MouseEvent::Buttons { held: held_buttons },
+                    // 届かなかったMoveがあっても受信側のカーソルを正しい位置に戻す
+                    _ = tick(&mut resync) => match last_position.resend() {
+                        Some(event) => event,
+                        None => continue,
+                    },
+                    _ = summary.tick() => {
+                        stats.report(remote_addr);
+                        if let Ok(heartbeat) = self.heartbeat.lock() {
+                            if let (true, Some(rtt)) = (heartbeat.is_connected(), heartbeat.rtt) {
+                                log::info!("Receiver at {} is alive, RTT {:?}", remote_addr, rtt);
+                            }
+                        }
+                        continue;
+                    }
+                    _ = heartbeat.tick(), if reconnect_at.is_none() => {
+                        let (lost, id) = match self.heartbeat.lock() {
+                            Ok(mut heartbeat) => (heartbeat.check(remote_addr), heartbeat.ping()),
+                            Err(_) => continue,
+                        };
+                        if lost {
+                            // 受信側が再起動していれば取り決めた圧縮方式も失われているので、ハンドシェイクからやり直す
+                            reconnect_at = Some(tokio::time::Instant::now());
+                            continue;
+                        }
+                        if let Err(e) = link.send(&self.encode(&Packet::Ping { id })?, remote_addr).await {
+                            log::debug!("Failed to send heartbeat to {}: {}", remote_addr, e);
+                        }
+                        continue;
+                    }
+                    data = link.recv(remote_addr), if reconnect_at.is_none() => {
+                        match data {
+                            Ok(Some(data)) => match self.decode_reply(&data) {
+                                Ok(Packet::Pong { id }) => {
+                                    if let Ok(mut heartbeat) = self.heartbeat.lock() {
+                                        heartbeat.pong(id, remote_addr);
+                                    }
+                                }
+                                Ok(Packet::Clipboard { text }) if self.config.share_clipboard => {
+                                    set_clipboard(text, &mut last_clipboard).await;
+                                }
+                                Ok(Packet::HelloRequest { .. }) => {
+                                    log::info!("Receiver at {} lost the handshake, reconnecting", remote_addr);
+                                    reconnect_at = Some(tokio::time::Instant::now());
+                                }
+                                _ => {}
+                            },
+                            Ok(None) if link.is_stream() => {
+                                log::warn!("Receiver at {} closed the connection, reconnecting", remote_addr);
+                                reconnect_at = Some(tokio::time::Instant::now());
+                            }
+                            Ok(None) => {}
+                            Err(e) if link.is_stream() => {
+                                log::warn!("Connection to {} failed: {}, reconnecting", remote_addr, e);
+                                reconnect_at = Some(tokio::time::Instant::now());
+                            }
+                            Err(e) => log::debug!("Failed to receive from {}: {}", remote_addr, e),
+                        }
+                        continue;
+                    }
+                    _ = wait_until(reconnect_at) => {
+                        log::info!("Reconnecting to {}:{}...", self.config.remote_ip, self.config.remote_port);
+                        match self.reconnect().await {
+                            Ok(reconnected) => {
+                                (link, remote_addr, codec, version) = reconnected;
+                                log::info!("Reconnected to {}, using codec {:?}, receiver protocol {}", remote_addr, codec, version);
+                                if let Ok(mut heartbeat) = self.heartbeat.lock() {
+                                    heartbeat.reconnected();
+                                }
+                                reconnect_at = None;
+                                backoff = RECONNECT_BACKOFF;
+                            }
+                            Err(e) => {
+                                backoff = (backoff * 2).min(RECONNECT_BACKOFF_MAX);
+                                log::warn!("Reconnect failed: {}, retrying in {:?}", e, backoff);
+                                reconnect_at = Some(tokio::time::Instant::now() + backoff);
+                            }
+                        }
+                        continue;
+                    }
+                }
+            };
+            log::trace!("NetworkSender received event: {:?}", event);
+            if quiet.is_quiet() {
+                continue;
+            }
+            if let Some((button, pressed)) = event.button() {
+                if pressed {
+                    held_buttons |= button;
+                } else {
+                    held_buttons &= !button;
+                }
+            }
+            last_position.observe(&event);
+            let entering = matches!(event, MouseEvent::ControlEnter);
+            let event = if self.config.normalized_coordinates {
+                // 受信側の解像度に依存しないよう、リモート画面に対する割合で送る
+                let (width, height) = self.config.remote_screen.os_size();
+                event.normalize(width, height)
+            } else {
+                event
+            };
+            if reconnect_at.is_some() {
+                stats.dropped += 1;
+                continue;
+            }
+            let event = match event {
+                MouseEvent::Scroll {
+                    x,
+                    y,
+                    delta_x,
+                    delta_y,
+                } if version < 2 => {
+                    let (delta_x, delta_y) =
+                        legacy_scroll.steps(delta_x, delta_y, SCROLL_UNITS_PER_NOTCH);
+                    if (delta_x, delta_y) == (0, 0) {
+                        continue;
+                    }
+                    MouseEvent::Scroll {
+                        x,
+                        y,
+                        delta_x,
+                        delta_y,
+                    }
+                }
+                event => event,
+            };
+            // 送信時刻を読めない古い受信側には従来の形式で送る
+            let event = if version >= 2 {
+                Packet::TimedEvent {
+                    epoch,
+                    seq,
+                    sent_ms: session_started.elapsed().as_millis() as u64,
+                    event,
+                }
+            } else {
+                Packet::Event { epoch, seq, event }
+            };
+            seq = seq.wrapping_add(1);
+            let packet = match codec {
+                Codec::None => event,
+                _ => Packet::Compressed(codec.compress(&bincode::serialize(&event)?)?),
+            };
+            let data = self.encode(&packet)?;
+            match link.send(&data, remote_addr).await {
                 Ok(bytes_sent) => {
-                    log::info!("Sent {} bytes to {}", bytes_sent, remote_addr);
+                    log::trace!("Sent {} bytes to {}", bytes_sent, remote_addr);
+                    stats.events += 1;
+                    stats.bytes += bytes_sent as u64;
+                    METRICS.events_sent.fetch_add(1, Ordering::Relaxed);
+                    METRICS
+                        .bytes_sent
+                        .fetch_add(bytes_sent as u64, Ordering::Relaxed);
                 }
                 Err(e) => {
-                    log::error!("Failed to send to {}: {}", remote_addr, e);
+                    log::warn!(
+                        "Failed to send to {}: {}, reconnecting in {:?}",
+                        remote_addr,
+                        e,
+                        backoff
+                    );
+                    stats.failures += 1;
+                    METRICS.send_errors.fetch_add(1, Ordering::Relaxed);
+                    reconnect_at = Some(tokio::time::Instant::now() + backoff);
+                    continue;
                 }
             }
+            if entering {
+                // 受信側のロックキーをこちらに合わせる。起動時から食い違っていても最初の移譲で揃う
+                if let event @ MouseEvent::Locks { known: 1.., .. } = crate::lock_keys::read() {
+                    deferred = Some(event);
+                }
+            }
+            if entering && self.config.share_clipboard {
+                self.send_clipboard(&mut link, remote_addr, &mut last_clipboard)
+                    .await;
+            }
         }
 
         Ok(())
     }
+
+    /// ローカルのクリップボードが前回から変わっていれば受信側に送る
+    async fn send_clipboard(
+        &self,
+        link: &mut Link,
+        remote_addr: SocketAddr,
+        last: &mut Option<String>,
+    ) {
+        let Some(text) = changed_clipboard(last).await else {
+            return;
+        };
+        let result = match self.encode(&Packet::Clipboard { text }) {
+            Ok(data) => link.send(&data, remote_addr).await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = result {
+            log::warn!("Failed to send the clipboard to {}: {}", remote_addr, e);
+        }
+    }
+}
+
+/// ローカルのクリップボードを読み、前回から変わっていて上限内なら返す
+async fn changed_clipboard(last: &mut Option<String>) -> Option<String> {
+    let text = match clipboard::read().await {
+        Ok(text) => text,
+        Err(e) => {
+            log::debug!("Not sharing the clipboard: {}", e);
+            return None;
+        }
+    };
+    if text.is_empty() || last.as_deref() == Some(text.as_str()) {
+        return None;
+    }
+    *last = Some(text.clone());
+    if text.len() > clipboard::MAX_BYTES {
+        log::warn!(
+            "Not sharing the clipboard: {} bytes exceeds the limit of {} bytes",
+            text.len(),
+            clipboard::MAX_BYTES
+        );
+        return None;
+    }
+    log::info!("Sharing the clipboard ({} bytes)", text.len());
+    Some(text)
+}
+
+/// 相手側から受け取ったクリップボードをローカルに設定する
+async fn set_clipboard(text: String, last: &mut Option<String>) {
+    if text.len() > clipboard::MAX_BYTES {
+        log::warn!("Ignoring an oversized clipboard of {} bytes", text.len());
+        return;
+    }
+    match clipboard::write(&text).await {
+        Ok(()) => {
+            log::info!("Clipboard updated from the peer ({} bytes)", text.len());
+            *last = Some(text);
+        }
+        Err(e) => log::warn!("Failed to set the clipboard: {}", e),
+    }
+}
+
+/// 送信の集計。イベントごとにログを出す代わりに定期的にまとめて出す
+#[derive(Default)]
+struct SendStats {
+    events: u64,
+    bytes: u64,
+    failures: u64,
+    /// 切断中に捨てたイベント
+    dropped: u64,
+}
+
+impl SendStats {
+    /// 前回からの集計をログに出してリセットする
+    fn report(&mut self, remote_addr: SocketAddr) {
+        let secs = STATS_INTERVAL.as_secs_f64();
+        if self.events > 0 {
+            log::info!(
+                "Sent {:.1} events/s, {:.0} bytes/s to {}",
+                self.events as f64 / secs,
+                self.bytes as f64 / secs,
+                remote_addr
+            );
+        }
+        if self.failures > 0 {
+            log::warn!(
+                "Failed to send {} events to {} in the last {:.0}s",
+                self.failures,
+                remote_addr,
+                secs
+            );
+        }
+        if self.dropped > 0 {
+            log::warn!(
+                "Dropped {} events while disconnected from {}",
+                self.dropped,
+                remote_addr
+            );
+        }
+        *self = Self::default();
+    }
+}
+
+/// 最後に送ったリモートでの位置（制御権がホストにあるときはNone）
+#[derive(Default)]
+struct LastPosition(Option<(f64, f64)>);
+
+impl LastPosition {
+    /// 送るイベントから位置を更新する
+    fn observe(&mut self, event: &MouseEvent) {
+        match *event {
+            MouseEvent::Move { x, y } | MouseEvent::Scroll { x, y, .. } => self.0 = Some((x, y)),
+            MouseEvent::ControlLeave => self.0 = None,
+            _ => {}
+        }
+    }
+
+    /// 送り直すMove。リモート操作中でなければNone
+    fn resend(&self) -> Option<MouseEvent> {
+        self.0.map(|(x, y)| MouseEvent::Move { x, y })
+    }
+}
+
+/// 無効（None）なら永遠に待つtick
+async fn tick(interval: &mut Option<tokio::time::Interval>) {
+    match interval {
+        Some(interval) => {
+            interval.tick().await;
+        }
+        None => std::future::pending().await,
+    }
+}
+
+/// 時刻が未定（None）なら永遠に待つsleep
+async fn wait_until(deadline: Option<tokio::time::Instant>) {
+    match deadline {
+        Some(deadline) => tokio::time::sleep_until(deadline).await,
+        None => std::future::pending().await,
+    }
+}
+
+/// 受け付ける送信元の範囲。`192.168.1.10`のような単一のアドレスか、`192.168.1.0/24`のようなCIDR
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct IpNet {
+    pub addr: IpAddr,
+    pub prefix: u8,
+}
+
+impl IpNet {
+    pub fn contains(&self, addr: IpAddr) -> bool {
+        // "::"で待ち受けるとIPv4の送信元は::ffff:a.b.c.dに見えるので、IPv4に戻して比べる
+        match (self.addr.to_canonical(), addr.to_canonical()) {
+            (IpAddr::V4(net), IpAddr::V4(addr)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0);
+                u32::from(net) & mask == u32::from(addr) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(addr)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix as u32).unwrap_or(0);
+                u128::from(net) & mask == u128::from(addr) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for IpNet {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let s = s.trim();
+        let (addr, prefix) = match s.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (s, None),
+        };
+        let addr = addr
+            .parse::<IpAddr>()
+            .map_err(|_| anyhow::anyhow!("invalid IP address {:?}", addr))?
+            .to_canonical();
+        let max = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) => prefix
+                .parse::<u8>()
+                .ok()
+                .filter(|prefix| *prefix <= max)
+                .ok_or_else(|| {
+                    anyhow::anyhow!("invalid prefix length {:?}, expected 0-{}", prefix, max)
+                })?,
+            None => max,
+        };
+        Ok(Self { addr, prefix })
+    }
+}
+
+impl TryFrom<String> for IpNet {
+    type Error = anyhow::Error;
+
+    fn try_from(s: String) -> Result<Self> {
+        s.parse()
+    }
+}
+
+impl From<IpNet> for String {
+    fn from(net: IpNet) -> Self {
+        net.to_string()
+    }
+}
+
+impl std::fmt::Display for IpNet {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let max = if self.addr.is_ipv4() { 32 } else { 128 };
+        if self.prefix == max {
+            write!(f, "{}", self.addr)
+        } else {
+            write!(f, "{}/{}", self.addr, self.prefix)
+        }
+    }
+}
+
+/// 受信側の動作設定
+#[derive(Debug, Clone, Default)]
+pub struct ReceiverOptions {
+    pub session_policy: SessionPolicy,
+    /// 受け付ける送信元。空なら全て受け付ける
+    pub allowed_senders: Vec<IpNet>,
+    /// 送信側とクリップボードをやり取りするか
+    pub share_clipboard: bool,
+    /// これより遅れて届いたMoveを捨てる（クリックなどは常に注入する）。ゼロで無効
+    pub max_event_age: Duration,
+    /// 待ち受けるアドレス（省略時は0.0.0.0）
+    pub bind_address: Option<IpAddr>,
 }
 
 pub struct NetworkReceiver {
     port: u16,
+    bind_address: IpAddr,
+    session_policy: SessionPolicy,
+    /// 受け付ける送信元。空なら全て受け付ける
+    allowed_senders: Vec<IpNet>,
+    /// ハンドシェイクで送信側に伝える情報
+    info: ReceiverInfo,
+    /// 送信側とクリップボードをやり取りするか
+    share_clipboard: bool,
+    /// これより遅れて届いたMoveを捨てる。ゼロで無効
+    max_event_age: Duration,
+    /// 設定されていれば、認証できないパケットを全て捨てる
+    cipher: Option<Cipher>,
 }
 
 impl NetworkReceiver {
-    pub fn new(port: u16) -> Self {
-        Self { port }
+    pub fn new(
+        port: u16,
+        options: ReceiverOptions,
+        info: ReceiverInfo,
+        cipher: Option<Cipher>,
+    ) -> Self {
+        Self {
+            port,
+            bind_address: options.bind_address.unwrap_or(Ipv4Addr::UNSPECIFIED.into()),
+            session_policy: options.session_policy,
+            allowed_senders: options.allowed_senders,
+            info,
+            share_clipboard: options.share_clipboard,
+            max_event_age: options.max_event_age,
+            cipher,
+        }
     }
 
     pub async fn start(&self, sender: mpsc::UnboundedSender<MouseEvent>) -> Result<()> {
-        let bind_addr: SocketAddr = format!("0.0.0.0:{}", self.port).parse()?;
+        self.run(false, |received| {
+            let _ = sender.send(received.event);
+        })
+        .await
+    }
+
+    /// 送信側には何も返さずに、受信したイベントをパケットの情報と一緒に渡す
+    ///
+    /// ハンドシェイクやハートビートに応答しないので、送信側は受信側がいないものとして
+    /// 圧縮せず、送信時刻も付けずに送ってくる。送信側の状態は変えない
+    pub async fn sniff(&self, sender: mpsc::UnboundedSender<ReceivedEvent>) -> Result<()> {
+        self.run(true, |received| {
+            let _ = sender.send(received);
+        })
+        .await
+    }
+
+    /// 受信ループ。`passive`なら送信元に一切返信しない
+    async fn run(&self, passive: bool, mut deliver: impl FnMut(ReceivedEvent)) -> Result<()> {
+        let bind_addr = SocketAddr::new(self.bind_address, self.port);
 
-        // receiverは常にUDP、固定バッファサイズ
+        // UDPとTCPの両方で待ち受け、どちらから届いたパケットも同じように扱う
         let socket = UdpSocket::bind(bind_addr).await?;
-        let mut buf = vec![0u8; 4096];
+        let listener = TcpListener::bind(bind_addr).await?;
+        let (frame_tx, mut frame_rx) = mpsc::unbounded_channel::<TcpFrame>();
+        let mut buf = vec![0u8; MAX_DATAGRAM_LEN];
+        // 送信元ごとに取り決めた圧縮方式
+        let mut codecs: HashMap<SocketAddr, Codec> = HashMap::new();
+        // ハンドシェイクのやり直しを求めた送信元（Helloが届くまで繰り返し求めないため）
+        let mut hello_requested: HashSet<SocketAddr> = HashSet::new();
+        // 0..1の割合で座標を送ってくる送信元
+        let mut normalized_senders: HashSet<SocketAddr> = HashSet::new();
+        // 送信元ごとの通し番号。複数の送信側の番号が混ざると互いのパケットを古いとみなしてしまう
+        let mut seq_trackers: HashMap<SocketAddr, SeqTracker> = HashMap::new();
+        let mut lag_tracker = LagTracker::new();
+        let mut summary = tokio::time::interval(STATS_INTERVAL);
+        // ControlEnterを受信してからControlLeaveまでの間か
+        let mut in_session = false;
+        // 拒否した送信元（ログを一度だけ出すため）
+        let mut rejected: HashSet<IpAddr> = HashSet::new();
+        // 最後に送った、または受け取ったクリップボード
+        let mut last_clipboard: Option<String> = None;
+        let mut replay = ReplayGuard::default();
+        // 認証に失敗した送信元（ログを一度だけ警告にするため）
+        let mut unauthenticated: HashSet<IpAddr> = HashSet::new();
 
-        log::info!("UDP receiver listening on {}", bind_addr);
+        log::info!("Receiver listening on {} (UDP and TCP)", bind_addr);
+        if !self.allowed_senders.is_empty() {
+            let allowed: Vec<String> = self
+                .allowed_senders
+                .iter()
+                .map(|net| net.to_string())
+                .collect();
+            log::info!("Accepting packets only from {}", allowed.join(", "));
+        }
+        if self.cipher.is_some() {
+            log::info!("Accepting only packets encrypted with the psk");
+        }
         loop {
-            let (len, addr) = socket.recv_from(&mut buf).await?;
-            log::debug!("Received {} bytes from {}", len, addr);
-            log::debug!("Raw bytes: {:?}", &buf[..len]);
-            match bincode::deserialize::<MouseEvent>(&buf[..len]) {
-                Ok(net_event) => {
-                    log::debug!("Parsed event: {:?}", net_event);
-                    let event = MouseEvent::from(net_event);
-                    let _ = sender.send(event);
+            let (data, addr, reply) = tokio::select! {
+                result = socket.recv_from(&mut buf) => {
+                    let (len, addr) = result?;
+                    (buf[..len].to_vec(), addr, None)
                 }
+                Some(frame) = frame_rx.recv() => (frame.data, frame.addr, Some(frame.reply)),
+                _ = summary.tick() => {
+                    for (addr, tracker) in &mut seq_trackers {
+                        tracker.report(*addr);
+                    }
+                    continue;
+                }
+                result = listener.accept() => {
+                    match result {
+                        Ok((stream, addr)) => {
+                            log::info!("TCP connection from {}", addr);
+                            tokio::spawn(serve_tcp(stream, addr, frame_tx.clone()));
+                        }
+                        Err(e) => log::warn!("Failed to accept TCP connection: {}", e),
+                    }
+                    continue;
+                }
+            };
+            let len = data.len();
+            if !self.allowed_senders.is_empty()
+                && !self
+                    .allowed_senders
+                    .iter()
+                    .any(|net| net.contains(addr.ip()))
+            {
+                if rejected.insert(addr.ip()) {
+                    log::warn!("Rejecting packets from {}: not in allowed senders", addr);
+                }
+                continue;
+            }
+            log::debug!("Received {} bytes from {}", len, addr);
+            let data = match &self.cipher {
+                Some(cipher) => match cipher.open(&data) {
+                    Ok(opened) if replay.accept(&opened) => opened.payload,
+                    Ok(_) => {
+                        log::warn!("Dropping a replayed packet from {}", addr);
+                        continue;
+                    }
+                    Err(e) => {
+                        if unauthenticated.insert(addr.ip()) {
+                            log::warn!("Dropping unauthenticated packets from {}: {}", addr, e);
+                        } else {
+                            log::debug!("Dropping unauthenticated packet from {}: {}", addr, e);
+                        }
+                        continue;
+                    }
+                },
+                None => data,
+            };
+            log::debug!("Raw bytes: {:?}", data);
+            let packets = match decode_datagram(&data) {
+                Ok(packets) => packets,
                 Err(e) => {
                     log::warn!("Failed to deserialize network event: {}", e);
+                    METRICS.decode_errors.fetch_add(1, Ordering::Relaxed);
                     log::debug!(
                         "Attempting to deserialize as string: {:?}",
-                        String::from_utf8_lossy(&buf[..len])
+                        String::from_utf8_lossy(&data)
                     );
+                    continue;
+                }
+            };
+            for mut packet in packets {
+                if let Packet::Compressed(data) = &packet {
+                    let decoded = match codecs.get(&addr) {
+                        Some(codec) => codec.decompress(data).and_then(|raw| decode_packet(&raw)),
+                        // 取り決めに加わっていなければ、対応している方式を順に試す
+                        None if passive => SUPPORTED_CODECS
+                            .iter()
+                            .find_map(|codec| {
+                                codec
+                                    .decompress(data)
+                                    .and_then(|raw| decode_packet(&raw))
+                                    .ok()
+                            })
+                            .ok_or_else(|| anyhow::anyhow!("unknown codec from {}", addr)),
+                        None => Err(anyhow::anyhow!("no codec negotiated with {}", addr)),
+                    };
+                    packet = match decoded {
+                        Ok(packet) => packet,
+                        Err(e) => {
+                            log::warn!("Failed to decompress network event: {}", e);
+                            METRICS.decode_errors.fetch_add(1, Ordering::Relaxed);
+                            // こちらが再起動して圧縮方式を忘れていれば、取り決め直すまで読めない
+                            if !passive && hello_requested.insert(addr) {
+                                self.request_hello(&socket, &reply, addr).await?;
+                            }
+                            continue;
+                        }
+                    };
                 }
+                if !passive
+                    && matches!(packet, Packet::Event { .. } | Packet::TimedEvent { .. })
+                    && !codecs.contains_key(&addr)
+                    && hello_requested.insert(addr)
+                {
+                    // 座標の形式などを取り決め直す
+                    self.request_hello(&socket, &reply, addr).await?;
+                }
+                // 従来の形式ではスクロール量がノッチ単位
+                if let Packet::Event {
+                    event:
+                        MouseEvent::Scroll {
+                            delta_x, delta_y, ..
+                        },
+                    ..
+                }
+                | Packet::Raw(MouseEvent::Scroll {
+                    delta_x, delta_y, ..
+                }) = &mut packet
+                {
+                    *delta_x = delta_x.saturating_mul(SCROLL_UNITS_PER_NOTCH);
+                    *delta_y = delta_y.saturating_mul(SCROLL_UNITS_PER_NOTCH);
+                }
+                match packet {
+                    Packet::Hello {
+                        codecs: remote,
+                        normalized,
+                        version,
+                    } => {
+                        let codec = Codec::negotiate(&remote);
+                        hello_requested.remove(&addr);
+                        if passive {
+                            // 取り決めには加わらない。送信側は応答がないので圧縮せずに送ってくる
+                            log::info!(
+                                "Handshake from {} (protocol {}, codecs {:?}, normalized {}), not answering",
+                                addr,
+                                version,
+                                remote,
+                                normalized
+                            );
+                            continue;
+                        }
+                        log::info!(
+                            "Handshake from {} (protocol {}), using codec {:?}",
+                            addr,
+                            version,
+                            codec
+                        );
+                        codecs.insert(addr, codec);
+                        if normalized {
+                            if self.info.screen.is_none() {
+                                log::error!(
+                                    "{} sends normalized coordinates but the screen size is unknown, pass --screen-size",
+                                    addr
+                                );
+                            }
+                            normalized_senders.insert(addr);
+                        } else {
+                            normalized_senders.remove(&addr);
+                        }
+                        let ack = bincode::serialize(&Packet::HelloAck {
+                            codec,
+                            info: self.info.clone(),
+                            version: PROTOCOL_VERSION,
+                        })?;
+                        if let Err(e) =
+                            send_reply(&socket, &reply, self.cipher.as_ref(), addr, ack).await
+                        {
+                            log::warn!("Failed to send handshake reply to {}: {}", addr, e);
+                        }
+                    }
+                    Packet::HelloAck { .. } | Packet::Pong { .. } => {}
+                    Packet::HelloRequest { .. } => {}
+                    Packet::Clipboard { text } => {
+                        if self.share_clipboard {
+                            set_clipboard(text, &mut last_clipboard).await;
+                        }
+                    }
+                    Packet::Ping { .. } if passive => {}
+                    Packet::Ping { id } => {
+                        let pong = bincode::serialize(&Packet::Pong { id })?;
+                        if let Err(e) =
+                            send_reply(&socket, &reply, self.cipher.as_ref(), addr, pong).await
+                        {
+                            log::debug!("Failed to answer heartbeat from {}: {}", addr, e);
+                        }
+                    }
+                    Packet::Event { epoch, seq, .. } | Packet::TimedEvent { epoch, seq, .. }
+                        if !seq_trackers.entry(addr).or_default().accept(epoch, seq) =>
+                    {
+                        log::debug!("Dropping stale event seq {} from {}", seq, addr);
+                    }
+                    Packet::TimedEvent {
+                        epoch,
+                        sent_ms,
+                        event: MouseEvent::Move { .. },
+                        ..
+                    } if !self.max_event_age.is_zero()
+                        && lag_tracker.lag(epoch, sent_ms) > self.max_event_age =>
+                    {
+                        // 溜まって遅れて届いた移動を再生すると、カーソルが古い軌跡をなぞってしまう
+                        log::debug!("Dropping a delayed move from {}", addr);
+                    }
+                    Packet::Event { .. } | Packet::TimedEvent { .. } | Packet::Raw(_) => {
+                        let Some(received) = packet.into_received(addr) else {
+                            continue;
+                        };
+                        let event = received.event;
+                        log::debug!("Parsed event: {:?}", event);
+                        METRICS.events_received.fetch_add(1, Ordering::Relaxed);
+                        match event {
+                            MouseEvent::Unknown => {
+                                log::warn!("Skipping unsupported event type from {}", addr);
+                                continue;
+                            }
+                            MouseEvent::ControlEnter => in_session = true,
+                            MouseEvent::ControlLeave => {
+                                in_session = false;
+                                // 制御がホストに戻るので、こちらでコピーした内容を返す
+                                if self.share_clipboard {
+                                    if let Some(text) = changed_clipboard(&mut last_clipboard).await
+                                    {
+                                        let data = bincode::serialize(&Packet::Clipboard { text })?;
+                                        if let Err(e) = send_reply(
+                                            &socket,
+                                            &reply,
+                                            self.cipher.as_ref(),
+                                            addr,
+                                            data,
+                                        )
+                                        .await
+                                        {
+                                            log::warn!(
+                                                "Failed to send the clipboard to {}: {}",
+                                                addr,
+                                                e
+                                            );
+                                        }
+                                    }
+                                }
+                            }
+                            // セッション開始前に届くのが前提のイベント
+                            MouseEvent::EdgeApproach { .. } | MouseEvent::EdgeRetreat => {}
+                            _ if !in_session
+                                && self.session_policy == SessionPolicy::IgnoreUntilEnter =>
+                            {
+                                log::debug!(
+                                    "Ignoring {} outside of a control session",
+                                    event.kind()
+                                );
+                                continue;
+                            }
+                            _ => {}
+                        }
+                        let event = match (normalized_senders.contains(&addr), self.info.screen) {
+                            (true, Some((width, height))) => {
+                                event.denormalize(width as f64, height as f64)
+                            }
+                            _ => event,
+                        };
+                        deliver(ReceivedEvent { event, ..received });
+                    }
+                    Packet::Compressed(_) => {
+                        log::warn!("Unexpected nested compressed packet from {}", addr);
+                    }
+                }
+            }
+        }
+    }
+
+    /// 送信元にハンドシェイクのやり直しを求める
+    async fn request_hello(
+        &self,
+        socket: &UdpSocket,
+        reply: &Option<mpsc::UnboundedSender<Vec<u8>>>,
+        addr: SocketAddr,
+    ) -> Result<()> {
+        log::info!("Asking {} to redo the handshake", addr);
+        let request = bincode::serialize(&Packet::HelloRequest {
+            version: PROTOCOL_VERSION,
+        })?;
+        if let Err(e) = send_reply(socket, reply, self.cipher.as_ref(), addr, request).await {
+            log::debug!("Failed to ask {} for a handshake: {}", addr, e);
+        }
+        Ok(())
+    }
+}
+
+/// pskが設定されていれば暗号化する
+fn seal(cipher: Option<&Cipher>, data: Vec<u8>) -> Result<Vec<u8>> {
+    match cipher {
+        Some(cipher) => cipher.seal(&data),
+        None => Ok(data),
+    }
+}
+
+/// 受信したパケットの送信元に返信する。TCPなら同じ接続、UDPなら送信元アドレスへ
+async fn send_reply(
+    socket: &UdpSocket,
+    reply: &Option<mpsc::UnboundedSender<Vec<u8>>>,
+    cipher: Option<&Cipher>,
+    addr: SocketAddr,
+    data: Vec<u8>,
+) -> Result<()> {
+    let data = seal(cipher, data)?;
+    match reply {
+        Some(reply) => reply
+            .send(data)
+            .map_err(|_| anyhow::anyhow!("connection closed")),
+        None => {
+            socket.send_to(&data, addr).await?;
+            Ok(())
+        }
+    }
+}
+
+/// TCP接続から受け取った1フレーム
+struct TcpFrame {
+    addr: SocketAddr,
+    data: Vec<u8>,
+    /// この接続への返信
+    reply: mpsc::UnboundedSender<Vec<u8>>,
+}
+
+/// TCP接続からフレームを読み、受信ループに渡す
+async fn serve_tcp(stream: TcpStream, addr: SocketAddr, frames: mpsc::UnboundedSender<TcpFrame>) {
+    let (mut reader, mut writer) = stream.into_split();
+    let (reply_tx, mut reply_rx) = mpsc::unbounded_channel::<Vec<u8>>();
+    let replies = tokio::spawn(async move {
+        while let Some(data) = reply_rx.recv().await {
+            if writer.write_all(&encode_frame(&data)).await.is_err() {
+                break;
+            }
+        }
+    });
+    let mut decoder = FrameDecoder::new();
+    loop {
+        match read_frame(&mut reader, &mut decoder).await {
+            Ok(Some(data)) => {
+                let frame = TcpFrame {
+                    addr,
+                    data,
+                    reply: reply_tx.clone(),
+                };
+                if frames.send(frame).is_err() {
+                    break;
+                }
+            }
+            Ok(None) => {
+                log::info!("TCP connection from {} closed", addr);
+                break;
+            }
+            Err(e) => {
+                log::warn!("TCP connection from {} failed: {}", addr, e);
+                break;
+            }
+        }
+    }
+    replies.abort();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bare_mouse_events_from_old_senders_are_accepted() {
+        for event in [
+            MouseEvent::Move { x: 0.0, y: 0.0 },
+            MouseEvent::Move { x: 640.5, y: 480.0 },
+            MouseEvent::LeftClick,
+            MouseEvent::RightRelease,
+            MouseEvent::ControlEnter,
+            MouseEvent::ControlLeave,
+            MouseEvent::Scroll {
+                x: 0.0,
+                y: 0.0,
+                delta_x: 0,
+                delta_y: -1,
+            },
+        ] {
+            let data = bincode::serialize(&event).unwrap();
+            match decode_datagram(&data).unwrap().as_slice() {
+                [Packet::Raw(decoded)] => assert_eq!(*decoded, event),
+                other => panic!("{:?} decoded as {:?}", event, other),
+            }
+        }
+    }
+
+    #[test]
+    fn bincode_and_json_packets_are_detected() {
+        let ping = bincode::serialize(&Packet::Ping { id: 7 }).unwrap();
+        assert!(matches!(
+            decode_datagram(&ping).unwrap().as_slice(),
+            [Packet::Ping { id: 7 }]
+        ));
+        let json = b" {\"Ping\":{\"id\":7}}\n\"LeftClick\"\n";
+        let packets = decode_datagram(json).unwrap();
+        assert!(matches!(
+            packets.as_slice(),
+            [Packet::Ping { id: 7 }, Packet::Raw(MouseEvent::LeftClick)]
+        ));
+    }
+
+    #[test]
+    fn unknown_json_events_with_fields_are_skipped() {
+        let json = b"{\"Teleport\":{\"x\":1.0,\"y\":2.0}}\n\"Hover\"\n\"LeftClick\"\n";
+        let packets = decode_datagram(json).unwrap();
+        assert!(matches!(
+            packets.as_slice(),
+            [
+                Packet::Raw(MouseEvent::Unknown),
+                Packet::Raw(MouseEvent::Unknown),
+                Packet::Raw(MouseEvent::LeftClick)
+            ]
+        ));
+        // JSONとして壊れていればエラー
+        assert!(decode_datagram(b"{\"Teleport\":").is_err());
+    }
+
+    #[test]
+    fn handshake_from_version_1_peers_is_accepted() {
+        #[derive(Serialize)]
+        enum OldPacket {
+            Hello {
+                codecs: Vec<Codec>,
+                normalized: bool,
+            },
+            HelloAck {
+                codec: Codec,
+                info: ReceiverInfo,
+            },
+            Event {
+                epoch: u64,
+                seq: u32,
+                event: MouseEvent,
+            },
+        }
+        let hello = OldPacket::Hello {
+            codecs: vec![Codec::None, Codec::Deflate],
+            normalized: true,
+        };
+        assert!(matches!(
+            decode_packet(&bincode::serialize(&hello).unwrap()).unwrap(),
+            Packet::Hello {
+                normalized: true,
+                version: 1,
+                ..
+            }
+        ));
+        let ack = OldPacket::HelloAck {
+            codec: Codec::Deflate,
+            info: ReceiverInfo::default(),
+        };
+        assert!(matches!(
+            decode_packet(&bincode::serialize(&ack).unwrap()).unwrap(),
+            Packet::HelloAck {
+                codec: Codec::Deflate,
+                version: 1,
+                ..
             }
+        ));
+        let event = OldPacket::Event {
+            epoch: 1,
+            seq: 2,
+            event: MouseEvent::LeftClick,
+        };
+        assert!(matches!(
+            decode_packet(&bincode::serialize(&event).unwrap()).unwrap(),
+            Packet::Event {
+                seq: 2,
+                event: MouseEvent::LeftClick,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn old_receivers_can_read_new_hello() {
+        #[derive(Deserialize)]
+        enum OldPacket {
+            Hello {
+                codecs: Vec<Codec>,
+                normalized: bool,
+            },
+        }
+        let hello = Packet::Hello {
+            codecs: SUPPORTED_CODECS.to_vec(),
+            normalized: false,
+            version: PROTOCOL_VERSION,
+        };
+        let OldPacket::Hello { codecs, normalized } =
+            bincode::deserialize(&bincode::serialize(&hello).unwrap()).unwrap();
+        assert_eq!(codecs, SUPPORTED_CODECS);
+        assert!(!normalized);
+    }
+
+    #[test]
+    fn seq_tracker_drops_stale_and_duplicate_packets() {
+        let mut tracker = SeqTracker::default();
+        assert!(tracker.accept(1, 10));
+        assert!(tracker.accept(1, 12));
+        assert!(!tracker.accept(1, 11));
+        assert!(!tracker.accept(1, 12));
+        assert!(tracker.accept(1, 13));
+        assert_eq!(tracker.dropped_total, 2);
+    }
+
+    #[test]
+    fn seq_tracker_survives_wraparound() {
+        let mut tracker = SeqTracker::default();
+        assert!(tracker.accept(1, u32::MAX - 1));
+        assert!(tracker.accept(1, u32::MAX));
+        assert!(tracker.accept(1, 0));
+        assert!(tracker.accept(1, 1));
+        assert!(!tracker.accept(1, u32::MAX));
+    }
+
+    #[test]
+    fn seq_tracker_resets_on_new_epoch() {
+        let mut tracker = SeqTracker::default();
+        assert!(tracker.accept(1, 5000));
+        // 送信側が再起動して番号が0から振り直される
+        assert!(tracker.accept(2, 0));
+        assert!(tracker.accept(2, 1));
+        assert!(!tracker.accept(2, 0));
+    }
+
+    #[test]
+    fn resync_resends_the_last_remote_position() {
+        let mut last = LastPosition::default();
+        assert_eq!(last.resend(), None);
+        last.observe(&MouseEvent::ControlEnter);
+        last.observe(&MouseEvent::Move { x: 10.0, y: 20.0 });
+        // 間のMoveが失われても、最後の位置を送り直せば揃う
+        last.observe(&MouseEvent::Move { x: 30.0, y: 40.0 });
+        last.observe(&MouseEvent::LeftClick);
+        assert_eq!(last.resend(), Some(MouseEvent::Move { x: 30.0, y: 40.0 }));
+        last.observe(&MouseEvent::Scroll {
+            x: 35.0,
+            y: 45.0,
+            delta_x: 0,
+            delta_y: 120,
+        });
+        assert_eq!(last.resend(), Some(MouseEvent::Move { x: 35.0, y: 45.0 }));
+        // ホストに戻ったら送り直さない
+        last.observe(&MouseEvent::ControlLeave);
+        assert_eq!(last.resend(), None);
+    }
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn ip_nets_are_parsed_with_prefix_bounds() {
+        let net: IpNet = " 192.168.1.7/24 ".parse().unwrap();
+        assert_eq!((net.addr, net.prefix), (ip("192.168.1.7"), 24));
+        assert_eq!(String::from(net), "192.168.1.7/24");
+        assert_eq!("10.0.0.1".parse::<IpNet>().unwrap().prefix, 32);
+        assert_eq!("fe80::1".parse::<IpNet>().unwrap().prefix, 128);
+        assert_eq!("::/0".parse::<IpNet>().unwrap().prefix, 0);
+        // IPv4射影アドレスはIPv4として扱う
+        let mapped: IpNet = "::ffff:10.0.0.1/32".parse().unwrap();
+        assert_eq!(mapped.addr, ip("10.0.0.1"));
+        for s in [
+            "10.0.0.1/33",
+            "fe80::1/129",
+            "10.0.0.1/",
+            "10.0.0.1/-1",
+            "10.0.0/8",
+            "",
+        ] {
+            assert!(s.parse::<IpNet>().is_err(), "{}", s);
         }
     }
+
+    #[test]
+    fn ip_nets_match_addresses_within_the_prefix() {
+        let net: IpNet = "192.168.1.0/24".parse().unwrap();
+        assert!(net.contains(ip("192.168.1.255")));
+        assert!(!net.contains(ip("192.168.2.1")));
+        // "::"で待ち受けたときの送信元
+        assert!(net.contains(ip("::ffff:192.168.1.9")));
+        let any: IpNet = "0.0.0.0/0".parse().unwrap();
+        assert!(any.contains(ip("203.0.113.5")));
+        assert!(!any.contains(ip("2001:db8::1")));
+        let host: IpNet = "10.0.0.1/32".parse().unwrap();
+        assert!(host.contains(ip("10.0.0.1")));
+        assert!(!host.contains(ip("10.0.0.2")));
+        let any6: IpNet = "::/0".parse().unwrap();
+        assert!(any6.contains(ip("2001:db8::1")));
+        let host6: IpNet = "2001:db8::1/128".parse().unwrap();
+        assert!(host6.contains(ip("2001:db8::1")));
+        assert!(!host6.contains(ip("2001:db8::2")));
+        let net6: IpNet = "2001:db8::/32".parse().unwrap();
+        assert!(net6.contains(ip("2001:db8:ffff::1")));
+        assert!(!net6.contains(ip("2001:db9::1")));
+    }
+
+    #[tokio::test]
+    async fn sniffing_reports_packet_metadata_without_answering() {
+        // 空いているポートを借りる
+        let port = std::net::UdpSocket::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let receiver = NetworkReceiver::new(
+            port,
+            ReceiverOptions {
+                bind_address: Some(Ipv4Addr::LOCALHOST.into()),
+                ..Default::default()
+            },
+            ReceiverInfo::default(),
+            None,
+        );
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let sniffer = tokio::spawn(async move { receiver.sniff(tx).await });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let target = SocketAddr::from((Ipv4Addr::LOCALHOST, port));
+        let packets = [
+            Packet::Hello {
+                codecs: SUPPORTED_CODECS.to_vec(),
+                normalized: false,
+                version: PROTOCOL_VERSION,
+            },
+            Packet::Ping { id: 1 },
+            Packet::TimedEvent {
+                epoch: 1,
+                seq: 7,
+                sent_ms: 1234,
+                event: MouseEvent::LeftClick,
+            },
+            Packet::Compressed(
+                Codec::Deflate
+                    .compress(
+                        &bincode::serialize(&Packet::Event {
+                            epoch: 1,
+                            seq: 8,
+                            event: MouseEvent::Move { x: 1.0, y: 2.0 },
+                        })
+                        .unwrap(),
+                    )
+                    .unwrap(),
+            ),
+        ];
+        for packet in &packets {
+            let data = bincode::serialize(packet).unwrap();
+            socket.send_to(&data, target).await.unwrap();
+        }
+
+        let click = rx.recv().await.unwrap();
+        assert_eq!(click.addr, socket.local_addr().unwrap());
+        assert_eq!((click.seq, click.sent_ms), (Some(7), Some(1234)));
+        assert_eq!(click.event, MouseEvent::LeftClick);
+        // 取り決めていない圧縮も読める
+        let moved = rx.recv().await.unwrap();
+        assert_eq!((moved.seq, moved.sent_ms), (Some(8), None));
+        assert_eq!(moved.event, MouseEvent::Move { x: 1.0, y: 2.0 });
+        // HelloにもPingにも応答しない
+        let mut buf = [0u8; 1024];
+        let reply =
+            tokio::time::timeout(Duration::from_millis(200), socket.recv_from(&mut buf)).await;
+        assert!(reply.is_err(), "sniffer answered the sender");
+        sniffer.abort();
+    }
+
+    #[test]
+    fn decompression_is_capped_at_the_frame_limit() {
+        let fits = vec![7u8; MAX_FRAME_LEN];
+        let packed = Codec::Deflate.compress(&fits).unwrap();
+        assert_eq!(Codec::Deflate.decompress(&packed).unwrap(), fits);
+        // 数十KBのパケットが何MBにも展開されるのを防ぐ
+        let bomb = Codec::Deflate.compress(&vec![0u8; 8 << 20]).unwrap();
+        assert!(bomb.len() < MAX_DATAGRAM_LEN);
+        assert!(Codec::Deflate.decompress(&bomb).is_err());
+    }
+
+    #[test]
+    fn frames_split_across_reads_are_reassembled() {
+        let mut stream = encode_frame(b"hello");
+        stream.extend(encode_frame(b""));
+        stream.extend(encode_frame(b"world!"));
+        let mut decoder = FrameDecoder::new();
+        let mut frames = Vec::new();
+        // 1バイトずつ届いても、長さプレフィックスの途中でも取り出せない
+        for byte in &stream {
+            decoder.extend(std::slice::from_ref(byte));
+            while let Some(frame) = decoder.next_frame().unwrap() {
+                frames.push(frame);
+            }
+        }
+        assert_eq!(
+            frames,
+            vec![b"hello".to_vec(), Vec::new(), b"world!".to_vec()]
+        );
+        assert!(decoder.next_frame().unwrap().is_none());
+    }
+
+    #[test]
+    fn several_frames_in_one_read_come_out_in_order() {
+        let mut decoder = FrameDecoder::new();
+        let mut data = encode_frame(b"a");
+        data.extend(encode_frame(b"bc"));
+        data.extend(&encode_frame(b"def")[..5]);
+        decoder.extend(&data);
+        assert_eq!(decoder.next_frame().unwrap(), Some(b"a".to_vec()));
+        assert_eq!(decoder.next_frame().unwrap(), Some(b"bc".to_vec()));
+        assert_eq!(decoder.next_frame().unwrap(), None);
+        decoder.extend(b"ef");
+        assert_eq!(decoder.next_frame().unwrap(), Some(b"def".to_vec()));
+    }
+
+    #[test]
+    fn oversized_frames_are_rejected() {
+        let mut decoder = FrameDecoder::new();
+        decoder.extend(&((MAX_FRAME_LEN + 1) as u32).to_be_bytes());
+        assert!(decoder.next_frame().is_err());
+    }
+
+    #[tokio::test]
+    async fn read_frame_waits_for_partial_reads() {
+        // 小さいバッファで、1回のreadが数バイトしか返らないようにする
+        let (mut writer, mut reader) = tokio::io::duplex(3);
+        tokio::spawn(async move {
+            use tokio::io::AsyncWriteExt;
+            writer
+                .write_all(&encode_frame(b"partial frame"))
+                .await
+                .unwrap();
+            writer.write_all(&encode_frame(b"next")).await.unwrap();
+            writer.write_all(&[0, 0]).await.unwrap();
+        });
+        let mut decoder = FrameDecoder::new();
+        let frame = read_frame(&mut reader, &mut decoder).await.unwrap();
+        assert_eq!(frame, Some(b"partial frame".to_vec()));
+        let frame = read_frame(&mut reader, &mut decoder).await.unwrap();
+        assert_eq!(frame, Some(b"next".to_vec()));
+        // 途中で閉じられたフレームは捨てて、接続終了として扱う
+        assert_eq!(read_frame(&mut reader, &mut decoder).await.unwrap(), None);
+    }
 }
@@ -0,0 +1,157 @@
+/// 1つの権限の確認結果
+pub struct PermissionStatus {
+    pub name: &'static str,
+    pub granted: bool,
+    /// 拒否されている場合の対処方法
+    pub remediation: &'static str,
+    /// falseなら代わりの手段があるので、なくても失敗扱いにしない
+    pub required: bool,
+}
+
+/// 必要な権限とツールを全て確認して結果を表示する。必須のものが全て揃っていればtrue
+pub fn report() -> bool {
+    let statuses = check();
+    for status in &statuses {
+        let label = match (status.granted, status.required) {
+            (true, _) => "ok",
+            (false, true) => "missing",
+            (false, false) => "missing (optional)",
+        };
+        println!("{:<20} {}", status.name, label);
+        if !status.granted {
+            println!("  -> {}", status.remediation);
+        }
+    }
+    statuses
+        .iter()
+        .all(|status| status.granted || !status.required)
+}
+
+/// イベントを作れるか（キャプチャの開始時と同じ確認）
+#[cfg(target_os = "macos")]
+pub fn probe_event_source() -> bool {
+    use core_graphics::event::{CGEvent, CGEventType, CGMouseButton};
+    use core_graphics::event_source::{CGEventSource, CGEventSourceStateID};
+    use core_graphics::geometry::CGPoint;
+
+    CGEventSource::new(CGEventSourceStateID::CombinedSessionState)
+        .and_then(|source| {
+            CGEvent::new_mouse_event(
+                source,
+                CGEventType::MouseMoved,
+                CGPoint::new(0.0, 0.0),
+                CGMouseButton::Left,
+            )
+        })
+        .is_ok()
+}
+
+#[cfg(target_os = "macos")]
+pub fn check() -> Vec<PermissionStatus> {
+    #[link(name = "ApplicationServices", kind = "framework")]
+    extern "C" {
+        fn AXIsProcessTrusted() -> bool;
+    }
+    #[link(name = "CoreGraphics", kind = "framework")]
+    extern "C" {
+        fn CGPreflightListenEventAccess() -> bool;
+    }
+
+    // どちらも問い合わせるだけで、許可ダイアログは出さない
+    let accessibility = unsafe { AXIsProcessTrusted() };
+    let input_monitoring = unsafe { CGPreflightListenEventAccess() };
+    vec![
+        PermissionStatus {
+            name: "Accessibility",
+            granted: accessibility,
+            remediation: "Open System Settings > Privacy & Security > Accessibility and enable this terminal (or the sharemouse binary), then restart it",
+            required: true,
+        },
+        PermissionStatus {
+            name: "Input Monitoring",
+            granted: input_monitoring,
+            remediation: "Open System Settings > Privacy & Security > Input Monitoring and enable this terminal (or the sharemouse binary), then restart it",
+            required: true,
+        },
+        PermissionStatus {
+            name: "Event source",
+            granted: probe_event_source(),
+            remediation: "Grant Accessibility as above; if it is already granted, remove and re-add the terminal in that list",
+            required: true,
+        },
+    ]
+}
+
+#[cfg(target_os = "linux")]
+pub fn check() -> Vec<PermissionStatus> {
+    use std::path::Path;
+    use std::process::Command;
+
+    // 入力デバイスが1つでも読めればキャプチャできる
+    let device_access = std::fs::read_dir("/dev/input")
+        .map(|entries| {
+            entries.flatten().any(|entry| {
+                entry.file_name().to_string_lossy().starts_with("event")
+                    && std::fs::File::open(entry.path()).is_ok()
+            })
+        })
+        .unwrap_or(false);
+    let ydotool = Command::new("ydotool")
+        .arg("--help")
+        .output()
+        .map(|output| output.status.success())
+        .unwrap_or(false);
+    let socket = std::env::var("YDOTOOL_SOCKET").unwrap_or_else(|_| "/tmp/.ydotool_socket".into());
+    let ydotoold = Path::new(&socket).exists();
+    let uinput = std::fs::OpenOptions::new()
+        .write(true)
+        .open("/dev/uinput")
+        .is_ok();
+    let x11 = crate::injector::linux::is_x11_session();
+    let xtest = x11 && crate::injector::linux::X11Injector::new(&Default::default()).is_ok();
+    let mut statuses = vec![
+        PermissionStatus {
+            name: "Input devices",
+            granted: device_access,
+            remediation:
+                "Add your user to the input group (sudo usermod -aG input $USER) and log in again",
+            required: true,
+        },
+        PermissionStatus {
+            name: "Injection backend",
+            granted: xtest || uinput || (ydotool && ydotoold),
+            remediation: "Make one of the options below available",
+            required: true,
+        },
+    ];
+    if x11 {
+        statuses.push(PermissionStatus {
+            name: "XTest",
+            granted: xtest,
+            remediation:
+                "Make sure DISPLAY points to your X server and the XTEST extension is enabled",
+            required: false,
+        });
+    }
+    statuses.extend([
+        PermissionStatus {
+            name: "uinput",
+            granted: uinput,
+            remediation: "Load the module (sudo modprobe uinput) and allow your user to write /dev/uinput (e.g. a udev rule granting the input group)",
+            required: false,
+        },
+        PermissionStatus {
+            name: "ydotool",
+            granted: ydotool,
+            remediation: "Install ydotool (e.g. sudo apt install ydotool) and make sure it is on PATH",
+            required: false,
+        },
+        PermissionStatus {
+            name: "ydotoold",
+            granted: ydotoold,
+            remediation: "Start the ydotoold daemon (e.g. systemctl --user enable --now ydotool) or set YDOTOOL_SOCKET",
+            required: false,
+        },
+    ]);
+    statuses
+}
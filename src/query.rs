@@ -0,0 +1,78 @@
+use crate::config::Config;
+use crate::network::{PeerStatus, SharedHeartbeat};
+use crate::virtual_model::SharedVirtualModel;
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{UnixListener, UnixStream};
+
+/// 実行中のsenderに問い合わせた結果
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Status {
+    /// "local" または "remote"
+    pub side: String,
+    pub x: f64,
+    pub y: f64,
+    /// 受信側との接続状態
+    pub peer: PeerStatus,
+}
+
+/// 問い合わせ用ソケットのパス
+pub fn socket_path() -> PathBuf {
+    let dir = std::env::var_os("XDG_RUNTIME_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(std::env::temp_dir);
+    let user = std::env::var("USER").unwrap_or_default();
+    dir.join(format!("sharemouse-{}.sock", user))
+}
+
+/// 接続ごとに現在の状態をJSONで1行返す
+pub async fn serve(
+    config: Config,
+    virtual_model: SharedVirtualModel,
+    heartbeat: SharedHeartbeat,
+) -> Result<()> {
+    let path = socket_path();
+    // 前回異常終了したときのソケットが残っていることがある
+    let _ = std::fs::remove_file(&path);
+    let listener = UnixListener::bind(&path)?;
+    log::info!("Query socket listening on {:?}", path);
+    loop {
+        let (mut stream, _) = listener.accept().await?;
+        let peer = match heartbeat.lock() {
+            Ok(heartbeat) => heartbeat.status(),
+            Err(_) => continue,
+        };
+        let status = match virtual_model.lock() {
+            Ok(vm) => Status {
+                side: if vm.in_host(&config) {
+                    "local"
+                } else {
+                    "remote"
+                }
+                .to_string(),
+                x: vm.virtual_x,
+                y: vm.virtual_y,
+                peer,
+            },
+            Err(_) => continue,
+        };
+        let mut line = serde_json::to_string(&status)?;
+        line.push('\n');
+        if let Err(e) = stream.write_all(line.as_bytes()).await {
+            log::debug!("Failed to answer status query: {}", e);
+        }
+    }
+}
+
+/// 実行中のsenderに制御側を問い合わせる
+pub async fn query() -> Result<Option<Status>> {
+    let stream = match UnixStream::connect(socket_path()).await {
+        Ok(stream) => stream,
+        Err(_) => return Ok(None),
+    };
+    let mut line = String::new();
+    BufReader::new(stream).read_line(&mut line).await?;
+    Ok(Some(serde_json::from_str(&line)?))
+}
@@ -0,0 +1,272 @@
+use crate::event::{MouseEvent, SCROLL_UNITS_PER_NOTCH};
+use crate::injector::MouseInjector;
+use anyhow::Result;
+use flate2::read::DeflateDecoder;
+use flate2::write::DeflateEncoder;
+use flate2::Compression;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, ErrorKind, Read, Write};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+/// 記録ファイルの先頭に置くマジックナンバー
+const MAGIC: &[u8; 5] = b"SMREC";
+/// 記録フォーマットのバージョン。古いバージョンの記録も読めるようにする
+///
+/// 1: スクロール量がノッチ単位
+/// 2: スクロール量が1/`SCROLL_UNITS_PER_NOTCH`ノッチ単位
+const FORMAT_VERSION: u8 = 2;
+
+/// 書き込みをまとめてflushする間隔
+///
+/// 強制終了されてもこの間隔より前の分は読める。通常の終了時はdropで残りをflushする
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// 1エントリの最大長。壊れた記録の長さで巨大な領域を確保しないため
+const MAX_ENTRY_LEN: usize = 64 * 1024;
+
+const CODEC_NONE: u8 = 0;
+const CODEC_DEFLATE: u8 = 1;
+
+/// 記録の1エントリ
+#[derive(Debug, Clone)]
+pub struct RecordedEvent {
+    /// 記録開始からの経過時間
+    pub elapsed: Duration,
+    pub event: MouseEvent,
+}
+
+/// イベントを長さプレフィックス付きbincodeで書き出す
+///
+/// フォーマット: MAGIC, version(u8), codec(u8), 本体
+/// 本体は `[u32 BE 長さ][bincode(経過ms, MouseEvent)]` の繰り返しで、codecに応じて圧縮される
+pub struct RecordingWriter {
+    body: Box<dyn Write>,
+    started: Instant,
+    last_flush: Instant,
+}
+
+impl RecordingWriter {
+    pub fn create<P: AsRef<Path>>(path: P, compress: bool) -> Result<Self> {
+        let mut file = BufWriter::new(File::create(path)?);
+        file.write_all(MAGIC)?;
+        let codec = if compress { CODEC_DEFLATE } else { CODEC_NONE };
+        file.write_all(&[FORMAT_VERSION, codec])?;
+        let body: Box<dyn Write> = if compress {
+            Box::new(DeflateEncoder::new(file, Compression::default()))
+        } else {
+            Box::new(file)
+        };
+        let now = Instant::now();
+        Ok(Self {
+            body,
+            started: now,
+            last_flush: now,
+        })
+    }
+
+    pub fn write(&mut self, event: &MouseEvent) -> Result<()> {
+        let elapsed_ms = self.started.elapsed().as_millis() as u64;
+        let data = bincode::serialize(&(elapsed_ms, event))?;
+        self.body.write_all(&(data.len() as u32).to_be_bytes())?;
+        self.body.write_all(&data)?;
+        // イベントごとにflushすると高頻度の移動でディスクへの書き込みが増えるので、間隔を空ける
+        if self.last_flush.elapsed() >= FLUSH_INTERVAL {
+            self.body.flush()?;
+            self.last_flush = Instant::now();
+        }
+        Ok(())
+    }
+}
+
+impl Drop for RecordingWriter {
+    fn drop(&mut self) {
+        if let Err(e) = self.body.flush() {
+            log::error!("Failed to flush the recording: {}", e);
+        }
+    }
+}
+
+pub struct RecordingReader {
+    body: Box<dyn Read>,
+    version: u8,
+}
+
+impl RecordingReader {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let mut file = BufReader::new(File::open(path)?);
+        let mut header = [0u8; 7];
+        file.read_exact(&mut header)?;
+        if &header[..5] != MAGIC {
+            anyhow::bail!("Not a ShareMouse recording");
+        }
+        let version = header[5];
+        if version > FORMAT_VERSION {
+            anyhow::bail!(
+                "Recording format version {} is newer than supported version {}",
+                version,
+                FORMAT_VERSION
+            );
+        }
+        let body: Box<dyn Read> = match header[6] {
+            CODEC_NONE => Box::new(file),
+            CODEC_DEFLATE => Box::new(DeflateDecoder::new(file)),
+            codec => anyhow::bail!("Unknown recording codec {}", codec),
+        };
+        Ok(Self { body, version })
+    }
+
+    /// 次のイベントを読む。記録の終端なら`None`
+    pub fn next_event(&mut self) -> Result<Option<RecordedEvent>> {
+        let mut len = [0u8; 4];
+        match self.body.read_exact(&mut len) {
+            Ok(()) => {}
+            // 強制終了された記録は途中で切れていることがある
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e.into()),
+        }
+        let len = u32::from_be_bytes(len) as usize;
+        if len > MAX_ENTRY_LEN {
+            anyhow::bail!(
+                "Recorded entry length {} exceeds limit {}",
+                len,
+                MAX_ENTRY_LEN
+            );
+        }
+        let mut data = vec![0u8; len];
+        match self.body.read_exact(&mut data) {
+            Ok(()) => {}
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e.into()),
+        }
+        let (elapsed_ms, mut event): (u64, MouseEvent) = bincode::deserialize(&data)?;
+        if self.version < 2 {
+            if let MouseEvent::Scroll {
+                delta_x, delta_y, ..
+            } = &mut event
+            {
+                *delta_x = delta_x.saturating_mul(SCROLL_UNITS_PER_NOTCH);
+                *delta_y = delta_y.saturating_mul(SCROLL_UNITS_PER_NOTCH);
+            }
+        }
+        Ok(Some(RecordedEvent {
+            elapsed: Duration::from_millis(elapsed_ms),
+            event,
+        }))
+    }
+}
+
+/// 注入するイベントを記録してから内側のインジェクタに渡す
+pub struct RecordingInjector {
+    inner: Box<dyn MouseInjector>,
+    writer: RecordingWriter,
+}
+
+impl RecordingInjector {
+    pub fn new(inner: Box<dyn MouseInjector>, writer: RecordingWriter) -> Self {
+        Self { inner, writer }
+    }
+}
+
+impl MouseInjector for RecordingInjector {
+    fn inject_event(&mut self, event: MouseEvent) -> Result<()> {
+        if let Err(e) = self.writer.write(&event) {
+            log::error!("Failed to record event: {}", e);
+        }
+        self.inner.inject_event(event)
+    }
+
+    fn backend(&self) -> Option<&'static str> {
+        self.inner.backend()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("sharemouse-{}-{}.smrec", name, std::process::id()))
+    }
+
+    fn read_all(path: &Path) -> Vec<MouseEvent> {
+        let mut reader = RecordingReader::open(path).unwrap();
+        let mut events = Vec::new();
+        while let Some(recorded) = reader.next_event().unwrap() {
+            events.push(recorded.event);
+        }
+        events
+    }
+
+    #[test]
+    fn drop_flushes_buffered_events() {
+        for compress in [false, true] {
+            let path = temp_path(&format!("flush-{}", compress));
+            let events = vec![
+                MouseEvent::Move { x: 1.0, y: 2.0 },
+                MouseEvent::LeftClick,
+                MouseEvent::LeftRelease,
+            ];
+            {
+                let mut writer = RecordingWriter::create(&path, compress).unwrap();
+                for event in &events {
+                    writer.write(event).unwrap();
+                }
+            }
+            assert_eq!(read_all(&path), events);
+            let _ = std::fs::remove_file(&path);
+        }
+    }
+
+    #[test]
+    fn version_1_scroll_is_converted_to_fine_units() {
+        let path = temp_path("v1-scroll");
+        {
+            let mut writer = RecordingWriter::create(&path, false).unwrap();
+            writer
+                .write(&MouseEvent::Scroll {
+                    x: 10.0,
+                    y: 20.0,
+                    delta_x: 1,
+                    delta_y: -2,
+                })
+                .unwrap();
+        }
+        // ヘッダのバージョンを1に書き換えて、以前の記録を再現する
+        let mut data = std::fs::read(&path).unwrap();
+        data[MAGIC.len()] = 1;
+        std::fs::write(&path, data).unwrap();
+        assert_eq!(
+            read_all(&path),
+            vec![MouseEvent::Scroll {
+                x: 10.0,
+                y: 20.0,
+                delta_x: SCROLL_UNITS_PER_NOTCH,
+                delta_y: -2 * SCROLL_UNITS_PER_NOTCH,
+            }]
+        );
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn oversized_entries_are_rejected() {
+        for compress in [false, true] {
+            let path = temp_path(&format!("oversized-{}", compress));
+            {
+                let mut writer = RecordingWriter::create(&path, compress).unwrap();
+                writer.write(&MouseEvent::LeftClick).unwrap();
+                // 壊れた長さ（約4GB）のエントリ
+                writer.body.write_all(&u32::MAX.to_be_bytes()).unwrap();
+                writer.body.write_all(&[0; 16]).unwrap();
+            }
+            let mut reader = RecordingReader::open(&path).unwrap();
+            assert_eq!(
+                reader.next_event().unwrap().unwrap().event,
+                MouseEvent::LeftClick
+            );
+            assert!(reader.next_event().is_err());
+            let _ = std::fs::remove_file(&path);
+        }
+    }
+}
@@ -0,0 +1,126 @@
+use chrono::{Datelike, Local, NaiveTime, Weekday};
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+/// 共有を止める時間帯
+///
+/// 書式は `[曜日,...] HH:MM-HH:MM`（例: `sat,sun 00:00-23:59`, `18:00-09:00`）。
+/// 曜日を省略すると毎日。終了時刻が開始時刻より前なら日をまたぐ
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct QuietHours {
+    /// 空なら毎日
+    pub days: Vec<Weekday>,
+    pub start: NaiveTime,
+    pub end: NaiveTime,
+}
+
+impl QuietHours {
+    /// 現在時刻が静音時間帯に入っているか
+    pub fn contains_now(&self) -> bool {
+        let now = Local::now();
+        self.contains(now.weekday(), now.time())
+    }
+
+    pub fn contains(&self, day: Weekday, time: NaiveTime) -> bool {
+        let active = |day: Weekday| self.days.is_empty() || self.days.contains(&day);
+        if self.start <= self.end {
+            active(day) && self.start <= time && time < self.end
+        } else {
+            // 日をまたぐ場合、日付が変わった後は開始した日の曜日で判定する
+            (active(day) && self.start <= time) || (active(day.pred()) && time < self.end)
+        }
+    }
+}
+
+impl FromStr for QuietHours {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let s = s.trim();
+        let (days, range) = match s.rsplit_once(' ') {
+            Some((days, range)) => (days.trim(), range),
+            None => ("", s),
+        };
+        let days = days
+            .split(',')
+            .filter(|day| !day.is_empty())
+            .map(|day| {
+                day.trim()
+                    .parse::<Weekday>()
+                    .map_err(|_| anyhow::anyhow!("invalid weekday {:?}", day))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        let (start, end) = range
+            .split_once('-')
+            .ok_or_else(|| anyhow::anyhow!("expected HH:MM-HH:MM, got {:?}", range))?;
+        let parse_time = |t: &str| {
+            NaiveTime::parse_from_str(t, "%H:%M")
+                .map_err(|_| anyhow::anyhow!("invalid time {:?}, expected HH:MM", t))
+        };
+        Ok(Self {
+            days,
+            start: parse_time(start)?,
+            end: parse_time(end)?,
+        })
+    }
+}
+
+impl TryFrom<String> for QuietHours {
+    type Error = anyhow::Error;
+
+    fn try_from(s: String) -> anyhow::Result<Self> {
+        s.parse()
+    }
+}
+
+impl From<QuietHours> for String {
+    fn from(quiet: QuietHours) -> Self {
+        let range = format!(
+            "{}-{}",
+            quiet.start.format("%H:%M"),
+            quiet.end.format("%H:%M")
+        );
+        if quiet.days.is_empty() {
+            return range;
+        }
+        let days: Vec<String> = quiet
+            .days
+            .iter()
+            .map(|day| day.to_string().to_lowercase())
+            .collect();
+        format!("{} {}", days.join(","), range)
+    }
+}
+
+/// 静音時間帯への出入りを追跡し、切り替わったときにログを出す
+pub struct QuietGate {
+    hours: Option<QuietHours>,
+    quiet: bool,
+}
+
+impl QuietGate {
+    pub fn new(hours: Option<QuietHours>) -> Self {
+        Self {
+            hours,
+            quiet: false,
+        }
+    }
+
+    /// 今イベントを止めるべきか
+    pub fn is_quiet(&mut self) -> bool {
+        let quiet = self
+            .hours
+            .as_ref()
+            .is_some_and(|hours| hours.contains_now());
+        if quiet != self.quiet {
+            self.quiet = quiet;
+            if quiet {
+                log::info!("Entering quiet hours: sharing is paused");
+            } else {
+                log::info!("Leaving quiet hours: sharing is resumed");
+            }
+        }
+        quiet
+    }
+}
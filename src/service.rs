@@ -0,0 +1,135 @@
+use anyhow::Result;
+use std::path::Path;
+
+/// サービスとして起動するモード
+#[derive(Debug, Clone, Copy, PartialEq, clap::ValueEnum)]
+pub enum ServiceMode {
+    Send,
+    Receive,
+}
+
+impl ServiceMode {
+    fn name(&self) -> &'static str {
+        match self {
+            ServiceMode::Send => "send",
+            ServiceMode::Receive => "receive",
+        }
+    }
+}
+
+/// サービスから起動するコマンドライン
+fn program_arguments(
+    mode: ServiceMode,
+    config: &Path,
+    port: u16,
+    log_level: &str,
+) -> Result<Vec<String>> {
+    let exe = std::env::current_exe()?;
+    let mut args = vec![
+        exe.display().to_string(),
+        "--log-level".to_string(),
+        log_level.to_string(),
+        mode.name().to_string(),
+    ];
+    match mode {
+        ServiceMode::Send => {
+            // サービスは作業ディレクトリが異なるので絶対パスにする
+            let config = std::fs::canonicalize(config)
+                .map_err(|e| anyhow::anyhow!("Config {:?} not found: {}", config, e))?;
+            args.extend(["--config".to_string(), config.display().to_string()]);
+        }
+        ServiceMode::Receive => {
+            args.extend(["--port".to_string(), port.to_string()]);
+        }
+    }
+    Ok(args)
+}
+
+/// launchdのplistを生成する
+#[cfg(target_os = "macos")]
+pub fn render(mode: ServiceMode, config: &Path, port: u16, log_level: &str) -> Result<String> {
+    let args = program_arguments(mode, config, port, log_level)?;
+    let args: String = args
+        .iter()
+        .map(|arg| format!("        <string>{}</string>\n", xml_escape(arg)))
+        .collect();
+    Ok(format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
+<plist version="1.0">
+<dict>
+    <key>Label</key>
+    <string>com.sharemouse.{name}</string>
+    <key>ProgramArguments</key>
+    <array>
+{args}    </array>
+    <key>RunAtLoad</key>
+    <true/>
+    <key>KeepAlive</key>
+    <true/>
+    <key>StandardOutPath</key>
+    <string>/tmp/sharemouse-{name}.log</string>
+    <key>StandardErrorPath</key>
+    <string>/tmp/sharemouse-{name}.log</string>
+</dict>
+</plist>
+"#,
+        name = mode.name(),
+        args = args
+    ))
+}
+
+#[cfg(target_os = "macos")]
+fn xml_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+/// systemdのユーザーユニットを生成する
+#[cfg(target_os = "linux")]
+pub fn render(mode: ServiceMode, config: &Path, port: u16, log_level: &str) -> Result<String> {
+    let args = program_arguments(mode, config, port, log_level)?;
+    let exec_start: Vec<String> = args
+        .iter()
+        .map(|arg| {
+            if arg.contains(char::is_whitespace) {
+                format!("\"{}\"", arg)
+            } else {
+                arg.clone()
+            }
+        })
+        .collect();
+    // 注入やキャプチャに必要な環境変数は、生成時のセッションの値を引き継ぐ
+    let environment: String = [
+        "DISPLAY",
+        "WAYLAND_DISPLAY",
+        "XDG_RUNTIME_DIR",
+        "YDOTOOL_SOCKET",
+    ]
+    .iter()
+    .filter_map(|key| {
+        std::env::var(key)
+            .ok()
+            .map(|value| format!("Environment={}={}\n", key, value))
+    })
+    .collect();
+    Ok(format!(
+        "[Unit]
+Description=ShareMouse {name}
+After=graphical-session.target
+PartOf=graphical-session.target
+
+[Service]
+ExecStart={exec_start}
+{environment}Restart=on-failure
+RestartSec=2
+
+[Install]
+WantedBy=graphical-session.target
+",
+        name = mode.name(),
+        exec_start = exec_start.join(" "),
+        environment = environment
+    ))
+}
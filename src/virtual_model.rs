@@ -1,67 +1,867 @@
 use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
-use crate::config::{Config, HostPosition};
+use crate::clock::{Clock, SystemClock};
+use crate::config::{Config, HostPosition, WarpStrategy, EDGE_WARP_INSET_PX};
+use crate::coordinate::LOG_TARGET;
+use crate::event::MouseEvent;
 
 /// 仮想マウスモデル - virtual_xとvirtual_yを管理
 pub struct VirtualModel {
     pub virtual_x: f64,
     pub virtual_y: f64,
+    /// リモート操作中に物理カーソルを固定する位置（移動量の基準点）
+    pub anchor_x: f64,
+    pub anchor_y: f64,
+    /// 初回の制御権移譲がユーザー確認待ちかどうか
+    pub transfer_blocked: bool,
+    transfer_prompted: bool,
+    /// 双方向モードで相手から操作されている間か（その間はリモートへ移譲しない）
+    pub remote_controlled: bool,
+    /// 押下中のボタン（`event::BUTTON_*` のビットマスク）
+    pub held_buttons: u8,
+    /// 直近の移動速度（px/ms）
+    pub velocity: f64,
+    last_update: Option<Instant>,
+    clock: Box<dyn Clock>,
+    /// 最後に制御側が切り替わった時刻
+    last_transfer: Option<Instant>,
+    /// 移譲エッジに押し当て始めた時刻
+    edge_since: Option<Instant>,
+    /// エッジ接近を予告中か
+    previewing: bool,
 }
 
+/// エッジからこの距離（px）以上離れたら、押し当てている時間を数え直す
+const EDGE_DWELL_SLACK_PX: f64 = 2.0;
+
+/// スリープ中の画面を起こすために送る往復移動の回数
+const WAKE_MOVE_COUNT: usize = 3;
+
 fn inner_crop(target: f64, max: f64) -> f64 {
     target.max(0.0).min(max)
 }
 
+/// 0..=maxの範囲に、両端から`EDGE_WARP_INSET_PX`内側へ収める
+fn inset_crop(target: f64, max: f64) -> f64 {
+    target.min(max - EDGE_WARP_INSET_PX).max(EDGE_WARP_INSET_PX)
+}
+
+/// ワープ先はOSが画素に丸めるので、ワープの反映とみなす最小の誤差（px）
+const WARP_ECHO_TOLERANCE_PX: f64 = 0.5;
+
 fn local_x_to_virtual(config: &Config, x: f64) -> f64 {
     if config.host_position == HostPosition::Right {
         return config.remote_screen.width as f64 + x;
     }
-    return x;
+    x
+}
+
+/// 仮想x座標がホスト側か
+///
+/// 左配置ならホストは[0, screen.width)、右配置なら[remote_screen.width, 全幅)
+fn is_host_x(config: &Config, virtual_x: f64) -> bool {
+    match config.host_position {
+        HostPosition::Left => virtual_x < config.screen.width as f64,
+        HostPosition::Right => config.remote_screen.width as f64 <= virtual_x,
+    }
+}
+
+/// in_hostの境界となる仮想x座標
+fn boundary_x(config: &Config) -> f64 {
+    match config.host_position {
+        HostPosition::Left => config.screen.width as f64,
+        HostPosition::Right => config.remote_screen.width as f64,
+    }
+}
+
+/// 画面を移ったときに入る仮想x座標（境界からentry_margin_px内側）
+fn entry_x(config: &Config, to_remote: bool) -> f64 {
+    let margin = config.entry_margin_px.max(1.0);
+    match (config.host_position, to_remote) {
+        (HostPosition::Left, true) | (HostPosition::Right, false) => boundary_x(config) + margin,
+        (HostPosition::Left, false) | (HostPosition::Right, true) => boundary_x(config) - margin,
+    }
+}
+
+/// 高さの異なる画面の間でy座標を移す
+///
+/// scale_on_transferなら高さの比率で変換し、そうでなければそのまま（はみ出た分は後でクロップされる）
+fn transfer_y(config: &Config, y: f64, from_height: u32, to_height: u32) -> f64 {
+    if config.scale_on_transfer {
+        y * to_height as f64 / from_height as f64
+    } else {
+        y
+    }
 }
 
 impl VirtualModel {
     pub fn new() -> Self {
+        Self::with_clock(Box::new(SystemClock))
+    }
+    pub fn with_clock(clock: Box<dyn Clock>) -> Self {
         Self {
             virtual_x: 0.0,
             virtual_y: 0.0,
+            anchor_x: 0.0,
+            anchor_y: 0.0,
+            transfer_blocked: false,
+            transfer_prompted: false,
+            remote_controlled: false,
+            held_buttons: 0,
+            velocity: 0.0,
+            last_update: None,
+            clock,
+            last_transfer: None,
+            edge_since: None,
+            previewing: false,
+        }
+    }
+    /// ボタンの押下状態を更新する
+    pub fn track_button(&mut self, event: &MouseEvent) {
+        if let Some((button, pressed)) = event.button() {
+            if pressed {
+                self.held_buttons |= button;
+            } else {
+                self.held_buttons &= !button;
+            }
         }
     }
     pub fn init(&mut self, config: &Config, x: f64, y: f64) {
+        let (x, y) = config.screen.to_physical(x, y);
         self.virtual_x = local_x_to_virtual(config, x);
         self.virtual_y = y;
+        self.transfer_blocked = config.confirm_first_transfer;
+        (self.anchor_x, self.anchor_y) = config.host_center();
     }
     pub fn in_host(&self, config: &Config) -> bool {
-        if config.host_position == HostPosition::Right {
-            return config.remote_screen.width as f64 <= self.virtual_x;
-        }
-        return config.screen.width as f64 <= self.virtual_x;
+        is_host_x(config, self.virtual_x)
     }
     pub fn crop(&self, config: &Config, x: f64, y: f64) -> (f64, f64) {
         let n_x = inner_crop(x, (config.screen.width + config.remote_screen.width) as f64);
         let n_y = inner_crop(y, config.remote_screen.height as f64);
-        return (n_x, n_y);
+        (n_x, n_y)
     }
     pub fn update(&mut self, config: &Config, x: f64, y: f64) {
-        if self.in_host(config) {
+        if !x.is_finite() || !y.is_finite() {
+            log::warn!("Ignoring non-finite mouse position ({}, {})", x, y);
+            return;
+        }
+        let was_in_host = self.in_host(config);
+        let (prev_x, prev_y) = (self.virtual_x, self.virtual_y);
+        if was_in_host {
+            let (x, y) = config.screen.to_physical(x, y);
             self.virtual_x = local_x_to_virtual(config, x);
             self.virtual_y = y;
-            return;
+        } else {
+            if self.is_warp_echo(config, x, y) {
+                return;
+            }
+            // 回転・反転した画面でも、物理的な向きでの移動量にしてから積算する
+            let (x_p, y_p) = config.screen.to_physical(x, y);
+            let (anchor_x_p, anchor_y_p) = config.screen.to_physical(self.anchor_x, self.anchor_y);
+            let d_x = x_p - anchor_x_p;
+            let d_y = y_p - anchor_y_p;
+            // 倍率を掛けた後でクロップするので、仮想画面の外には出ない
+            let scale = config.sensitivity * (1.0 + config.acceleration * d_x.hypot(d_y));
+            let (d_x, d_y) = (d_x * scale, d_y * scale);
+            let (n_x, n_y) = self.crop(config, self.virtual_x + d_x, self.virtual_y + d_y);
+            self.virtual_x = n_x;
+            self.virtual_y = n_y;
+        }
+        let now = self.clock.now();
+        if let Some(last) = self.last_update {
+            let elapsed_ms = now.duration_since(last).as_secs_f64() * 1000.0;
+            if elapsed_ms > 0.0 {
+                let distance = (self.virtual_x - prev_x).hypot(self.virtual_y - prev_y);
+                self.velocity = distance / elapsed_ms;
+            }
+        }
+        self.last_update = Some(now);
+        let crossing = was_in_host != self.in_host(config);
+        if crossing && !self.crossing_allowed(config, was_in_host, prev_y) {
+            // 越えられないエッジは壁として扱う
+            self.virtual_x = prev_x;
+        }
+        if !crossing && (self.virtual_x - boundary_x(config)).abs() > EDGE_DWELL_SLACK_PX {
+            self.edge_since = None;
+        }
+        if was_in_host != self.in_host(config) {
+            self.last_transfer = Some(now);
+            self.edge_since = None;
+        }
+        if was_in_host && !self.in_host(config) {
+            self.enter_remote(config, x, y);
+        }
+        if !was_in_host && self.in_host(config) {
+            self.enter_host(config);
+        }
+        if !self.virtual_x.is_finite() || !self.virtual_y.is_finite() {
+            // NaNはclampで除去されず以降の状態を全て汚染するので、直前の値に戻す
+            log::warn!(
+                "Virtual position became non-finite ({}, {}), resetting to ({}, {})",
+                self.virtual_x,
+                self.virtual_y,
+                prev_x,
+                prev_y
+            );
+            self.virtual_x = prev_x;
+            self.virtual_y = prev_y;
+        }
+        log::debug!(
+            target: LOG_TARGET,
+            "update: local ({:.1}, {:.1}) in_host={} -> virtual ({:.1}, {:.1}) -> virtual ({:.1}, {:.1}) in_host={}",
+            x,
+            y,
+            was_in_host,
+            prev_x,
+            prev_y,
+            self.virtual_x,
+            self.virtual_y,
+            self.in_host(config)
+        );
+    }
+    /// リモート操作中の位置がアンカーへのワープそのもの（とその端数）か
+    pub fn is_warp_echo(&self, config: &Config, x: f64, y: f64) -> bool {
+        let tolerance = config.center_deadband_px.max(WARP_ECHO_TOLERANCE_PX);
+        !self.in_host(config)
+            && (x - self.anchor_x).abs() <= tolerance
+            && (y - self.anchor_y).abs() <= tolerance
+    }
+    /// エッジを越えて制御側を切り替えてよいか
+    fn crossing_allowed(&mut self, config: &Config, from_host: bool, y: f64) -> bool {
+        if self.held_buttons != 0 {
+            // ドラッグ中は押下からリリースまで同じ側で完結させる
+            return false;
+        }
+        if let Some(last) = self.last_transfer {
+            // 切り替え直後に反対側へ跳ね返らないようにする
+            let cooldown = Duration::from_millis(config.transfer_cooldown_ms);
+            if self.clock.now().saturating_duration_since(last) < cooldown {
+                return false;
+            }
+        }
+        if self.velocity < config.edge_min_velocity {
+            // ゆっくり押し当てただけでは移譲しない
+            log::debug!(
+                "Edge hit too slowly ({:.2} px/ms < {:.2} px/ms)",
+                self.velocity,
+                config.edge_min_velocity
+            );
+            return false;
+        }
+        if !from_host {
+            return config
+                .edge
+                .to_host
+                .contains(y / config.remote_screen.height as f64);
+        }
+        if config
+            .quiet_hours
+            .as_ref()
+            .is_some_and(|hours| hours.contains_now())
+        {
+            // 静音時間帯はホスト側に留める
+            return false;
+        }
+        if self.remote_controlled {
+            // 相手に操作されて動いたカーソルで、相手へ移譲し返さない
+            return false;
+        }
+        if self.transfer_blocked {
+            // 初回の制御権移譲は確認されるまでホスト側に留める
+            if !self.transfer_prompted {
+                self.transfer_prompted = true;
+                log::warn!(
+                    "Transfer to remote is blocked: press Enter in this terminal to allow it"
+                );
+            }
+            return false;
+        }
+        if !config
+            .edge
+            .to_remote
+            .contains(y / config.screen.height as f64)
+        {
+            return false;
+        }
+        if config.edge_dwell_ms > 0 {
+            // しばらく押し当て続けてはじめて移譲する（勢い余って触れただけでは移らない）
+            let now = self.clock.now();
+            let since = *self.edge_since.get_or_insert(now);
+            if now.saturating_duration_since(since) < Duration::from_millis(config.edge_dwell_ms) {
+                return false;
+            }
+        }
+        true
+    }
+    /// ホスト → リモートに切り替わったときの初期化
+    ///
+    /// 前回のリモート操作の値を引き継がないよう、仮想座標を現在の物理位置から作り直す
+    fn enter_remote(&mut self, config: &Config, x: f64, y: f64) {
+        let (_, physical_y) = config.screen.to_physical(x, y);
+        let entry_y = transfer_y(
+            config,
+            physical_y,
+            config.screen.height,
+            config.remote_screen.height,
+        );
+        (self.virtual_x, self.virtual_y) = self.crop(config, entry_x(config, true), entry_y);
+        let (width, height) = config.screen.os_size();
+        (self.anchor_x, self.anchor_y) = match config.warp_strategy {
+            WarpStrategy::Center => config.host_center(),
+            // 画面端に留めると、端に向かう移動がOSにクランプされて測れない
+            WarpStrategy::Park => (inset_crop(x, width - 1.0), inset_crop(y, height - 1.0)),
+            WarpStrategy::Edge => {
+                let edge_x = match config.host_position {
+                    HostPosition::Left => config.screen.width as f64 - 1.0 - EDGE_WARP_INSET_PX,
+                    HostPosition::Right => EDGE_WARP_INSET_PX,
+                };
+                config.screen.to_os(
+                    edge_x,
+                    inner_crop(physical_y, config.screen.height as f64 - 1.0),
+                )
+            }
+        };
+    }
+    /// リモート → ホストに切り替わったときの初期化
+    ///
+    /// home_positionがあれば、仮想座標をそこに置き直す。物理カーソルはリモート操作中の
+    /// アンカーなどに残っているので、呼び出し側が`local_position`へワープして仮想座標と揃える
+    fn enter_host(&mut self, config: &Config) {
+        if let Some(home) = config.home_position {
+            let (x, y) = config.screen.to_physical(home.x, home.y);
+            self.virtual_x = local_x_to_virtual(config, x);
+            self.virtual_y = y;
+        } else {
+            self.virtual_x = entry_x(config, false);
+            let y = transfer_y(
+                config,
+                self.virtual_y,
+                config.remote_screen.height,
+                config.screen.height,
+            );
+            // リモートの方が高ければ、ホストの画面外に出ないようにする
+            self.virtual_y = inner_crop(y, config.screen.height as f64 - 1.0);
+        }
+    }
+    /// エッジを使わずに制御側を切り替える（ホットキー用）。切り替えたらtrue
+    ///
+    /// ホストにはいつでも戻せるが、リモートへは静音時間帯と初回確認に従う
+    pub fn toggle_control(&mut self, config: &Config) -> bool {
+        let to_remote = self.in_host(config);
+        if to_remote
+            && (self.transfer_blocked
+                || self.remote_controlled
+                || config
+                    .quiet_hours
+                    .as_ref()
+                    .is_some_and(|hours| hours.contains_now()))
+        {
+            log::info!("Ignoring the control hotkey: transfer to remote is not allowed now");
+            return false;
+        }
+        self.switch_control(config, to_remote);
+        log::info!(
+            "Control switched to the {} by hotkey",
+            if to_remote { "remote" } else { "host" }
+        );
+        true
+    }
+    /// 制御がリモートにあればホストに戻す（終了時用）。戻したらtrue
+    pub fn return_to_host(&mut self, config: &Config) -> bool {
+        if self.in_host(config) {
+            return false;
+        }
+        self.switch_control(config, false);
+        true
+    }
+    /// 仮想カーソルを境界の向こう側に置き、制御側を切り替える
+    fn switch_control(&mut self, config: &Config, to_remote: bool) {
+        if to_remote {
+            let (x, y) = self.local_position(config);
+            self.enter_remote(config, x, y);
+        } else {
+            self.enter_host(config);
+        }
+        self.last_transfer = Some(self.clock.now());
+        self.previewing = false;
+    }
+    /// 仮想座標 → ホストのOS座標
+    pub fn local_position(&self, config: &Config) -> (f64, f64) {
+        let x = if config.host_position == HostPosition::Right {
+            self.virtual_x - config.remote_screen.width as f64
+        } else {
+            self.virtual_x
+        };
+        let (local_x, local_y) = config.screen.to_os(x, self.virtual_y);
+        log::debug!(
+            target: LOG_TARGET,
+            "local_position: virtual ({:.1}, {:.1}) -> unflipped ({:.1}, {:.1}) -> local ({:.1}, {:.1})",
+            self.virtual_x,
+            self.virtual_y,
+            x,
+            self.virtual_y,
+            local_x,
+            local_y
+        );
+        (local_x, local_y)
+    }
+    /// リモート画面を起こすための小さな移動
+    ///
+    /// 移動は絶対座標なので、最後に元の位置へ戻せばずれは残らない
+    pub fn wake_moves(&self, config: &Config) -> Vec<MouseEvent> {
+        let (x, y) = self.receiver_position(config);
+        // 画面端でもはみ出さない向きに揺らす
+        let dx = if x >= 1.0 { -1.0 } else { 1.0 };
+        (0..WAKE_MOVE_COUNT)
+            .flat_map(|_| [MouseEvent::Move { x: x + dx, y }, MouseEvent::Move { x, y }])
+            .collect()
+    }
+    /// エッジへの接近・離脱を判定し、受信側に伝えるイベントを返す
+    pub fn edge_preview(&mut self, config: &Config) -> Option<MouseEvent> {
+        if config.edge_preview_px <= 0.0 {
+            return None;
+        }
+        let boundary = boundary_x(config);
+        let near = self.in_host(config)
+            && (self.virtual_x - boundary).abs() <= config.edge_preview_px
+            && config
+                .edge
+                .to_remote
+                .contains(self.virtual_y / config.screen.height as f64);
+        match (near, self.previewing) {
+            (true, _) => {
+                self.previewing = true;
+                // リモートに入ったときの位置
+                let entry_y = transfer_y(
+                    config,
+                    self.virtual_y,
+                    config.screen.height,
+                    config.remote_screen.height,
+                )
+                .min(config.remote_screen.height as f64 - 1.0);
+                let (x, y) = self.remote_position(config, entry_x(config, true), entry_y);
+                Some(MouseEvent::EdgeApproach { x, y })
+            }
+            (false, true) => {
+                self.previewing = false;
+                Some(MouseEvent::EdgeRetreat)
+            }
+            (false, false) => None,
         }
-        let (center_x, center_y) = config.host_center();
-        let d_x = x - center_x;
-        let d_y = y - center_y;
-        let (n_x, n_y) = self.crop(config, self.virtual_x + d_x, self.virtual_y + d_y);
-        self.virtual_x = n_x;
-        self.virtual_y = n_y;
     }
     pub fn receiver_position(&self, config: &Config) -> (f64, f64) {
-        if config.host_position == HostPosition::Right {
-            (self.virtual_x, self.virtual_y)
+        self.remote_position(config, self.virtual_x, self.virtual_y)
+    }
+    /// 仮想座標 → 受信側のOS座標
+    fn remote_position(&self, config: &Config, virtual_x: f64, virtual_y: f64) -> (f64, f64) {
+        let x = if config.host_position == HostPosition::Right {
+            virtual_x
         } else {
-            (self.virtual_x - config.screen.width as f64, self.virtual_y)
+            virtual_x - config.screen.width as f64
+        };
+        let (mut remote_x, mut remote_y) = config.remote_screen.to_os(x, virtual_y);
+        if let Some(monitor) = &config.remote_monitor {
+            // 対象モニターのローカル座標からリモートのデスクトップ座標へ
+            remote_x += monitor.x;
+            remote_y += monitor.y;
         }
+        log::debug!(
+            target: LOG_TARGET,
+            "receiver_position: virtual ({:.1}, {:.1}) -> unflipped ({:.1}, {:.1}) -> remote ({:.1}, {:.1})",
+            virtual_x,
+            virtual_y,
+            x,
+            virtual_y,
+            remote_x,
+            remote_y
+        );
+        (remote_x, remote_y)
     }
 }
 
 /// スレッドセーフなVirtualModel
 pub type SharedVirtualModel = Arc<Mutex<VirtualModel>>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::FakeClock;
+    use crate::config::{EdgeRange, Screen};
+
+    fn screen(width: u32, height: u32) -> Screen {
+        Screen {
+            width,
+            height,
+            flip_x: false,
+            flip_y: false,
+            rotation: 0,
+        }
+    }
+
+    /// ホスト1000x800が左、リモート800x600が右
+    fn config() -> Config {
+        let mut config = Config::template();
+        config.screen = screen(1000, 800);
+        config.remote_screen = screen(800, 600);
+        config
+    }
+
+    fn model(config: &Config, x: f64, y: f64) -> VirtualModel {
+        let mut vm = VirtualModel::new();
+        vm.init(config, x, y);
+        vm
+    }
+
+    fn model_with_clock(config: &Config, clock: &FakeClock, x: f64, y: f64) -> VirtualModel {
+        let mut vm = VirtualModel::with_clock(Box::new(clock.clone()));
+        vm.init(config, x, y);
+        vm
+    }
+
+    #[test]
+    fn host_side_matrix() {
+        // ホスト1000幅、リモート800幅
+        for (host_position, virtual_x, expected) in [
+            (HostPosition::Left, 0.0, true),
+            (HostPosition::Left, 999.9, true),
+            (HostPosition::Left, 1000.0, false),
+            (HostPosition::Left, 1799.0, false),
+            (HostPosition::Right, 0.0, false),
+            (HostPosition::Right, 799.9, false),
+            (HostPosition::Right, 800.0, true),
+            (HostPosition::Right, 1799.0, true),
+        ] {
+            let mut config = config();
+            config.host_position = host_position;
+            assert_eq!(
+                is_host_x(&config, virtual_x),
+                expected,
+                "{:?} at {}",
+                host_position,
+                virtual_x
+            );
+            let mut vm = model(&config, 0.0, 0.0);
+            vm.virtual_x = virtual_x;
+            assert_eq!(vm.in_host(&config), expected);
+        }
+    }
+
+    #[test]
+    fn init_starts_on_the_host_in_either_layout() {
+        for host_position in [HostPosition::Left, HostPosition::Right] {
+            let mut config = config();
+            config.host_position = host_position;
+            for x in [0.0, 500.0, 999.0] {
+                assert!(model(&config, x, 400.0).in_host(&config));
+            }
+        }
+    }
+
+    #[test]
+    fn non_finite_positions_are_ignored() {
+        let config = config();
+        let mut vm = model(&config, 500.0, 400.0);
+        for _ in 0..2 {
+            let before = (vm.virtual_x, vm.virtual_y, vm.in_host(&config));
+            for (x, y) in [
+                (f64::NAN, 400.0),
+                (500.0, f64::NAN),
+                (f64::INFINITY, 400.0),
+                (500.0, f64::NEG_INFINITY),
+            ] {
+                vm.update(&config, x, y);
+                assert_eq!((vm.virtual_x, vm.virtual_y, vm.in_host(&config)), before);
+            }
+            // リモート操作中も同じ
+            assert!(vm.toggle_control(&config));
+        }
+        // 無視した後も普通に動かせる
+        vm.update(&config, vm.anchor_x + 10.0, vm.anchor_y);
+        assert!(vm.virtual_x.is_finite() && vm.virtual_y.is_finite());
+    }
+
+    #[test]
+    fn held_buttons_keep_a_drag_on_one_side() {
+        let config = config();
+        let mut vm = model(&config, 990.0, 400.0);
+        vm.track_button(&MouseEvent::LeftClick);
+        push(&mut vm, &config, 15.0, 400.0);
+        assert!(vm.in_host(&config));
+        vm.track_button(&MouseEvent::LeftRelease);
+        assert_eq!(vm.held_buttons, 0);
+        push(&mut vm, &config, 15.0, 400.0);
+        assert!(!vm.in_host(&config));
+        // リモートでのドラッグ中も戻らない
+        vm.track_button(&MouseEvent::RightClick);
+        push(&mut vm, &config, -100.0, 0.0);
+        assert!(!vm.in_host(&config));
+        vm.track_button(&MouseEvent::RightRelease);
+        push(&mut vm, &config, -100.0, 0.0);
+        assert!(vm.in_host(&config));
+    }
+
+    #[test]
+    fn crossing_follows_edge_ranges_and_blocks() {
+        let mut config = config();
+        config.edge.to_remote = EdgeRange {
+            start: 0.5,
+            end: 1.0,
+        };
+        config.edge.to_host = EdgeRange {
+            start: 0.0,
+            end: 0.5,
+        };
+        // 移譲範囲外のエッジは壁
+        let mut vm = model(&config, 990.0, 100.0);
+        push(&mut vm, &config, 15.0, 100.0);
+        assert!(vm.in_host(&config));
+        vm.update(&config, 990.0, 600.0);
+        push(&mut vm, &config, 15.0, 600.0);
+        assert!(!vm.in_host(&config));
+        // リモートの下半分からは戻れない
+        vm.update(&config, vm.anchor_x, vm.anchor_y + 200.0);
+        push(&mut vm, &config, -100.0, 0.0);
+        assert!(!vm.in_host(&config));
+        vm.update(&config, vm.anchor_x, vm.anchor_y - 400.0);
+        push(&mut vm, &config, -100.0, 0.0);
+        assert!(vm.in_host(&config));
+
+        let config = self::config();
+        let mut vm = model(&config, 990.0, 400.0);
+        vm.transfer_blocked = true;
+        push(&mut vm, &config, 15.0, 400.0);
+        assert!(vm.in_host(&config));
+        assert!(!vm.toggle_control(&config));
+        vm.transfer_blocked = false;
+        vm.remote_controlled = true;
+        push(&mut vm, &config, 15.0, 400.0);
+        assert!(vm.in_host(&config));
+        assert!(!vm.toggle_control(&config));
+        vm.remote_controlled = false;
+        push(&mut vm, &config, 15.0, 400.0);
+        assert!(!vm.in_host(&config));
+    }
+
+    #[test]
+    fn remote_moves_keep_the_host_cursor_on_the_anchor() {
+        for strategy in [WarpStrategy::Center, WarpStrategy::Park, WarpStrategy::Edge] {
+            let mut config = config();
+            config.warp_strategy = strategy;
+            let mut vm = model(&config, 990.0, 700.0);
+            push(&mut vm, &config, 15.0, 700.0);
+            assert!(!vm.in_host(&config), "{:?}", strategy);
+            // リモート操作中に物理カーソルを戻す先はアンカーだけで、移動しても変わらない
+            let anchor = (vm.anchor_x, vm.anchor_y);
+            assert!((0.0..1000.0).contains(&anchor.0) && (0.0..800.0).contains(&anchor.1));
+            for (dx, dy) in [(50.0, 0.0), (-30.0, 80.0), (0.0, -500.0), (300.0, 300.0)] {
+                vm.update(&config, vm.anchor_x + dx, vm.anchor_y + dy);
+                assert!(!vm.in_host(&config), "{:?}", strategy);
+                assert_eq!((vm.anchor_x, vm.anchor_y), anchor, "{:?}", strategy);
+            }
+        }
+    }
+
+    #[test]
+    fn cooldown_blocks_bouncing_back() {
+        let clock = FakeClock::default();
+        let mut config = config();
+        config.transfer_cooldown_ms = 200;
+        let mut vm = model_with_clock(&config, &clock, 990.0, 400.0);
+        push(&mut vm, &config, 15.0, 400.0);
+        assert!(!vm.in_host(&config));
+        // 切り替え直後は戻れない
+        clock.advance(Duration::from_millis(150));
+        push(&mut vm, &config, -100.0, 400.0);
+        assert!(!vm.in_host(&config));
+        clock.advance(Duration::from_millis(60));
+        push(&mut vm, &config, -100.0, 400.0);
+        assert!(vm.in_host(&config));
+    }
+
+    #[test]
+    fn dwell_needs_a_continuous_push() {
+        let clock = FakeClock::default();
+        let mut config = config();
+        config.edge_dwell_ms = 100;
+        let mut vm = model_with_clock(&config, &clock, 990.0, 400.0);
+        push(&mut vm, &config, 15.0, 400.0);
+        assert!(vm.in_host(&config));
+        clock.advance(Duration::from_millis(60));
+        push(&mut vm, &config, 15.0, 400.0);
+        assert!(vm.in_host(&config));
+        // エッジから離れると数え直す
+        push(&mut vm, &config, -20.0, 400.0);
+        clock.advance(Duration::from_millis(60));
+        push(&mut vm, &config, 40.0, 400.0);
+        assert!(vm.in_host(&config));
+        clock.advance(Duration::from_millis(60));
+        push(&mut vm, &config, 40.0, 400.0);
+        assert!(vm.in_host(&config));
+        clock.advance(Duration::from_millis(50));
+        push(&mut vm, &config, 40.0, 400.0);
+        assert!(!vm.in_host(&config));
+    }
+
+    #[test]
+    fn entry_respects_margin_on_each_edge() {
+        for (host_position, start_x, push, remote_x, host_x) in [
+            (HostPosition::Left, 990.0, 15.0, 1020.0, 980.0),
+            (HostPosition::Right, 10.0, -15.0, 780.0, 820.0),
+        ] {
+            let mut config = config();
+            config.host_position = host_position;
+            config.entry_margin_px = 20.0;
+            let mut vm = model(&config, start_x, 400.0);
+            vm.update(&config, start_x + push, 400.0);
+            assert!(!vm.in_host(&config), "{:?}", host_position);
+            assert_eq!(vm.virtual_x, remote_x, "{:?}", host_position);
+            // 入った位置から戻る向きに、マージンを越えるまで動かす
+            vm.update(&config, vm.anchor_x - push * 3.0, vm.anchor_y);
+            assert!(vm.in_host(&config), "{:?}", host_position);
+            assert_eq!(vm.virtual_x, host_x, "{:?}", host_position);
+        }
+    }
+
+    #[test]
+    fn hotkey_entry_respects_margin() {
+        let mut config = config();
+        config.entry_margin_px = 20.0;
+        let mut vm = model(&config, 500.0, 400.0);
+        assert!(vm.toggle_control(&config));
+        assert_eq!(vm.virtual_x, 1020.0);
+        assert!(vm.toggle_control(&config));
+        assert_eq!(vm.local_position(&config).0, 980.0);
+    }
+
+    #[test]
+    fn rotated_host_moves_in_physical_direction() {
+        let mut config = config();
+        config.screen = Screen {
+            rotation: 90,
+            ..screen(800, 1000)
+        };
+        let mut vm = model(&config, 100.0, 100.0);
+        assert!(vm.toggle_control(&config));
+        let (x, y) = (vm.virtual_x, vm.virtual_y);
+        // OS座標で右へ動くと、物理的には下へ動く
+        vm.update(&config, vm.anchor_x + 10.0, vm.anchor_y);
+        assert_eq!((vm.virtual_x, vm.virtual_y), (x, y + 10.0));
+    }
+
+    #[test]
+    fn rotated_remote_receives_os_coordinates() {
+        let mut config = config();
+        config.remote_screen = Screen {
+            rotation: 90,
+            ..screen(600, 800)
+        };
+        let mut vm = model(&config, 500.0, 300.0);
+        assert!(vm.toggle_control(&config));
+        let (x, y) = vm.receiver_position(&config);
+        let (width, height) = config.remote_screen.os_size();
+        assert!((0.0..width).contains(&x) && (0.0..height).contains(&y));
+        // 物理的な左端から入るので、90°回転した画面ではOS座標の下端になる
+        assert_eq!(
+            y,
+            height - 1.0 - (vm.virtual_x - config.screen.width as f64)
+        );
+    }
+
+    #[test]
+    fn park_anchor_is_inset_from_the_screen_edges() {
+        let mut config = config();
+        config.warp_strategy = WarpStrategy::Park;
+        // 右下の角から移譲する
+        let mut vm = model(&config, 990.0, 799.0);
+        vm.update(&config, 1005.0, 799.0);
+        assert!(!vm.in_host(&config));
+        assert_eq!(
+            (vm.anchor_x, vm.anchor_y),
+            (999.0 - EDGE_WARP_INSET_PX, 799.0 - EDGE_WARP_INSET_PX)
+        );
+        // 留めた位置から、越えてきたエッジの方向へ動いた分もそのまま測れる
+        let x = vm.virtual_x;
+        vm.update(&config, vm.anchor_x + 5.0, vm.anchor_y);
+        assert_eq!(vm.virtual_x, x + 5.0);
+    }
+
+    #[test]
+    fn warp_echo_tolerates_rounding() {
+        let mut config = config();
+        config.center_deadband_px = 0.0;
+        let mut vm = model(&config, 500.0, 400.0);
+        assert!(vm.toggle_control(&config));
+        let (x, y) = (vm.anchor_x, vm.anchor_y);
+        assert!(vm.is_warp_echo(&config, x + 0.3, y - 0.2));
+        assert!(!vm.is_warp_echo(&config, x + 1.0, y));
+    }
+
+    #[test]
+    fn center_deadband_ignores_offsets_around_the_anchor() {
+        let mut config = config();
+        config.center_deadband_px = 3.0;
+        let mut vm = model(&config, 500.0, 400.0);
+        assert!(vm.toggle_control(&config));
+        let start = (vm.virtual_x, vm.virtual_y);
+        for (dx, dy) in [(3.0, 0.0), (-2.0, 3.0), (0.5, -3.0)] {
+            vm.update(&config, vm.anchor_x + dx, vm.anchor_y + dy);
+            assert_eq!((vm.virtual_x, vm.virtual_y), start, "({}, {})", dx, dy);
+        }
+        // どちらかの軸がデッドバンドを出れば、アンカーからの移動量をそのまま使う
+        vm.update(&config, vm.anchor_x + 4.0, vm.anchor_y + 1.0);
+        assert_eq!((vm.virtual_x, vm.virtual_y), (start.0 + 4.0, start.1 + 1.0));
+        // ホスト側では効かない
+        let mut vm = model(&config, 500.0, 400.0);
+        vm.update(&config, 501.0, 400.0);
+        assert_eq!(vm.virtual_x, 501.0);
+    }
+
+    /// 物理カーソルを動かしてエッジを越えさせる（リモート中はアンカーからの移動量になる）
+    fn push(vm: &mut VirtualModel, config: &Config, dx: f64, y: f64) {
+        if vm.in_host(config) {
+            let (x, _) = vm.local_position(config);
+            vm.update(config, x + dx, y);
+        } else {
+            vm.update(config, vm.anchor_x + dx, vm.anchor_y);
+        }
+    }
+
+    #[test]
+    fn returning_to_host_does_not_jump_on_the_next_move() {
+        let mut config = config();
+        // リモートの方が高い
+        config.remote_screen = screen(800, 1200);
+        let mut vm = model(&config, 990.0, 400.0);
+        push(&mut vm, &config, 20.0, 400.0);
+        assert!(!vm.in_host(&config));
+        // リモートで下の方まで動いてから戻る
+        vm.update(&config, vm.anchor_x, vm.anchor_y + 700.0);
+        push(&mut vm, &config, -300.0, 0.0);
+        assert!(vm.in_host(&config));
+        // キャプチャは物理カーソルをlocal_positionへワープするので、次の移動はそこから測られる
+        let (x, y) = vm.local_position(&config);
+        assert!((0.0..1000.0).contains(&x) && (0.0..800.0).contains(&y));
+        let returned = (vm.virtual_x, vm.virtual_y);
+        vm.update(&config, x - 1.0, y);
+        assert_eq!((vm.virtual_x, vm.virtual_y), (returned.0 - 1.0, returned.1));
+    }
+
+    #[test]
+    fn each_remote_session_starts_from_the_crossing_point() {
+        let config = config();
+        let mut vm = model(&config, 990.0, 100.0);
+        for y in [100.0, 700.0, 300.0] {
+            // 前のセッションの終わりとは違う高さから入り直す
+            vm.update(&config, 990.0, y);
+            push(&mut vm, &config, 20.0, y);
+            assert!(!vm.in_host(&config));
+            assert_eq!(vm.virtual_y, y.min(600.0));
+            assert_eq!(vm.virtual_x, 1000.0 + config.entry_margin_px.max(1.0));
+            vm.update(&config, vm.anchor_x + 200.0, vm.anchor_y + 50.0);
+            push(&mut vm, &config, -400.0, 0.0);
+            assert!(vm.in_host(&config));
+        }
+    }
+}